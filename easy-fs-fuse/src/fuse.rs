@@ -0,0 +1,373 @@
+//! Mounting an image on the host, so that ordinary tools can look at and
+//! change the disk of the kernel. FUSE numbers the root 1 while easy-fs
+//! numbers it 0, hence every inode number here is one more than on disk.
+
+use easy_fs::{BlockDevice, EasyFileSystem, Inode, StatMode, BLOCK_SZ, DIRENT_SZ, MAX_FILE_SIZE};
+use fuser::{
+    FileAttr,
+    FileType,
+    Filesystem,
+    ReplyAttr,
+    ReplyCreate,
+    ReplyData,
+    ReplyDirectory,
+    ReplyEmpty,
+    ReplyEntry,
+    ReplyWrite,
+    Request,
+    TimeOrNow,
+};
+use libc::{EEXIST, EFBIG, EINVAL, EISDIR, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EPERM};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Nothing but this program changes the image while it is mounted.
+const TTL: Duration = Duration::from_secs(1);
+
+pub struct EasyFuse {
+    efs: Arc<spin::Mutex<EasyFileSystem>>,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+// The operations give what to reply with, or the errno to reply with
+// instead, so that they can be run without the kernel.
+impl EasyFuse {
+    pub fn new(efs: Arc<spin::Mutex<EasyFileSystem>>) -> Self {
+        let block_device = Arc::clone(&efs.lock().block_device);
+        Self { efs, block_device }
+    }
+
+    /// Inode `ino`, which the kernel may still ask about after it was
+    /// removed and its number freed.
+    pub fn inode(&self, ino: u64) -> Result<Inode, i32> {
+        let inode_id = ino.checked_sub(1)
+            .and_then(|inode_id| u32::try_from(inode_id).ok())
+            .ok_or(ENOENT)?;
+        if !self.efs.lock().is_inode_allocated(inode_id) {
+            return Err(ENOENT);
+        }
+        Ok(Inode::new(inode_id, Arc::clone(&self.efs), Arc::clone(&self.block_device)))
+    }
+
+    /// Directory `parent` and `name` as a str.
+    fn dir_and_name<'a>(&self, parent: u64, name: &'a OsStr) -> Result<(Inode, &'a str), i32> {
+        let dir = self.inode(parent)?;
+        if !dir.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let name = name.to_str().ok_or(EINVAL)?;
+        Ok((dir, name))
+    }
+
+    /// easy-fs takes the blocks it needs as it goes and panics if there
+    /// are none, so whether they are there has to be found out before.
+    fn check_space(&self, blocks: u32) -> Result<(), i32> {
+        if blocks > self.efs.lock().free_data_blocks() {
+            return Err(ENOSPC);
+        }
+        Ok(())
+    }
+
+    fn check_growth(&self, inode: &Inode, new_size: u64) -> Result<(), i32> {
+        if new_size > MAX_FILE_SIZE as u64 {
+            return Err(EFBIG);
+        }
+        self.check_space(inode.blocks_needed(new_size as usize))
+    }
+
+    /// The blocks a new entry in `dir` may take, if no slot is free.
+    fn dirent_blocks(dir: &Inode) -> u32 {
+        dir.blocks_needed(dir.size() + DIRENT_SZ)
+    }
+
+    pub fn lookup_inode(&self, parent: u64, name: &OsStr) -> Result<Arc<Inode>, i32> {
+        let (dir, name) = self.dir_and_name(parent, name)?;
+        dir.find(name).ok_or(ENOENT)
+    }
+
+    pub fn set_size(&self, ino: u64, size: u64) -> Result<Inode, i32> {
+        let inode = self.inode(ino)?;
+        if inode.is_dir() {
+            return Err(EISDIR);
+        }
+        self.check_growth(&inode, size)?;
+        let size = size as usize;
+        let old_size = inode.size();
+        if size < old_size {
+            inode.truncate(size);
+        } else if size > old_size {
+            inode.write_at(size, &[]);
+        }
+        Ok(inode)
+    }
+
+    pub fn read_data(&self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let mut buffer = vec![0u8; size as usize];
+        let len = self.inode(ino)?.read_at(offset as usize, &mut buffer);
+        buffer.truncate(len);
+        Ok(buffer)
+    }
+
+    pub fn write_data(&self, ino: u64, offset: i64, data: &[u8]) -> Result<usize, i32> {
+        let inode = self.inode(ino)?;
+        self.check_growth(&inode, (offset as u64).saturating_add(data.len() as u64))?;
+        Ok(inode.write_at(offset as usize, data))
+    }
+
+    pub fn create_file(&self, parent: u64, name: &OsStr) -> Result<Arc<Inode>, i32> {
+        let (dir, name) = self.dir_and_name(parent, name)?;
+        if dir.find(name).is_some() {
+            return Err(EEXIST);
+        }
+        self.check_space(Self::dirent_blocks(&dir))?;
+        dir.create(name).ok_or(EINVAL)
+    }
+
+    pub fn make_dir(&self, parent: u64, name: &OsStr) -> Result<Arc<Inode>, i32> {
+        let (dir, name) = self.dir_and_name(parent, name)?;
+        if dir.find(name).is_some() {
+            return Err(EEXIST);
+        }
+        // and one for "." and ".." of the new directory
+        self.check_space(Self::dirent_blocks(&dir) + 1)?;
+        dir.mkdir(name).ok_or(EINVAL)
+    }
+
+    pub fn link_file(&self, ino: u64, newparent: u64, newname: &OsStr) -> Result<Inode, i32> {
+        let (dir, name) = self.dir_and_name(newparent, newname)?;
+        let inode = self.inode(ino)?;
+        if inode.is_dir() {
+            return Err(EPERM);
+        }
+        if dir.find(name).is_some() {
+            return Err(EEXIST);
+        }
+        self.check_space(Self::dirent_blocks(&dir))?;
+        dir.link(name, &inode).map(|_| inode).map_err(|_| EINVAL)
+    }
+
+    pub fn remove_file(&self, parent: u64, name: &OsStr) -> Result<(), i32> {
+        let (dir, name) = self.dir_and_name(parent, name)?;
+        match dir.find(name) {
+            Some(inode) if inode.is_dir() => Err(EISDIR),
+            Some(_) => dir.unlink(name).map_err(|_| EPERM),
+            None => Err(ENOENT),
+        }
+    }
+
+    pub fn remove_dir(&self, parent: u64, name: &OsStr) -> Result<(), i32> {
+        let (dir, name) = self.dir_and_name(parent, name)?;
+        match dir.find(name) {
+            Some(inode) if !inode.is_dir() => Err(ENOTDIR),
+            Some(_) => dir.rmdir(name).map_err(|_| ENOTEMPTY),
+            None => Err(ENOENT),
+        }
+    }
+
+    /// The entries of directory `ino` from `offset` on, each with the
+    /// offset of the next one. Those are the offsets of easy-fs, which go
+    /// back to FUSE to continue from.
+    pub fn dir_entries(&self, ino: u64, offset: i64) -> Result<Vec<(u64, i64, FileType, String)>, i32> {
+        let dir = self.inode(ino)?;
+        if !dir.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let mut entries = Vec::new();
+        let mut offset = offset as usize;
+        while let Some((name, inode_id, mode, next)) = dir.read_dir(offset) {
+            let kind = if mode.contains(StatMode::DIR) {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            entries.push((inode_id as u64 + 1, next as i64, kind, name));
+            offset = next;
+        }
+        Ok(entries)
+    }
+}
+
+/// easy-fs keeps no owners, modes or times: everything belongs to whoever
+/// asks and was last changed at the epoch.
+fn attr(req: &Request<'_>, inode: &Inode) -> FileAttr {
+    let stat = inode.stat();
+    let size = inode.size() as u64;
+    FileAttr {
+        ino: stat.ino + 1,
+        size,
+        blocks: (size + BLOCK_SZ as u64 - 1) / BLOCK_SZ as u64,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: if inode.is_dir() { FileType::Directory } else { FileType::RegularFile },
+        perm: 0o755,
+        nlink: stat.nlink,
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        blksize: BLOCK_SZ as u32,
+        flags: 0,
+    }
+}
+
+impl Filesystem for EasyFuse {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_inode(parent, name) {
+            Ok(inode) => reply.entry(&TTL, &attr(req, &inode), 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.inode(ino) {
+            Ok(inode) => reply.attr(&TTL, &attr(req, &inode)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    // only the size can be changed, as by O_TRUNC
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let inode = match size {
+            Some(size) => self.set_size(ino, size),
+            None => self.inode(ino),
+        };
+        match inode {
+            Ok(inode) => reply.attr(&TTL, &attr(req, &inode)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_data(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_data(ino, offset, data) {
+            Ok(len) => reply.written(len as u32),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match self.create_file(parent, name) {
+            Ok(inode) => reply.created(&TTL, &attr(req, &inode), 0, 0, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match self.make_dir(parent, name) {
+            Ok(inode) => reply.entry(&TTL, &attr(req, &inode), 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn link(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        match self.link_file(ino, newparent, newname) {
+            Ok(inode) => reply.entry(&TTL, &attr(req, &inode), 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove_file(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove_dir(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entries = match self.dir_entries(ino, offset) {
+            Ok(entries) => entries,
+            Err(errno) => return reply.error(errno),
+        };
+        for (ino, next, kind, name) in entries {
+            if reply.add(ino, next, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
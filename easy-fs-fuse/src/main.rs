@@ -1,12 +1,24 @@
+#[cfg(feature = "mount")]
+mod fuse;
+
 use easy_fs::{
     BlockDevice,
     EasyFileSystem,
+    Inode,
+    StatMode,
+    DIRENT_SZ,
+    MAX_FILE_SIZE,
 };
-use std::fs::{File, OpenOptions, read_dir};
-use std::io::{Read, Write, Seek, SeekFrom};
+use std::fs::{File, OpenOptions, read_dir, create_dir_all};
+use std::io::{Read, Write, Seek, SeekFrom, Error, ErrorKind};
+use std::path::Path;
 use std::sync::Mutex;
 use std::sync::Arc;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches, SubCommand};
+#[cfg(feature = "mount")]
+use fuser::MountOption;
+#[cfg(feature = "mount")]
+use fuse::EasyFuse;
 
 const BLOCK_SZ: usize = 512;
 
@@ -29,11 +41,7 @@ impl BlockDevice for BlockFile {
 }
 
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
-}
-
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
+    let app = App::new("EasyFileSystem packer")
         .arg(Arg::with_name("source")
             .short("s")
             .long("source")
@@ -46,7 +54,254 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .takes_value(true)
             .help("Executable target dir(with backslash)")
         )
-        .get_matches();
+        .arg(Arg::with_name("update")
+            .short("u")
+            .long("update")
+            .help("Put the executables into the image already there instead of a new one")
+        )
+        .subcommand(SubCommand::with_name("fsck")
+            .about("Check an image for leaked or doubly allocated blocks and inodes, \
+                wrong link counts and dangling directory entries")
+            .arg(Arg::with_name("image")
+                .required(true)
+                .help("Image to check")
+            )
+            .arg(Arg::with_name("repair")
+                .short("r")
+                .long("repair")
+                .help("Fix what is found")
+            )
+        )
+        .subcommand(SubCommand::with_name("list")
+            .about("List the files of an image with their inode numbers and sizes")
+            .arg(Arg::with_name("image").required(true))
+        )
+        .subcommand(SubCommand::with_name("extract")
+            .about("Copy a file or directory of an image to the host")
+            .arg(Arg::with_name("image").required(true))
+            .arg(Arg::with_name("path").required(true).help("Path in the image"))
+            .arg(Arg::with_name("host_path").required(true).help("Path on the host"))
+        )
+        .subcommand(SubCommand::with_name("add")
+            .about("Copy a host file into an image as a new file")
+            .arg(Arg::with_name("image").required(true))
+            .arg(Arg::with_name("host_path").required(true).help("Path on the host"))
+            .arg(Arg::with_name("path").help("Path in the image, by default the name in the root"))
+        )
+        .subcommand(SubCommand::with_name("replace")
+            .about("Overwrite a file of an image with a host file")
+            .arg(Arg::with_name("image").required(true))
+            .arg(Arg::with_name("host_path").required(true).help("Path on the host"))
+            .arg(Arg::with_name("path").help("Path in the image, by default the name in the root"))
+        )
+        .subcommand(SubCommand::with_name("remove")
+            .about("Remove a file or empty directory from an image")
+            .arg(Arg::with_name("image").required(true))
+            .arg(Arg::with_name("path").required(true).help("Path in the image"))
+        );
+    #[cfg(feature = "mount")]
+    let app = app.subcommand(SubCommand::with_name("mount")
+        .about("Mount an image on the host through FUSE until it is unmounted again")
+        .arg(Arg::with_name("image").required(true))
+        .arg(Arg::with_name("mountpoint").required(true))
+    );
+    let matches = app.get_matches();
+    match matches.subcommand() {
+        ("fsck", Some(fsck_matches)) =>
+            easy_fs_fsck(fsck_matches).expect("Error when checking easy-fs!"),
+        #[cfg(feature = "mount")]
+        ("mount", Some(mount_matches)) =>
+            easy_fs_mount(mount_matches).expect("Error when mounting easy-fs!"),
+        ("", None) => easy_fs_pack(&matches).expect("Error when packing easy-fs!"),
+        (subcommand, Some(edit_matches)) =>
+            easy_fs_edit(subcommand, edit_matches).expect("Error when editing easy-fs!"),
+        _ => unreachable!(),
+    }
+}
+
+fn open_image(image_path: &str) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(image_path)?;
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+/// Print what is wrong with the image and, with `--repair`, fix it. Exits
+/// with 1 if problems are left.
+fn easy_fs_fsck(matches: &ArgMatches) -> std::io::Result<()> {
+    let image_path = matches.value_of("image").unwrap();
+    let repair = matches.is_present("repair");
+    let efs = EasyFileSystem::open(open_image(image_path)?);
+    let problems = EasyFileSystem::fsck(&efs, repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", image_path);
+        return Ok(());
+    }
+    let left = if repair {
+        EasyFileSystem::fsck(&efs, false).len()
+    } else {
+        problems.len()
+    };
+    println!("{}: {} problems found, {} left", image_path, problems.len(), left);
+    if left > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "mount")]
+fn easy_fs_mount(matches: &ArgMatches) -> std::io::Result<()> {
+    let efs = EasyFileSystem::open(open_image(matches.value_of("image").unwrap())?);
+    fuser::mount2(
+        EasyFuse::new(efs),
+        matches.value_of("mountpoint").unwrap(),
+        &[MountOption::FSName(String::from("easy-fs")), MountOption::DefaultPermissions],
+    )
+}
+
+fn image_error(path: &str, kind: ErrorKind, message: &str) -> Error {
+    Error::new(kind, format!("{}: {}", path, message))
+}
+
+/// The directory `path` of the image is in and the last name of it.
+fn parent_of<'a>(root_inode: &Arc<Inode>, path: &'a str) -> std::io::Result<(Arc<Inode>, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (dir_path, name) = match path.rfind('/') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => ("", path),
+    };
+    let dir = root_inode.find_path(dir_path)
+        .filter(|dir| dir.is_dir())
+        .ok_or_else(|| image_error(dir_path, ErrorKind::NotFound, "no such directory"))?;
+    Ok((dir, name))
+}
+
+/// Every file and directory below `dir` as (inode number, size, path),
+/// the paths starting with `path`.
+fn list(dir: &Inode, path: &str, entries: &mut Vec<(u32, usize, String)>) {
+    let mut offset = 0;
+    while let Some((name, inode_id, mode, next)) = dir.read_dir(offset) {
+        offset = next;
+        if name == "." || name == ".." {
+            continue;
+        }
+        let inode = dir.find(&name).unwrap();
+        let inode_path = format!("{}/{}", path, name);
+        entries.push((inode_id, inode.size(), inode_path.clone()));
+        if mode.contains(StatMode::DIR) {
+            list(&inode, &inode_path, entries);
+        }
+    }
+}
+
+fn extract(inode: &Inode, host_path: &Path) -> std::io::Result<()> {
+    if inode.is_dir() {
+        create_dir_all(host_path)?;
+        for name in inode.ls() {
+            if name != "." && name != ".." {
+                extract(&inode.find(&name).unwrap(), &host_path.join(&name))?;
+            }
+        }
+    } else {
+        let mut data = vec![0u8; inode.size()];
+        inode.read_at(0, &mut data);
+        File::create(host_path)?.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Copy `host_path` to `path` of the image, a new file unless `replace`,
+/// then an existing one. Nothing is changed if the data does not fit.
+fn put_file(
+    efs: &Arc<spin::Mutex<EasyFileSystem>>,
+    root_inode: &Arc<Inode>,
+    host_path: &str,
+    path: &str,
+    replace: bool,
+) -> std::io::Result<()> {
+    let mut data = Vec::new();
+    File::open(host_path)?.read_to_end(&mut data)?;
+    if data.len() > MAX_FILE_SIZE {
+        return Err(image_error(path, ErrorKind::InvalidInput, "file too large"));
+    }
+    let (dir, name) = parent_of(root_inode, path)?;
+    // a file being replaced already has some of the blocks, and keeps
+    // them until it is cleared
+    let fits = |inode: &Inode, new_size: usize| {
+        inode.blocks_needed(new_size) <= efs.lock().free_data_blocks()
+    };
+    let no_space = || image_error(path, ErrorKind::Other, "no space left in the image");
+    let inode = match (dir.find(name), replace) {
+        (None, false) => {
+            if !fits(&dir, dir.size() + DIRENT_SZ) {
+                return Err(no_space());
+            }
+            let inode = dir.create(name)
+                .ok_or_else(|| image_error(path, ErrorKind::InvalidInput, "invalid name"))?;
+            if !fits(&inode, data.len()) {
+                dir.unlink(name).unwrap();
+                return Err(no_space());
+            }
+            inode
+        }
+        (Some(inode), true) if inode.is_file() => {
+            if !fits(&inode, data.len()) {
+                return Err(no_space());
+            }
+            inode.clear();
+            inode
+        }
+        (Some(_), false) => return Err(image_error(path, ErrorKind::AlreadyExists, "already exists")),
+        (Some(_), true) => return Err(image_error(path, ErrorKind::InvalidInput, "not a file")),
+        (None, true) => return Err(image_error(path, ErrorKind::NotFound, "no such file")),
+    };
+    inode.write_at(0, &data);
+    Ok(())
+}
+
+fn remove(root_inode: &Arc<Inode>, path: &str) -> std::io::Result<()> {
+    let (dir, name) = parent_of(root_inode, path)?;
+    let inode = dir.find(name)
+        .ok_or_else(|| image_error(path, ErrorKind::NotFound, "no such file or directory"))?;
+    let removed = if inode.is_dir() { dir.rmdir(name) } else { dir.unlink(name) };
+    removed.map_err(|_| image_error(path, ErrorKind::InvalidInput, "cannot remove"))
+}
+
+/// List, extract, add, replace or remove files of an existing image.
+fn easy_fs_edit(subcommand: &str, matches: &ArgMatches) -> std::io::Result<()> {
+    let efs = EasyFileSystem::open(open_image(matches.value_of("image").unwrap())?);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    // for add and replace
+    let host_path = matches.value_of("host_path").unwrap_or_default();
+    let path = matches.value_of("path").unwrap_or_else(|| {
+        Path::new(host_path).file_name().and_then(|name| name.to_str()).unwrap_or_default()
+    });
+    match subcommand {
+        "list" => {
+            let mut entries = Vec::new();
+            list(&root_inode, "", &mut entries);
+            for (inode_id, size, path) in entries {
+                println!("{:>6} {:>10} {}", inode_id, size, path);
+            }
+            Ok(())
+        }
+        "extract" => {
+            let inode = root_inode.find_path(path)
+                .ok_or_else(|| image_error(path, ErrorKind::NotFound, "no such file or directory"))?;
+            extract(&inode, Path::new(host_path))
+        }
+        "add" => put_file(&efs, &root_inode, host_path, path, false),
+        "replace" => put_file(&efs, &root_inode, host_path, path, true),
+        "remove" => remove(&root_inode, path),
+        _ => unreachable!(),
+    }
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
@@ -60,11 +315,16 @@ fn easy_fs_pack() -> std::io::Result<()> {
         f
     })));
 
-    let efs = EasyFileSystem::create(
-        block_file.clone(),
-        8192,
-        1,
-    );
+    let block_device: Arc<dyn BlockDevice> = block_file.clone();
+    let efs = if matches.is_present("update") && EasyFileSystem::probe(&block_device) {
+        EasyFileSystem::open(block_file.clone())
+    } else {
+        EasyFileSystem::create(
+            block_file.clone(),
+            8192,
+            1,
+        )
+    };
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
@@ -76,11 +336,8 @@ fn easy_fs_pack() -> std::io::Result<()> {
         })
         .collect();
     for app in apps {
-        let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
-        let mut all_data: Vec<u8> = Vec::new();
-        host_file.read_to_end(&mut all_data).unwrap();
-        let inode = root_inode.create(app.as_str()).unwrap();
-        inode.write_at(0, all_data.as_slice());
+        let replace = root_inode.find(app.as_str()).is_some();
+        put_file(&efs, &root_inode, &format!("{}{}", target_path, app), &app, replace)?;
     }
     for app in root_inode.ls() {
         println!("{}", app);
@@ -161,3 +418,540 @@ fn efs_test() -> std::io::Result<()> {
     Ok(())
 }
             
+
+#[test]
+fn efs_dir_test() -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("target/fs_dir.img")?;
+        f.set_len(8192 * 512).unwrap();
+        f
+    })));
+    EasyFileSystem::create(
+        block_file.clone(),
+        4096,
+        1,
+    );
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert_eq!(root_inode.stat().nlink, 2);
+
+    let dira = root_inode.mkdir("dira").unwrap();
+    assert!(dira.is_dir());
+    assert!(root_inode.mkdir("dira").is_none());
+    assert_eq!(root_inode.stat().nlink, 3);
+    let dirb = dira.mkdir("dirb").unwrap();
+    let filec = dirb.create("filec").unwrap();
+    assert!(filec.is_file());
+    let greet_str = "Hello, directories!";
+    filec.write_at(0, greet_str.as_bytes());
+
+    let found = root_inode.find_path("dira//dirb/./filec").unwrap();
+    assert_eq!(found.inode_id(), filec.inode_id());
+    let mut buffer = [0u8; 64];
+    let len = found.read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    assert_eq!(
+        root_inode.find_path("dira/dirb/../..").unwrap().inode_id(),
+        root_inode.inode_id(),
+    );
+    assert!(root_inode.find_path("dira/dirb/filec/x").is_none());
+    assert_eq!(dirb.ls(), vec![".", "..", "filec"]);
+
+    dira.link("filed", &filec).unwrap();
+    assert_eq!(filec.stat().nlink, 2);
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some((name, inode_id, mode, next)) = dira.read_dir(offset) {
+        entries.push((name, inode_id, mode));
+        offset = next;
+    }
+    assert_eq!(entries, vec![
+        (String::from("."), dira.inode_id(), StatMode::DIR),
+        (String::from(".."), root_inode.inode_id(), StatMode::DIR),
+        (String::from("dirb"), dirb.inode_id(), StatMode::DIR),
+        (String::from("filed"), filec.inode_id(), StatMode::FILE),
+    ]);
+    assert!(filec.read_dir(0).is_none());
+    assert!(dira.link("dirc", &dirb).is_err());
+    assert!(dira.rmdir("dirb").is_err());
+    assert!(dira.unlink("dirb").is_err());
+    dirb.unlink("filec").unwrap();
+    assert!(dirb.find("filec").is_none());
+    assert_eq!(filec.stat().nlink, 1);
+    let len = dira.find("filed").unwrap().read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    dira.unlink("filed").unwrap();
+
+    dira.rmdir("dirb").unwrap();
+    assert!(dira.find("dirb").is_none());
+    assert_eq!(dira.stat().nlink, 2);
+    assert!(root_inode.rmdir(".").is_err());
+    root_inode.rmdir("dira").unwrap();
+    assert_eq!(root_inode.stat().nlink, 2);
+    assert_eq!(root_inode.ls(), vec![".", ".."]);
+    Ok(())
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    use easy_fs::FsckProblem;
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("target/fs_fsck.img")?;
+        f.set_len(8192 * 512).unwrap();
+        f
+    })));
+    let efs = EasyFileSystem::create(
+        block_file.clone(),
+        4096,
+        1,
+    );
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dira = root_inode.mkdir("dira").unwrap();
+    let filea = dira.create("filea").unwrap();
+    filea.write_at(0, "Hello, fsck!".repeat(100).as_bytes());
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+
+    // fileb loses its block to filec
+    let fileb = root_inode.create("fileb").unwrap();
+    let shared_block = {
+        let mut fs = efs.lock();
+        let block_id = fs.alloc_data();
+        fs.dealloc_data(block_id);
+        block_id
+    };
+    fileb.write_at(0, b"fileb");
+    efs.lock().dealloc_data(shared_block);
+    let filec = root_inode.create("filec").unwrap();
+    filec.write_at(0, b"filec");
+    let leaked_block = efs.lock().alloc_data();
+    let leaked_inode = efs.lock().alloc_inode();
+    // the entry of dira stays, and so do filea and the ".." of dira
+    let dira_id = dira.inode_id();
+    efs.lock().dealloc_inode(dira_id);
+
+    let problems = EasyFileSystem::fsck(&efs, false);
+    for problem in [
+        FsckProblem::DanglingEntry {
+            dir: 0,
+            name: String::from("dira"),
+            inode: dira_id,
+        },
+        FsckProblem::LeakedInode(filea.inode_id()),
+        FsckProblem::LeakedInode(leaked_inode),
+        FsckProblem::WrongNlink { inode: 0, nlink: 3, expected: 2 },
+        FsckProblem::DoubleAllocatedBlock {
+            block_id: shared_block,
+            inodes: (fileb.inode_id(), filec.inode_id()),
+        },
+        FsckProblem::LeakedBlock(leaked_block),
+    ].iter() {
+        assert!(problems.contains(problem), "{} not found", problem);
+    }
+    // those of dira and filea as well
+    let leaked_blocks = problems.iter()
+        .filter(|problem| matches!(problem, FsckProblem::LeakedBlock(_)))
+        .count();
+    assert_eq!(leaked_blocks, 1 + 1 + 3);
+    assert_eq!(problems.len(), 5 + leaked_blocks);
+
+    assert_eq!(EasyFileSystem::fsck(&efs, true), problems);
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    assert_eq!(root_inode.ls(), vec![".", "..", "fileb", "filec"]);
+    assert_eq!(root_inode.stat().nlink, 2);
+    fileb.write_at(0, b"fileb");
+    let mut buffer = [0u8; 8];
+    assert_eq!(fileb.read_at(0, &mut buffer), 5);
+    assert_eq!(&buffer[..5], b"fileb");
+    assert_eq!(filec.read_at(0, &mut buffer), 5);
+    assert_eq!(&buffer[..5], b"filec");
+
+    // the repair has reached the image
+    let efs = EasyFileSystem::open(open_image("target/fs_fsck.img")?);
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+
+    // a directory larger than any inode can be keeps the blocks it has
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dirc = root_inode.mkdir("dirc").unwrap();
+    dirc.create("filed").unwrap();
+    let dirc_id = dirc.inode_id();
+    let (block_id, block_offset) = efs.lock().get_disk_inode_pos(dirc_id);
+    let mut block = [0u8; BLOCK_SZ];
+    efs.lock().read_raw_block(block_id, &mut block);
+    block[block_offset..block_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    efs.lock().write_raw_block(block_id, &block);
+    let problems = EasyFileSystem::fsck(&efs, false);
+    assert_eq!(problems, vec![FsckProblem::BadSize {
+        inode: dirc_id,
+        size: u32::MAX,
+        fitted: BLOCK_SZ as u32,
+    }]);
+    assert_eq!(EasyFileSystem::fsck(&efs, true), problems);
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    assert_eq!(dirc.ls(), vec![".", "..", "filed"]);
+    Ok(())
+}
+
+#[test]
+fn efs_image_test() -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("target/fs_image.img")?;
+        f.set_len(8192 * 512).unwrap();
+        f
+    })));
+    let efs = EasyFileSystem::create(
+        block_file.clone(),
+        4096,
+        1,
+    );
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dira = root_inode.mkdir("dira").unwrap();
+    create_dir_all("target/image_test")?;
+    let greet_str = "Hello, image!".repeat(100);
+    File::create("target/image_test/filea")?.write_all(greet_str.as_bytes())?;
+    File::create("target/image_test/fileb")?.write_all(b"fileb")?;
+
+    put_file(&efs, &root_inode, "target/image_test/filea", "filea", false)?;
+    put_file(&efs, &root_inode, "target/image_test/fileb", "dira/fileb", false)?;
+    let error_kind = |result: std::io::Result<()>| result.unwrap_err().kind();
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/fileb", "filea", false)),
+        ErrorKind::AlreadyExists,
+    );
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/fileb", "filec", true)),
+        ErrorKind::NotFound,
+    );
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/fileb", "dirb/filec", false)),
+        ErrorKind::NotFound,
+    );
+    // what does not fit leaves the image as it was
+    let free_blocks = efs.lock().free_data_blocks();
+    File::create("target/image_test/filec")?
+        .write_all(&vec![b'c'; free_blocks as usize * BLOCK_SZ])?;
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/filec", "filea", true)),
+        ErrorKind::Other,
+    );
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/filec", "filec", false)),
+        ErrorKind::Other,
+    );
+    File::create("target/image_test/filed")?.write_all(&vec![b'd'; MAX_FILE_SIZE + 1])?;
+    assert_eq!(
+        error_kind(put_file(&efs, &root_inode, "target/image_test/filed", "filea", true)),
+        ErrorKind::InvalidInput,
+    );
+    assert!(root_inode.find("filec").is_none());
+    assert_eq!(efs.lock().free_data_blocks(), free_blocks);
+    let mut buffer = vec![0u8; greet_str.len()];
+    root_inode.find("filea").unwrap().read_at(0, &mut buffer);
+    assert_eq!(buffer, greet_str.as_bytes());
+
+    let filea_id = root_inode.find("filea").unwrap().inode_id();
+    let fileb_id = dira.find("fileb").unwrap().inode_id();
+    let mut entries = Vec::new();
+    list(&root_inode, "", &mut entries);
+    assert_eq!(entries, vec![
+        (dira.inode_id(), 3 * 32, String::from("/dira")),
+        (fileb_id, 5, String::from("/dira/fileb")),
+        (filea_id, greet_str.len(), String::from("/filea")),
+    ]);
+
+    put_file(&efs, &root_inode, "target/image_test/fileb", "filea", true)?;
+    assert_eq!(root_inode.find("filea").unwrap().size(), 5);
+    extract(&root_inode, Path::new("target/image_test/out"))?;
+    let mut data = String::new();
+    File::open("target/image_test/out/dira/fileb")?.read_to_string(&mut data)?;
+    assert_eq!(data, "fileb");
+
+    assert_eq!(error_kind(remove(&root_inode, "dira")), ErrorKind::InvalidInput);
+    remove(&root_inode, "dira/fileb")?;
+    remove(&root_inode, "dira/")?;
+    assert_eq!(error_kind(remove(&root_inode, "dira")), ErrorKind::NotFound);
+
+    // what is left has reached the image
+    let efs = EasyFileSystem::open(open_image("target/fs_image.img")?);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let mut entries = Vec::new();
+    list(&root_inode, "", &mut entries);
+    assert_eq!(entries, vec![(filea_id, 5, String::from("/filea"))]);
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    Ok(())
+}
+
+/// A disk in memory which loses every write after the first `writes_left`,
+/// as if the machine crashed there.
+#[cfg(test)]
+struct CrashDisk {
+    blocks: Arc<Mutex<Vec<[u8; BLOCK_SZ]>>>,
+    writes_left: Mutex<usize>,
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.blocks.lock().unwrap()[block_id]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut writes_left = self.writes_left.lock().unwrap();
+        if *writes_left > 0 {
+            *writes_left -= 1;
+            self.blocks.lock().unwrap()[block_id].copy_from_slice(buf);
+        }
+    }
+}
+
+/// Run `op` on the root directory of a copy of `image`, crash after
+/// `writes` block writes and open what reached the disk again.
+#[cfg(test)]
+fn crash_after(
+    image: &[[u8; BLOCK_SZ]],
+    writes: usize,
+    op: impl Fn(&easy_fs::Inode),
+) -> Arc<spin::Mutex<EasyFileSystem>> {
+    let blocks = Arc::new(Mutex::new(image.to_vec()));
+    let disk = Arc::new(CrashDisk {
+        blocks: blocks.clone(),
+        writes_left: Mutex::new(writes),
+    });
+    let efs = EasyFileSystem::open(disk);
+    op(&EasyFileSystem::root_inode(&efs));
+    let disk = Arc::new(CrashDisk {
+        blocks,
+        writes_left: Mutex::new(usize::MAX),
+    });
+    EasyFileSystem::open(disk)
+}
+
+
+#[test]
+fn efs_journal_test() {
+    let blocks = Arc::new(Mutex::new(vec![[0u8; BLOCK_SZ]; 4096]));
+    let disk = Arc::new(CrashDisk {
+        blocks: blocks.clone(),
+        writes_left: Mutex::new(usize::MAX),
+    });
+    let efs = EasyFileSystem::create(disk, 4096, 1);
+    let greet_str = "Hello, journal!".repeat(100);
+    EasyFileSystem::root_inode(&efs)
+        .create("filea")
+        .unwrap()
+        .write_at(0, greet_str.as_bytes());
+    let image = blocks.lock().unwrap().clone();
+
+    // the first free inode and data block, which show leaked ones
+    let next_free = |efs: &Arc<spin::Mutex<EasyFileSystem>>| {
+        let mut fs = efs.lock();
+        (fs.alloc_inode(), fs.alloc_data())
+    };
+    let filea_intact = |root_inode: &easy_fs::Inode| {
+        let filea = root_inode.find("filea").unwrap();
+        let mut buffer = vec![0u8; greet_str.len() + 1];
+        assert_eq!(filea.read_at(0, &mut buffer), greet_str.len());
+        assert_eq!(&buffer[..greet_str.len()], greet_str.as_bytes());
+        assert_eq!(filea.stat().nlink, 1);
+    };
+    let untouched = next_free(&crash_after(&image, 0, |_| {}));
+
+    // Wherever it crashes, an operation has happened either completely
+    // or not at all, and once it has, it stays.
+    let check_atomic = |op: &dyn Fn(&easy_fs::Inode), done: &dyn Fn(&easy_fs::Inode) -> bool| {
+        let completed = next_free(&crash_after(&image, usize::MAX, op));
+        let mut first_done = None;
+        for writes in 0..64 {
+            let efs = crash_after(&image, writes, op);
+            let is_done = done(&EasyFileSystem::root_inode(&efs));
+            assert_eq!(next_free(&efs), if is_done { completed } else { untouched });
+            match first_done {
+                Some(_) => assert!(is_done),
+                None if is_done => first_done = Some(writes),
+                None => {}
+            }
+        }
+        // nothing but the journal is written before the commit
+        assert!(first_done.unwrap() > 1);
+    };
+
+    check_atomic(
+        &|root_inode| { root_inode.mkdir("dira").unwrap(); },
+        &|root_inode| {
+            filea_intact(root_inode);
+            match root_inode.find("dira") {
+                Some(dira) => {
+                    assert_eq!(dira.ls(), vec![".", ".."]);
+                    assert_eq!(root_inode.stat().nlink, 3);
+                    true
+                }
+                None => {
+                    assert_eq!(root_inode.ls(), vec![".", "..", "filea"]);
+                    assert_eq!(root_inode.stat().nlink, 2);
+                    false
+                }
+            }
+        },
+    );
+    check_atomic(
+        &|root_inode| root_inode.find("filea").unwrap().truncate(600),
+        &|root_inode| {
+            let filea = root_inode.find("filea").unwrap();
+            if filea.size() == greet_str.len() {
+                filea_intact(root_inode);
+                false
+            } else {
+                let mut buffer = vec![0u8; 601];
+                assert_eq!(filea.read_at(0, &mut buffer), 600);
+                assert_eq!(&buffer[..600], &greet_str.as_bytes()[..600]);
+                true
+            }
+        },
+    );
+    check_atomic(
+        &|root_inode| root_inode.unlink("filea").unwrap(),
+        &|root_inode| {
+            if root_inode.find("filea").is_some() {
+                filea_intact(root_inode);
+                false
+            } else {
+                assert_eq!(root_inode.ls(), vec![".", ".."]);
+                true
+            }
+        },
+    );
+
+    // A write larger than a transaction commits chunk by chunk: cut off
+    // in the middle, the file has the chunks before and no block of the
+    // one that was being written.
+    let chunk = 16 * BLOCK_SZ; // WRITE_CHUNK of easy-fs
+    let data: Vec<u8> = (0..2 * chunk + 1000).map(|i| (i % 251) as u8).collect();
+    let append = |root_inode: &easy_fs::Inode| {
+        root_inode.find("filea").unwrap().write_at(greet_str.len(), &data);
+    };
+    let mut sizes = Vec::new();
+    for writes in 0..400 {
+        let efs = crash_after(&image, writes, append);
+        let filea = EasyFileSystem::root_inode(&efs).find("filea").unwrap();
+        let written = filea.size() - greet_str.len();
+        assert!(written % chunk == 0 || written == data.len());
+        let mut buffer = vec![0u8; filea.size() + 1];
+        assert_eq!(filea.read_at(0, &mut buffer), filea.size());
+        assert_eq!(&buffer[..greet_str.len()], greet_str.as_bytes());
+        assert_eq!(&buffer[greet_str.len()..filea.size()], &data[..written]);
+        assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+        if sizes.last() != Some(&written) {
+            sizes.push(written);
+        }
+    }
+    assert_eq!(sizes, vec![0, chunk, 2 * chunk, data.len()]);
+}
+
+#[cfg(feature = "mount")]
+#[test]
+fn efs_fuse_test() {
+    use libc::{EEXIST, EFBIG, EISDIR, ENOENT, ENOSPC};
+    use std::ffi::OsStr;
+    let disk = Arc::new(CrashDisk {
+        blocks: Arc::new(Mutex::new(vec![[0u8; BLOCK_SZ]; 4096])),
+        writes_left: Mutex::new(usize::MAX),
+    });
+    let efs = EasyFileSystem::create(disk, 4096, 1);
+    let fuse = EasyFuse::new(efs.clone());
+    let name = OsStr::new;
+    let root = 1;
+
+    let filea = fuse.create_file(root, name("filea")).unwrap().inode_id() as u64 + 1;
+    assert_eq!(fuse.create_file(root, name("filea")).err(), Some(EEXIST));
+    let greet_str = "Hello, FUSE!".repeat(100);
+    assert_eq!(fuse.write_data(filea, 0, greet_str.as_bytes()), Ok(greet_str.len()));
+    assert_eq!(fuse.read_data(filea, 0, 2048).unwrap(), greet_str.as_bytes());
+    assert_eq!(fuse.read_data(filea, 1000, 100).unwrap(), &greet_str.as_bytes()[1000..1100]);
+
+    // shrink into the middle of a block, and what grows back reads zeros
+    assert_eq!(fuse.set_size(filea, 700).unwrap().size(), 700);
+    assert_eq!(fuse.read_data(filea, 0, 2048).unwrap(), &greet_str.as_bytes()[..700]);
+    assert_eq!(fuse.set_size(filea, 1200).unwrap().size(), 1200);
+    let data = fuse.read_data(filea, 0, 2048).unwrap();
+    assert_eq!(&data[..700], &greet_str.as_bytes()[..700]);
+    assert!(data[700..].iter().all(|byte| *byte == 0));
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    assert_eq!(fuse.set_size(root, 0).err(), Some(EISDIR));
+
+    // from the second level of indirect blocks down to the first and none
+    let fileb = fuse.create_file(root, name("fileb")).unwrap().inode_id() as u64 + 1;
+    let data: Vec<u8> = (0..200 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    assert_eq!(fuse.write_data(fileb, 0, &data), Ok(data.len()));
+    for &size in [100 * BLOCK_SZ + 10, 10 * BLOCK_SZ, 0].iter() {
+        fuse.set_size(fileb, size as u64).unwrap();
+        assert_eq!(fuse.read_data(fileb, 0, data.len() as u32).unwrap(), &data[..size]);
+        assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    }
+
+    let dira = fuse.make_dir(root, name("dira")).unwrap().inode_id() as u64 + 1;
+    fuse.link_file(filea, dira, name("filec")).unwrap();
+    let entries = fuse.dir_entries(root, 0).unwrap();
+    let listed: Vec<(u64, &str)> = entries.iter()
+        .map(|(ino, _, _, name)| (*ino, name.as_str()))
+        .collect();
+    assert_eq!(listed, vec![
+        (root, "."),
+        (root, ".."),
+        (filea, "filea"),
+        (fileb, "fileb"),
+        (dira, "dira"),
+    ]);
+    // going on from where a full reply stopped
+    assert_eq!(fuse.dir_entries(root, entries[2].1).unwrap().len(), 2);
+
+    assert_eq!(fuse.remove_file(root, name("dira")), Err(EISDIR));
+    fuse.remove_file(root, name("filea")).unwrap();
+    assert_eq!(fuse.remove_file(root, name("filea")), Err(ENOENT));
+    assert_eq!(fuse.read_data(filea, 0, 16).unwrap(), &greet_str.as_bytes()[..16]);
+    fuse.remove_file(dira, name("filec")).unwrap();
+    fuse.remove_file(root, name("fileb")).unwrap();
+    assert_eq!(fuse.dir_entries(dira, 0).unwrap().len(), 2);
+    assert_eq!(fuse.dir_entries(root, 0).unwrap().len(), 3);
+
+    // the kernel may still ask about inodes it was told of before
+    for &ino in [filea, fileb, 0, u64::MAX].iter() {
+        assert_eq!(fuse.inode(ino).err(), Some(ENOENT));
+        assert_eq!(fuse.read_data(ino, 0, 16), Err(ENOENT));
+    }
+
+    // a file that does not fit is refused rather than taking the mount down
+    let filed = fuse.create_file(root, name("filed")).unwrap().inode_id() as u64 + 1;
+    let chunk = vec![0x5au8; 64 * BLOCK_SZ];
+    let mut size = 0;
+    while let Ok(len) = fuse.write_data(filed, size as i64, &chunk) {
+        size += len;
+    }
+    assert_eq!(fuse.write_data(filed, size as i64, &chunk), Err(ENOSPC));
+    assert_eq!(fuse.set_size(filed, (size + chunk.len()) as u64).err(), Some(ENOSPC));
+    // what is left still takes smaller writes
+    while let Ok(len) = fuse.write_data(filed, size as i64, &chunk[..BLOCK_SZ]) {
+        size += len;
+    }
+    assert!(efs.lock().free_data_blocks() <= 1);
+    assert_eq!(fuse.inode(filed).unwrap().size(), size);
+    assert_eq!(fuse.read_data(filed, size as i64 - 4, 16).unwrap(), &chunk[..4]);
+    assert_eq!(fuse.make_dir(root, name("dirb")).err(), Some(ENOSPC));
+    assert_eq!(fuse.set_size(filed, MAX_FILE_SIZE as u64 + 1).err(), Some(EFBIG));
+    assert_eq!(fuse.write_data(filed, MAX_FILE_SIZE as i64, b"x"), Err(EFBIG));
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+    fuse.remove_file(root, name("filed")).unwrap();
+    assert_eq!(fuse.inode(filed).err(), Some(ENOENT));
+    fuse.make_dir(root, name("dirb")).unwrap();
+    assert!(EasyFileSystem::fsck(&efs, false).is_empty());
+}
@@ -30,25 +30,25 @@ impl Bitmap {
 
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
-            let pos = get_block_cache(
+            let block_cache = get_block_cache(
                 block_id + self.start_block_id,
                 Arc::clone(block_device),
-            ).lock().modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
+            );
+            // look before touching, so that full blocks are not modified
+            let pos = block_cache.lock().read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block
                     .iter()
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| {
                         (bits64_pos, bits64.trailing_ones() as usize)
-                    }) {
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos)
-                } else {
-                    None
-                }
+                    })
             });
-            if pos.is_some() {
-                return pos;
+            if let Some((bits64_pos, inner_pos)) = pos {
+                block_cache.lock().modify(0, |bitmap_block: &mut BitmapBlock| {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                });
+                return Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos);
             }
         }
         None
@@ -65,6 +65,48 @@ impl Bitmap {
         });
     }
 
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(
+            block_pos + self.start_block_id,
+            Arc::clone(block_device),
+        ).lock().read(0, |bitmap_block: &BitmapBlock| {
+            bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+        })
+    }
+
+    /// Mark `bit` allocated, whatever it was.
+    pub fn mark(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(
+            block_pos + self.start_block_id,
+            Arc::clone(block_device),
+        ).lock().modify(0, |bitmap_block: &mut BitmapBlock| {
+            bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+        });
+    }
+
+    /// How many of the first `bits` bits are set.
+    pub fn count_allocated(&self, block_device: &Arc<dyn BlockDevice>, bits: usize) -> usize {
+        (0..self.blocks).map(|block_id| {
+            get_block_cache(
+                block_id + self.start_block_id,
+                Arc::clone(block_device),
+            ).lock().read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block.iter().enumerate().map(|(bits64_pos, bits64)| {
+                    let start = block_id * BLOCK_BITS + bits64_pos * 64;
+                    if start >= bits {
+                        0
+                    } else if start + 64 <= bits {
+                        bits64.count_ones() as usize
+                    } else {
+                        (bits64 & ((1u64 << (bits - start)) - 1)).count_ones() as usize
+                    }
+                }).sum::<usize>()
+            })
+        }).sum()
+    }
+
     pub fn maximum(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
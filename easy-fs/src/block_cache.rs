@@ -4,6 +4,7 @@ use super::{
 };
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -56,6 +57,14 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.cache
+    }
+
     pub fn sync(&mut self) {
         if self.modified {
             self.modified = false;
@@ -72,13 +81,26 @@ impl Drop for BlockCache {
 
 const BLOCK_CACHE_SIZE: usize = 16;
 
+/// Blocks of different devices share the cache, so entries are keyed
+/// by the device as well as the block id.
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    queue: VecDeque<((usize, usize), Arc<Mutex<BlockCache>>)>,
+    /// The devices with a transaction running. Their modified blocks must
+    /// not be written back before it commits, so they stay in the cache
+    /// even if that grows past its size meanwhile.
+    transactions: Vec<usize>,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
-        Self { queue: VecDeque::new() }
+        Self {
+            queue: VecDeque::new(),
+            transactions: Vec::new(),
+        }
     }
 
     pub fn get_block_cache(
@@ -86,17 +108,25 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
+        let key = (device_id(&block_device), block_id);
         if let Some(pair) = self.queue
             .iter()
-            .find(|pair| pair.0 == block_id) {
+            .find(|pair| pair.0 == key) {
             Arc::clone(&pair.1)
         } else {
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                if let Some((idx, _)) = self.queue
+            let transactions = &self.transactions;
+            while self.queue.len() >= BLOCK_CACHE_SIZE {
+                // nobody else holds a block only the cache refers to, so
+                // it can be locked here
+                if let Some(idx) = self.queue
                     .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1) {
+                    .position(|((device, _), block_cache)| {
+                        Arc::strong_count(block_cache) == 1
+                            && !(transactions.contains(device) && block_cache.lock().modified)
+                    }) {
                     self.queue.drain(idx..=idx);
+                } else if self.queue.iter().any(|((device, _), _)| transactions.contains(device)) {
+                    break;
                 } else {
                     panic!("Run out of BlockCache!");
                 }
@@ -105,7 +135,7 @@ impl BlockCacheManager {
             let block_cache = Arc::new(Mutex::new(
                 BlockCache::new(block_id, Arc::clone(&block_device))
             ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue.push_back((key, Arc::clone(&block_cache)));
             block_cache
         }
     }
@@ -117,6 +147,40 @@ lazy_static! {
     );
 }
 
+/// Keep what is modified on `block_device` from now on in the cache,
+/// until `end_transaction`.
+pub fn begin_transaction(block_device: &Arc<dyn BlockDevice>) {
+    BLOCK_CACHE_MANAGER.lock().transactions.push(device_id(block_device));
+}
+
+/// End the transaction running on `block_device` and hand out the cached
+/// blocks of the device, among which the ones it has modified. The
+/// blocks cannot leave the cache while they are held.
+pub fn end_transaction(block_device: &Arc<dyn BlockDevice>) -> Vec<(usize, Arc<Mutex<BlockCache>>)> {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    let device = device_id(block_device);
+    manager.transactions.retain(|id| *id != device);
+    manager.queue
+        .iter()
+        .filter(|((id, _), _)| *id == device)
+        .map(|((_, block_id), block_cache)| (*block_id, Arc::clone(block_cache)))
+        .collect()
+}
+
+/// Write back the modified blocks of `block_device`, which must have no
+/// transaction running.
+pub fn sync_device(block_device: &Arc<dyn BlockDevice>) {
+    let device = device_id(block_device);
+    let block_caches: Vec<_> = BLOCK_CACHE_MANAGER.lock().queue
+        .iter()
+        .filter(|((id, _), _)| *id == device)
+        .map(|(_, block_cache)| Arc::clone(block_cache))
+        .collect();
+    for block_cache in block_caches {
+        block_cache.lock().sync();
+    }
+}
+
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>
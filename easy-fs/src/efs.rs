@@ -6,45 +6,55 @@ use super::{
     SuperBlock,
     DiskInode,
     DiskInodeType,
+    DirEntry,
+    DIRENT_SZ,
     Inode,
+    Journal,
     get_block_cache,
 };
+use alloc::vec::Vec;
 use crate::BLOCK_SZ;
 
 pub struct EasyFileSystem {
     pub block_device: Arc<dyn BlockDevice>,
     pub inode_bitmap: Bitmap,
     pub data_bitmap: Bitmap,
+    journal: Journal,
     inode_area_start_block: u32,
     data_area_start_block: u32,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// The journal header and the log blocks. A transaction has to fit into
+/// the log, which is why large writes are split into several of them.
+const JOURNAL_BLOCKS: u32 = 1 + 64;
+
 impl EasyFileSystem {
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
     ) -> Arc<Mutex<Self>> {
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
+        let inode_bitmap = Bitmap::new((1 + JOURNAL_BLOCKS) as usize, inode_bitmap_blocks as usize);
         let inode_num = inode_bitmap.maximum();
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_total_blocks = total_blocks - 1 - JOURNAL_BLOCKS - inode_total_blocks;
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            (1 + JOURNAL_BLOCKS + inode_total_blocks) as usize,
             data_bitmap_blocks as usize,
         );
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            journal: Journal::new(1, JOURNAL_BLOCKS as usize),
+            inode_area_start_block: 1 + JOURNAL_BLOCKS + inode_bitmap_blocks,
+            data_area_start_block: 1 + JOURNAL_BLOCKS + inode_total_blocks + data_bitmap_blocks,
         };
 
         for i in 0..total_blocks {
@@ -63,6 +73,7 @@ impl EasyFileSystem {
             .modify(0, |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
+                    JOURNAL_BLOCKS,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
@@ -72,6 +83,10 @@ impl EasyFileSystem {
 
         assert_eq!(efs.alloc_inode(), 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
+        let root_size = (2 * DIRENT_SZ) as u32;
+        let root_blocks: Vec<u32> = (0..DiskInode::total_blocks(root_size))
+            .map(|_| efs.alloc_data())
+            .collect();
         get_block_cache(
             root_inode_block_id as usize,
             Arc::clone(&block_device),
@@ -79,32 +94,60 @@ impl EasyFileSystem {
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
                 disk_inode.initialize(DiskInodeType::Directory);
+                // Both "." and ".." of the root directory refer to itself
+                disk_inode.increase_size(root_size, root_blocks, &block_device);
+                disk_inode.write_at(0, DirEntry::new(".", 0).as_bytes(), &block_device);
+                disk_inode.write_at(DIRENT_SZ, DirEntry::new("..", 0).as_bytes(), &block_device);
             });
         Arc::new(Mutex::new(efs))
     }
 
+    /// Open the filesystem on `block_device`, completing the transaction
+    /// that was committed last if a crash interrupted it.
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
-        get_block_cache(0, Arc::clone(&block_device))
+        let efs = get_block_cache(0, Arc::clone(&block_device))
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
+                let journal_blocks = super_block.journal_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
-                    block_device,
+                Self {
+                    block_device: Arc::clone(&block_device),
                     inode_bitmap: Bitmap::new(
-                        1,
+                        (1 + journal_blocks) as usize,
                         super_block.inode_bitmap_blocks as usize,
                     ),
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (1 + journal_blocks + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                    journal: Journal::new(1, journal_blocks as usize),
+                    inode_area_start_block: 1 + journal_blocks + super_block.inode_bitmap_blocks,
+                    data_area_start_block: 1 + journal_blocks + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                }
+            });
+        efs.journal.replay(&block_device);
+        Arc::new(Mutex::new(efs))
+    }
+
+    /// Whether `block_device` holds an easy-fs of the layout `open` takes.
+    pub fn probe(block_device: &Arc<dyn BlockDevice>) -> bool {
+        get_block_cache(0, Arc::clone(block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.is_valid())
+    }
+
+    /// Start a transaction: nothing modified from now on reaches the disk
+    /// before `commit`, and then all of it does at once. Transactions are
+    /// run one at a time under the lock of the filesystem.
+    pub fn begin(&self) {
+        self.journal.begin(&self.block_device);
+    }
+
+    pub fn commit(&self) {
+        self.journal.commit(&self.block_device);
     }
 
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
@@ -138,11 +181,28 @@ impl EasyFileSystem {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
 
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap.dealloc(&self.block_device, inode_id as usize);
+    }
+
+    /// Whether `inode_id` names an inode in use, rather than a freed one or
+    /// none at all.
+    pub fn is_inode_allocated(&self, inode_id: u32) -> bool {
+        (inode_id as usize) < self.inode_bitmap.maximum()
+            && self.inode_bitmap.is_allocated(&self.block_device, inode_id as usize)
+    }
+
+    /// Allocate a data block, zeroed. This happens here rather than on
+    /// release, so that releasing a large file keeps to a few bitmap
+    /// blocks of the journal.
     pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+        self.try_alloc_data().unwrap()
     }
 
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// `alloc_data`, or None if the data area is full.
+    pub fn try_alloc_data(&mut self) -> Option<u32> {
+        let block_id = self.data_bitmap.alloc(&self.block_device)? as u32
+            + self.data_area_start_block;
         get_block_cache(
             block_id as usize,
             Arc::clone(&self.block_device),
@@ -151,9 +211,46 @@ impl EasyFileSystem {
             .modify(0, |data_block: &mut DataBlock| {
                 data_block.iter_mut().for_each(|p| { *p = 0; })
             });
+        Some(block_id)
+    }
+
+    pub fn dealloc_data(&mut self, block_id: u32) {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
         );
     }
+
+    /// The number of data blocks not in use. The bitmap may have more bits
+    /// than there are blocks, so only those of the data area count.
+    pub fn free_data_blocks(&self) -> u32 {
+        let data_area_blocks = get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.data_area_blocks);
+        data_area_blocks
+            - self.data_bitmap.count_allocated(&self.block_device, data_area_blocks as usize) as u32
+    }
+
+    pub fn total_blocks(&self) -> u32 {
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.total_blocks)
+    }
+
+    /// Copy out block `block_id` of the device as it is, going through the
+    /// block cache so that what the filesystem has not written back yet
+    /// shows.
+    pub fn read_raw_block(&self, block_id: u32, buf: &mut [u8]) {
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |data_block: &DataBlock| buf.copy_from_slice(data_block));
+    }
+
+    pub fn write_raw_block(&self, block_id: u32, buf: &[u8]) {
+        self.begin();
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(0, |data_block: &mut DataBlock| data_block.copy_from_slice(buf));
+        self.commit();
+    }
 }
@@ -0,0 +1,449 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+use spin::Mutex;
+use super::{
+    EasyFileSystem,
+    SuperBlock,
+    DiskInode,
+    DirEntry,
+    IndirectBlock,
+    DIRENT_SZ,
+    INODE_DIRECT_COUNT,
+    INODE_INDIRECT1_COUNT,
+    MAX_FILE_SIZE,
+    BLOCK_SZ,
+    get_block_cache,
+    sync_device,
+};
+
+/// What fsck finds wrong with a filesystem.
+#[derive(Debug, PartialEq)]
+pub enum FsckProblem {
+    /// Allocated in the data bitmap but used by no inode.
+    LeakedBlock(u32),
+    /// Used by two inodes, or twice by the same one.
+    DoubleAllocatedBlock { block_id: u32, inodes: (u32, u32) },
+    /// Used by an inode but free in the data bitmap.
+    UnmarkedBlock { block_id: u32, inode: u32 },
+    /// Referred to by an inode but outside the data area.
+    BadBlock { block_id: u32, inode: u32 },
+    /// Allocated in the inode bitmap but in no directory.
+    LeakedInode(u32),
+    WrongNlink { inode: u32, nlink: u32, expected: u32 },
+    /// An entry of a directory for an inode that is not allocated.
+    DanglingEntry { dir: u32, name: String, inode: u32 },
+    /// Larger than any inode can be. `fitted` is what the data blocks
+    /// in the data area at the start of its tree hold.
+    BadSize { inode: u32, size: u32, fitted: u32 },
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            FsckProblem::LeakedBlock(block_id) =>
+                write!(f, "block {} is allocated but unused", block_id),
+            FsckProblem::DoubleAllocatedBlock { block_id, inodes } =>
+                write!(f, "block {} is used by inode {} and inode {}", block_id, inodes.0, inodes.1),
+            FsckProblem::UnmarkedBlock { block_id, inode } =>
+                write!(f, "block {} of inode {} is not allocated", block_id, inode),
+            FsckProblem::BadBlock { block_id, inode } =>
+                write!(f, "inode {} refers to block {} outside the data area", inode, block_id),
+            FsckProblem::LeakedInode(inode) =>
+                write!(f, "inode {} is allocated but in no directory", inode),
+            FsckProblem::WrongNlink { inode, nlink, expected } =>
+                write!(f, "inode {} has nlink {} instead of {}", inode, nlink, expected),
+            FsckProblem::DanglingEntry { dir, name, inode } =>
+                write!(f, "entry {} of directory inode {} refers to free inode {}", name, dir, inode),
+            FsckProblem::BadSize { inode, size, fitted } =>
+                write!(f, "inode {} has size {}, more than its blocks hold, instead of {}", inode, size, fitted),
+        }
+    }
+}
+
+/// Where a block is referred to from, to point that at a copy instead.
+#[derive(Copy, Clone)]
+enum BlockRef {
+    Direct(u32, usize),
+    Indirect1(u32),
+    Indirect2(u32),
+    Entry(u32, usize),
+}
+
+/// A block of the tree of an inode. `block_ref` is `None` for the data
+/// blocks under an indirect block outside the data area, which only keep
+/// the place of those that can be read.
+struct TreeBlock {
+    block_id: u32,
+    block_ref: Option<BlockRef>,
+    is_data: bool,
+}
+
+struct InodeInfo {
+    size: u32,
+    direct: [u32; INODE_DIRECT_COUNT],
+    indirect1: u32,
+    indirect2: u32,
+    nlink: u32,
+    is_dir: bool,
+    data_blocks: u32,
+}
+
+/// A block copied for its second user shares the blocks it refers to, so
+/// those are only found and copied on the next pass.
+const MAX_REPAIR_PASSES: usize = 4;
+
+impl EasyFileSystem {
+    /// Check the whole filesystem: the blocks of every inode against each
+    /// other and the data bitmap, the entries of every directory against
+    /// the inode bitmap and the link counts. With `repair`, what is found
+    /// is fixed as well. Leaked blocks and inodes are freed, a block used
+    /// twice is copied for its second user, a block outside the data area
+    /// is replaced by a zeroed one, link counts are corrected, dangling
+    /// entries removed and sizes too large cut down to the blocks there
+    /// are, each repair in a transaction of its own. Blocks
+    /// there is no room to copy are left as they are. The problems of the
+    /// filesystem as it was are returned.
+    pub fn fsck(efs: &Arc<Mutex<Self>>, repair: bool) -> Vec<FsckProblem> {
+        let mut fs = efs.lock();
+        let problems = fs.check(repair);
+        if repair {
+            for _ in 1..MAX_REPAIR_PASSES {
+                if fs.check(true).is_empty() {
+                    break;
+                }
+            }
+            sync_device(&fs.block_device);
+        }
+        problems
+    }
+
+    fn check(&mut self, repair: bool) -> Vec<FsckProblem> {
+        let (inode_count, data_area_blocks) = get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                let inodes_per_block = BLOCK_SZ / core::mem::size_of::<DiskInode>();
+                (
+                    self.inode_bitmap.maximum()
+                        .min(super_block.inode_area_blocks as usize * inodes_per_block),
+                    super_block.data_area_blocks as usize,
+                )
+            });
+        let mut problems = Vec::new();
+
+        // every directory from the root, counting the entries of each inode
+        let mut refs = vec![0u32; inode_count];
+        let mut reachable = vec![false; inode_count];
+        reachable[0] = true;
+        let mut dirs = vec![0u32];
+        while let Some(dir) = dirs.pop() {
+            let mut info = self.inode_info(dir);
+            self.fit_size(dir, &mut info, data_area_blocks);
+            let data: Vec<TreeBlock> = self.block_tree(dir, &info, data_area_blocks)
+                .into_iter()
+                .filter(|tree_block| tree_block.is_data)
+                .collect();
+            let dirents_per_block = BLOCK_SZ / DIRENT_SZ;
+            let slots = (info.size as usize / DIRENT_SZ).min(data.len() * dirents_per_block);
+            for slot in 0..slots {
+                let block_id = data[slot / dirents_per_block].block_id;
+                if !self.in_data_area(block_id, data_area_blocks) {
+                    continue;
+                }
+                let offset = slot % dirents_per_block * DIRENT_SZ;
+                let block_cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+                let dirent = block_cache.lock().read(offset, |dirent: &DirEntry| {
+                    if dirent.is_empty() {
+                        None
+                    } else {
+                        Some((String::from(dirent.name()), dirent.inode_number()))
+                    }
+                });
+                let (name, inode_id) = match dirent {
+                    Some(dirent) => dirent,
+                    None => continue,
+                };
+                if inode_id as usize >= inode_count
+                    || !self.inode_bitmap.is_allocated(&self.block_device, inode_id as usize) {
+                    problems.push(FsckProblem::DanglingEntry { dir, name, inode: inode_id });
+                    if repair {
+                        self.repair_step(|_| {
+                            block_cache.lock().modify(offset, |dirent: &mut DirEntry| {
+                                *dirent = DirEntry::empty();
+                            });
+                        });
+                    }
+                    continue;
+                }
+                refs[inode_id as usize] += 1;
+                if !reachable[inode_id as usize] {
+                    reachable[inode_id as usize] = true;
+                    if self.inode_info(inode_id).is_dir {
+                        dirs.push(inode_id);
+                    }
+                }
+            }
+        }
+
+        for (inode_id, reachable) in reachable.iter().enumerate() {
+            if !reachable && self.inode_bitmap.is_allocated(&self.block_device, inode_id) {
+                problems.push(FsckProblem::LeakedInode(inode_id as u32));
+                if repair {
+                    self.repair_step(|fs| fs.inode_bitmap.dealloc(&fs.block_device, inode_id));
+                }
+            }
+        }
+
+        // the blocks of the inodes in use, by the first inode using each
+        let mut owners: Vec<Option<u32>> = vec![None; data_area_blocks];
+        let mut copies: Vec<(Option<u32>, BlockRef)> = Vec::new();
+        for inode_id in (0..inode_count as u32).filter(|id| reachable[*id as usize]) {
+            let mut info = self.inode_info(inode_id);
+            let size = info.size;
+            if self.fit_size(inode_id, &mut info, data_area_blocks) {
+                problems.push(FsckProblem::BadSize { inode: inode_id, size, fitted: info.size });
+                if repair {
+                    self.repair_step(|fs| fs.modify_disk_inode(inode_id, |disk_inode| {
+                        disk_inode.size = info.size;
+                    }));
+                }
+            }
+            // what is under an indirect block used twice is only looked
+            // at once that block has its own copy
+            let mut shared: Vec<u32> = Vec::new();
+            for tree_block in self.block_tree(inode_id, &info, data_area_blocks) {
+                let block_id = tree_block.block_id;
+                let block_ref = match tree_block.block_ref {
+                    Some(BlockRef::Entry(parent, _)) if shared.contains(&parent) => {
+                        if !tree_block.is_data {
+                            shared.push(block_id);
+                        }
+                        continue;
+                    }
+                    Some(block_ref) => block_ref,
+                    None => continue,
+                };
+                if !self.in_data_area(block_id, data_area_blocks) {
+                    problems.push(FsckProblem::BadBlock { block_id, inode: inode_id });
+                    copies.push((None, block_ref));
+                    continue;
+                }
+                let bit = (block_id - self.get_data_block_id(0)) as usize;
+                match owners[bit] {
+                    Some(owner) => {
+                        problems.push(FsckProblem::DoubleAllocatedBlock {
+                            block_id,
+                            inodes: (owner, inode_id),
+                        });
+                        copies.push((Some(block_id), block_ref));
+                        if !tree_block.is_data {
+                            shared.push(block_id);
+                        }
+                    }
+                    None => {
+                        owners[bit] = Some(inode_id);
+                        if !self.data_bitmap.is_allocated(&self.block_device, bit) {
+                            problems.push(FsckProblem::UnmarkedBlock { block_id, inode: inode_id });
+                            if repair {
+                                self.repair_step(|fs| fs.data_bitmap.mark(&fs.block_device, bit));
+                            }
+                        }
+                    }
+                }
+            }
+            if info.nlink != refs[inode_id as usize] {
+                problems.push(FsckProblem::WrongNlink {
+                    inode: inode_id,
+                    nlink: info.nlink,
+                    expected: refs[inode_id as usize],
+                });
+                if repair {
+                    self.repair_step(|fs| fs.modify_disk_inode(inode_id, |disk_inode| {
+                        disk_inode.nlink = refs[inode_id as usize];
+                    }));
+                }
+            }
+        }
+
+        for (bit, owner) in owners.iter().enumerate() {
+            if owner.is_none() && self.data_bitmap.is_allocated(&self.block_device, bit) {
+                problems.push(FsckProblem::LeakedBlock(self.get_data_block_id(bit as u32)));
+                if repair {
+                    self.repair_step(|fs| fs.data_bitmap.dealloc(&fs.block_device, bit));
+                }
+            }
+        }
+
+        // only now that the data bitmap is right can blocks be allocated;
+        // without room for a copy the problem is left, to be found again
+        if repair {
+            for (block_id, block_ref) in copies {
+                self.begin();
+                let new_block_id = match self.try_alloc_data() {
+                    Some(new_block_id) => new_block_id,
+                    None => {
+                        self.commit();
+                        break;
+                    }
+                };
+                if let Some(block_id) = block_id {
+                    let mut data = [0u8; BLOCK_SZ];
+                    self.read_raw_block(block_id, &mut data);
+                    get_block_cache(new_block_id as usize, Arc::clone(&self.block_device))
+                        .lock()
+                        .modify(0, |data_block: &mut [u8; BLOCK_SZ]| data_block.copy_from_slice(&data));
+                }
+                self.set_block_ref(block_ref, new_block_id);
+                self.commit();
+            }
+        }
+        problems
+    }
+
+    /// Make one repair a transaction of its own, so that a crash in the
+    /// middle of fsck leaves each either done or not.
+    fn repair_step(&self, f: impl FnOnce(&Self)) {
+        self.begin();
+        f(self);
+        self.commit();
+    }
+
+    fn in_data_area(&self, block_id: u32, data_area_blocks: usize) -> bool {
+        let start = self.get_data_block_id(0);
+        block_id >= start && ((block_id - start) as usize) < data_area_blocks
+    }
+
+    fn inode_info(&self, inode_id: u32) -> InodeInfo {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| InodeInfo {
+                size: disk_inode.size,
+                direct: disk_inode.direct,
+                indirect1: disk_inode.indirect1,
+                indirect2: disk_inode.indirect2,
+                nlink: disk_inode.nlink,
+                is_dir: disk_inode.is_dir(),
+                // the size may be anything, even too close to u32::MAX to round up
+                data_blocks: ((disk_inode.size as usize + BLOCK_SZ - 1) / BLOCK_SZ)
+                    .min(MAX_FILE_SIZE / BLOCK_SZ) as u32,
+            })
+    }
+
+    /// Cut the size of `info` down to the data blocks in the data area its
+    /// tree starts with if it is larger than any inode can be, rather than
+    /// take whatever the indirect blocks hold past them for its data.
+    /// Returns whether it was.
+    fn fit_size(&self, inode_id: u32, info: &mut InodeInfo, data_area_blocks: usize) -> bool {
+        if info.size as usize <= MAX_FILE_SIZE {
+            return false;
+        }
+        let data_blocks = self.block_tree(inode_id, info, data_area_blocks)
+            .into_iter()
+            .filter(|tree_block| tree_block.is_data)
+            .take_while(|tree_block| self.in_data_area(tree_block.block_id, data_area_blocks))
+            .count();
+        info.size = (data_blocks * BLOCK_SZ) as u32;
+        info.data_blocks = data_blocks as u32;
+        true
+    }
+
+    fn modify_disk_inode(&self, inode_id: u32, f: impl FnOnce(&mut DiskInode)) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f);
+    }
+
+    /// The blocks of inode `inode_id`, its data blocks in order. Indirect
+    /// blocks outside the data area are not read.
+    fn block_tree(&self, inode_id: u32, info: &InodeInfo, data_area_blocks: usize) -> Vec<TreeBlock> {
+        let mut tree = Vec::new();
+        let mut data_blocks = info.data_blocks as usize;
+        for (idx, block_id) in info.direct.iter().enumerate().take(data_blocks) {
+            tree.push(TreeBlock {
+                block_id: *block_id,
+                block_ref: Some(BlockRef::Direct(inode_id, idx)),
+                is_data: true,
+            });
+        }
+        if data_blocks <= INODE_DIRECT_COUNT {
+            return tree;
+        }
+        data_blocks -= INODE_DIRECT_COUNT;
+        tree.push(TreeBlock {
+            block_id: info.indirect1,
+            block_ref: Some(BlockRef::Indirect1(inode_id)),
+            is_data: false,
+        });
+        self.indirect_entries(
+            info.indirect1,
+            data_blocks.min(INODE_INDIRECT1_COUNT),
+            true,
+            data_area_blocks,
+            &mut tree,
+        );
+        if data_blocks <= INODE_INDIRECT1_COUNT {
+            return tree;
+        }
+        data_blocks -= INODE_INDIRECT1_COUNT;
+        tree.push(TreeBlock {
+            block_id: info.indirect2,
+            block_ref: Some(BlockRef::Indirect2(inode_id)),
+            is_data: false,
+        });
+        let indirect1_blocks = (data_blocks + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+        let first = tree.len();
+        self.indirect_entries(info.indirect2, indirect1_blocks, false, data_area_blocks, &mut tree);
+        let indirect1s: Vec<u32> = tree[first..].iter().map(|tree_block| tree_block.block_id).collect();
+        for (i, indirect1) in indirect1s.into_iter().enumerate() {
+            let entries = (data_blocks - i * INODE_INDIRECT1_COUNT).min(INODE_INDIRECT1_COUNT);
+            self.indirect_entries(indirect1, entries, true, data_area_blocks, &mut tree);
+        }
+        tree
+    }
+
+    fn indirect_entries(
+        &self,
+        block_id: u32,
+        count: usize,
+        is_data: bool,
+        data_area_blocks: usize,
+        tree: &mut Vec<TreeBlock>,
+    ) {
+        if !self.in_data_area(block_id, data_area_blocks) {
+            // block 0 is the superblock, never in the data area
+            tree.extend((0..count).map(|_| TreeBlock { block_id: 0, block_ref: None, is_data }));
+            return;
+        }
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |indirect_block: &IndirectBlock| {
+                for (idx, entry) in indirect_block.iter().enumerate().take(count) {
+                    tree.push(TreeBlock {
+                        block_id: *entry,
+                        block_ref: Some(BlockRef::Entry(block_id, idx)),
+                        is_data,
+                    });
+                }
+            });
+    }
+
+    fn set_block_ref(&self, block_ref: BlockRef, block_id: u32) {
+        match block_ref {
+            BlockRef::Direct(inode_id, idx) =>
+                self.modify_disk_inode(inode_id, |disk_inode| disk_inode.direct[idx] = block_id),
+            BlockRef::Indirect1(inode_id) =>
+                self.modify_disk_inode(inode_id, |disk_inode| disk_inode.indirect1 = block_id),
+            BlockRef::Indirect2(inode_id) =>
+                self.modify_disk_inode(inode_id, |disk_inode| disk_inode.indirect2 = block_id),
+            BlockRef::Entry(indirect_block_id, idx) => {
+                get_block_cache(indirect_block_id as usize, Arc::clone(&self.block_device))
+                    .lock()
+                    .modify(0, |indirect_block: &mut IndirectBlock| indirect_block[idx] = block_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use super::{
+    BlockDevice,
+    JournalHeader,
+    JOURNAL_HEADER_CAPACITY,
+    BLOCK_SZ,
+    begin_transaction,
+    end_transaction,
+};
+
+/// Write-ahead journal making each change to the filesystem atomic. What
+/// a transaction modifies stays in the block cache until it commits: the
+/// new blocks are first written to the log, then the header listing them,
+/// and only then the blocks themselves. After a crash the blocks of a
+/// committed transaction are copied from the log again, while those of
+/// one that has not committed never left the cache.
+pub struct Journal {
+    header_block: usize,
+    log_blocks: usize,
+}
+
+impl Journal {
+    /// The journal in the `blocks` blocks from `start_block` on, the
+    /// header and the log blocks.
+    pub fn new(start_block: usize, blocks: usize) -> Self {
+        assert!(blocks >= 2 && blocks - 1 <= JOURNAL_HEADER_CAPACITY);
+        Self {
+            header_block: start_block,
+            log_blocks: blocks - 1,
+        }
+    }
+
+    pub fn begin(&self, block_device: &Arc<dyn BlockDevice>) {
+        begin_transaction(block_device);
+    }
+
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) {
+        let blocks: Vec<_> = end_transaction(block_device)
+            .into_iter()
+            .filter(|(_, block_cache)| block_cache.lock().is_modified())
+            .collect();
+        if blocks.is_empty() {
+            return;
+        }
+        assert!(blocks.len() <= self.log_blocks, "Transaction too large for the journal!");
+        let mut header = JournalHeader::empty();
+        for (i, (block_id, block_cache)) in blocks.iter().enumerate() {
+            block_device.write_block(self.header_block + 1 + i, block_cache.lock().data());
+            header.blocks[i] = *block_id as u32;
+        }
+        header.count = blocks.len() as u32;
+        block_device.write_block(self.header_block, header.as_bytes());
+        for (_, block_cache) in blocks.iter() {
+            block_cache.lock().sync();
+        }
+        block_device.write_block(self.header_block, JournalHeader::empty().as_bytes());
+    }
+
+    /// Finish the transaction that committed before a crash, if any. It
+    /// has to happen before the blocks it touches are cached.
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) {
+        let mut header = JournalHeader::empty();
+        block_device.read_block(self.header_block, header.as_bytes_mut());
+        if header.count == 0 {
+            return;
+        }
+        assert!(header.count as usize <= self.log_blocks, "Corrupted journal!");
+        let mut block = [0u8; BLOCK_SZ];
+        for i in 0..header.count as usize {
+            block_device.read_block(self.header_block + 1 + i, &mut block);
+            block_device.write_block(header.blocks[i] as usize, &block);
+        }
+        block_device.write_block(self.header_block, JournalHeader::empty().as_bytes());
+    }
+}
@@ -7,20 +7,24 @@ use super::{
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
+const EFS_MAGIC: u32 = 0x3b800002;
+pub const INODE_DIRECT_COUNT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 27;
+pub const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The largest file the direct and indirect blocks of an inode can hold.
+pub const MAX_FILE_SIZE: usize = INDIRECT2_BOUND * BLOCK_SZ;
 
 #[repr(C)]
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
+    /// The journal header and the log blocks after it, which come right
+    /// after the super block.
+    pub journal_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
@@ -31,6 +35,7 @@ impl Debug for SuperBlock {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         f.debug_struct("SuperBlock")
             .field("total_blocks", &self.total_blocks)
+            .field("journal_blocks", &self.journal_blocks)
             .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
@@ -43,6 +48,7 @@ impl SuperBlock {
     pub fn initialize(
         &mut self,
         total_blocks: u32,
+        journal_blocks: u32,
         inode_bitmap_blocks: u32,
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
@@ -51,6 +57,7 @@ impl SuperBlock {
         *self = Self {
             magic: EFS_MAGIC,
             total_blocks,
+            journal_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
@@ -63,13 +70,51 @@ impl SuperBlock {
     }
 }
 
-#[derive(PartialEq)]
+pub const JOURNAL_HEADER_CAPACITY: usize = BLOCK_SZ / 4 - 1;
+
+/// The first block of the journal. A transaction has committed once the
+/// header lists the blocks it modified; their new contents are in the log
+/// blocks after the header, in the same order.
+#[repr(C)]
+pub struct JournalHeader {
+    pub count: u32,
+    pub blocks: [u32; JOURNAL_HEADER_CAPACITY],
+}
+
+impl JournalHeader {
+    pub fn empty() -> Self {
+        Self {
+            count: 0,
+            blocks: [0; JOURNAL_HEADER_CAPACITY],
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const _ as usize as *const u8,
+                BLOCK_SZ,
+            )
+        }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self as *mut _ as usize as *mut u8,
+                BLOCK_SZ,
+            )
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
 }
 
-type IndirectBlock = [u32; BLOCK_SZ / 4];
+pub type IndirectBlock = [u32; BLOCK_SZ / 4];
 type DataBlock = [u8; BLOCK_SZ];
 
 #[repr(C)]
@@ -78,6 +123,7 @@ pub struct DiskInode {
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    pub nlink: u32,
     type_: DiskInodeType,
 }
 
@@ -87,6 +133,12 @@ impl DiskInode {
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        // A directory is referenced by its own "." entry
+        // and by the entry in its parent directory.
+        self.nlink = match type_ {
+            DiskInodeType::File => 1,
+            DiskInodeType::Directory => 2,
+        };
         self.type_ = type_;
     }
 
@@ -261,7 +313,7 @@ impl DiskInode {
             Arc::clone(block_device),
         )
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
+            .read(0, |indirect1: &IndirectBlock| {
                 while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
                     v.push(indirect1[current_blocks]);
                     current_blocks += 1;
@@ -284,7 +336,7 @@ impl DiskInode {
             Arc::clone(block_device),
         )
             .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
+            .read(0, |indirect2: &IndirectBlock| {
                 for i in 0..a1 {
                     v.push(indirect2[i]);
                     get_block_cache(
@@ -292,7 +344,7 @@ impl DiskInode {
                         Arc::clone(block_device),
                     )
                         .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
+                        .read(0, |indirect1: &IndirectBlock| {
                             for j in 0..INODE_INDIRECT1_COUNT {
                                 v.push(indirect1[j]);
                             }
@@ -306,7 +358,7 @@ impl DiskInode {
                         Arc::clone(block_device),
                     )
                         .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
+                        .read(0, |indirect1: &IndirectBlock| {
                             for j in 0..b1 {
                                 v.push(indirect1[j]);
                             }
@@ -317,6 +369,45 @@ impl DiskInode {
         v
     }
 
+    /// Shrink to `new_size`, returning the data blocks past the new end
+    /// and the index blocks nothing is left in.
+    pub fn decrease_size(&mut self, new_size: u32, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let old_blocks = self.data_blocks() as usize;
+        let new_blocks = Self::_data_blocks(new_size) as usize;
+        let mut v: Vec<u32> = (new_blocks..old_blocks)
+            .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+            .collect();
+        for inner_id in new_blocks..old_blocks.min(INODE_DIRECT_COUNT) {
+            self.direct[inner_id] = 0;
+        }
+        if old_blocks > INODE_DIRECT_COUNT && new_blocks <= INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+            self.indirect1 = 0;
+        }
+        if old_blocks > INDIRECT1_BOUND {
+            // how many first level blocks under indirect2 a size needs
+            let indirect1s = |blocks: usize| {
+                (blocks.max(INDIRECT1_BOUND) - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1)
+                    / INODE_INDIRECT1_COUNT
+            };
+            get_block_cache(
+                self.indirect2 as usize,
+                Arc::clone(block_device),
+            )
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(&indirect2[indirect1s(new_blocks)..indirect1s(old_blocks)]);
+                });
+            if new_blocks <= INDIRECT1_BOUND {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        self.size = new_size;
+        v
+    }
+
     pub fn read_at(
         &self,
         offset: usize,
@@ -438,4 +529,8 @@ impl DirEntry {
     pub fn inode_number(&self) -> u32 {
         self.inode_number
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == 0
+    }
 }
@@ -9,13 +9,18 @@ mod bitmap;
 mod block_cache;
 mod block_dev;
 mod efs;
+mod fsck;
+mod journal;
 mod layout;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use fsck::FsckProblem;
 pub use vfs::{Inode, Stat, StatMode};
+pub use layout::{DIRENT_SZ, MAX_FILE_SIZE};
 use layout::*;
 use bitmap::Bitmap;
-use block_cache::get_block_cache;
+use block_cache::{get_block_cache, begin_transaction, end_transaction, sync_device};
+use journal::Journal;
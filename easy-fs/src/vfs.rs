@@ -5,6 +5,8 @@ use super::{
     DirEntry,
     EasyFileSystem,
     DIRENT_SZ,
+    NAME_LENGTH_LIMIT,
+    BLOCK_SZ,
     get_block_cache,
 };
 use alloc::sync::Arc;
@@ -12,6 +14,9 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
 
+/// How much of a write goes into one transaction.
+const WRITE_CHUNK: usize = 16 * BLOCK_SZ;
+
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
@@ -48,6 +53,15 @@ impl Inode {
         ).lock().modify(self.block_offset, f)
     }
 
+    /// Run `f` on the locked filesystem as one transaction.
+    fn transaction<V>(&self, f: impl FnOnce(&mut MutexGuard<EasyFileSystem>) -> V) -> V {
+        let mut fs = self.fs.lock();
+        fs.begin();
+        let ret = f(&mut fs);
+        fs.commit();
+        ret
+    }
+
     fn find_inode_id(
         &self,
         name: &str,
@@ -65,7 +79,7 @@ impl Inode {
                 ),
                 DIRENT_SZ,
             );
-            if dirent.name() == name {
+            if !dirent.is_empty() && dirent.name() == name {
                 return Some(dirent.inode_number() as u32);
             }
         }
@@ -75,6 +89,9 @@ impl Inode {
     pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
         let _ = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
             self.find_inode_id(name, disk_inode)
                 .map(|inode_id| {
                     Arc::new(Self::new(
@@ -86,6 +103,29 @@ impl Inode {
         })
     }
 
+    /// Walk `path` component by component starting from this inode.
+    /// Empty components are skipped, so "a//b/" is the same as "a/b",
+    /// while "." and ".." are resolved through the directory entries.
+    pub fn find_path(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+        let mut inode = Arc::clone(self);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.find(name)?;
+        }
+        Some(inode)
+    }
+
+    pub fn inode_id(&self) -> u32 {
+        self.fs.lock().get_inode_id(self.block_id as u32, self.block_offset)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_file())
+    }
+
     fn increase_size(
         &self,
         new_size: u32,
@@ -103,38 +143,124 @@ impl Inode {
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
-        if self.modify_disk_inode(|root_inode| {
-            assert!(root_inode.is_dir());
-            self.find_inode_id(name, root_inode)
-        }).is_some() {
-            return None;
+    fn release_blocks(
+        &self,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let size = disk_inode.size;
+        let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
+        assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+        for data_block in data_blocks_dealloc {
+            fs.dealloc_data(data_block);
         }
+    }
 
-        let new_inode_id = fs.alloc_inode();
-        let (new_inode_block_id, new_inode_block_offset)
-            = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(
-            new_inode_block_id as usize,
-            Arc::clone(&self.block_device),
-        )
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
-            });
-        self.modify_disk_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
+    /// Put a new entry into the first empty slot of a directory,
+    /// growing the directory if there is none.
+    fn add_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        dir_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        let slot = (0..file_count)
+            .find(|i| {
+                dir_inode.read_at(
+                    DIRENT_SZ * i,
+                    dirent.as_bytes_mut(),
+                    &self.block_device,
+                );
+                dirent.is_empty()
+            })
+            .unwrap_or(file_count);
+        if slot == file_count {
             let new_size = (file_count + 1) * DIRENT_SZ;
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
+            self.increase_size(new_size as u32, dir_inode, fs);
+        }
+        let dirent = DirEntry::new(name, inode_id);
+        dir_inode.write_at(
+            slot * DIRENT_SZ,
+            dirent.as_bytes(),
+            &self.block_device,
+        );
+    }
+
+    /// Clear the entry called `name` and return the inode it refers to.
+    fn remove_dirent(&self, name: &str, dir_inode: &mut DiskInode) -> Option<u32> {
+        let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        for i in 0..file_count {
+            dir_inode.read_at(
+                DIRENT_SZ * i,
+                dirent.as_bytes_mut(),
                 &self.block_device,
             );
-        });
-        drop(fs);
+            if !dirent.is_empty() && dirent.name() == name {
+                let new_dirent = DirEntry::empty();
+                dir_inode.write_at(
+                    DIRENT_SZ * i,
+                    new_dirent.as_bytes(),
+                    &self.block_device,
+                );
+                return Some(dirent.inode_number());
+            }
+        }
+        None
+    }
+
+    fn is_empty_dir(&self, dir_inode: &DiskInode) -> bool {
+        let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        (0..file_count).all(|i| {
+            dir_inode.read_at(
+                DIRENT_SZ * i,
+                dirent.as_bytes_mut(),
+                &self.block_device,
+            );
+            dirent.is_empty() || dirent.name() == "." || dirent.name() == ".."
+        })
+    }
+
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT || name.contains('/') {
+            return None;
+        }
+        let new_inode_id = self.transaction(|fs| {
+            if self.read_disk_inode(|dir_inode| {
+                !dir_inode.is_dir() || self.find_inode_id(name, dir_inode).is_some()
+            }) {
+                return None;
+            }
+
+            let new_inode_id = fs.alloc_inode();
+            let (new_inode_block_id, new_inode_block_offset)
+                = fs.get_disk_inode_pos(new_inode_id);
+            let dir_inode_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
+            get_block_cache(
+                new_inode_block_id as usize,
+                Arc::clone(&self.block_device),
+            )
+                .lock()
+                .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
+                    new_inode.initialize(type_);
+                    if type_ == DiskInodeType::Directory {
+                        self.add_dirent(".", new_inode_id, new_inode, fs);
+                        self.add_dirent("..", dir_inode_id, new_inode, fs);
+                    }
+                });
+            self.modify_disk_inode(|dir_inode| {
+                self.add_dirent(name, new_inode_id, dir_inode, fs);
+                if type_ == DiskInodeType::Directory {
+                    // ".." of the new directory
+                    dir_inode.nlink += 1;
+                }
+            });
+            Some(new_inode_id)
+        })?;
         Some(Arc::new(Self::new(
             new_inode_id,
             self.fs.clone(),
@@ -142,103 +268,132 @@ impl Inode {
         )))
     }
 
-    pub fn get_stat(&self, file: Arc<Inode>) -> Stat {
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    pub fn mkdir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    pub fn stat(&self) -> Stat {
         let fs = self.fs.lock();
-        let file_inode_id = fs.get_inode_id(file.block_id as u32, file.block_offset);
-        if file_inode_id == 0 {
-            return Stat {
+        let inode_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
+        self.read_disk_inode(|disk_inode| {
+            Stat {
                 dev: 0,
-                ino: 0,
-                mode: StatMode::DIR,
-                nlink: 1,
+                ino: inode_id as u64,
+                mode: if disk_inode.is_dir() { StatMode::DIR } else { StatMode::FILE },
+                nlink: disk_inode.nlink,
                 _pad: [0; 7],
-            };
-        }
-
-        let mut nlink: u32 = 0;
-        self.read_disk_inode(|root_inode| {
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let mut dirent = DirEntry::empty();
-            for i in 9..file_count {
-                assert_eq! (
-                    root_inode.read_at(
-                        i * DIRENT_SZ,
-                        dirent.as_bytes_mut(),
-                        &self.block_device,
-                    ),
-                    DIRENT_SZ,
-                );
-                if dirent.inode_number() == file_inode_id {
-                    nlink += 1;
-                }
             }
-        });
-        Stat {
-            dev: 0,
-            ino: file_inode_id as u64,
-            mode: StatMode::FILE,
-            nlink,
-            _pad: [0; 7],
-        }
+        })
     }
 
-    pub fn link(&self, old_name: &str, new_name: &str) -> Result<(), ()> {
-        let mut fs = self.fs.lock();
-        let inode_id = match self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(old_name, disk_inode)
-        }) {
-            Some(id) => id,
-            None => return Err(()),
-        };
-        if self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(new_name, disk_inode)
-        }).is_some() {
+    /// Add an entry called `new_name` in this directory referring to `file`.
+    /// Hard links to directories are not allowed.
+    pub fn link(&self, new_name: &str, file: &Inode) -> Result<(), ()> {
+        if new_name.is_empty() || new_name.len() > NAME_LENGTH_LIMIT || new_name.contains('/') {
             return Err(());
         }
-        self.modify_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            self.increase_size(new_size as u32, disk_inode, &mut fs);
-            let dirent = DirEntry::new(new_name, inode_id);
-            disk_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
-        drop(fs);
-        Ok(())
+        if !Arc::ptr_eq(&self.fs, &file.fs) || !file.is_file() {
+            return Err(());
+        }
+        self.transaction(|fs| {
+            if self.read_disk_inode(|dir_inode| {
+                !dir_inode.is_dir() || self.find_inode_id(new_name, dir_inode).is_some()
+            }) {
+                return Err(());
+            }
+            let inode_id = fs.get_inode_id(file.block_id as u32, file.block_offset);
+            self.modify_disk_inode(|dir_inode| {
+                self.add_dirent(new_name, inode_id, dir_inode, fs);
+            });
+            file.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink += 1;
+            });
+            Ok(())
+        })
     }
 
+    /// Remove the entry `name` referring to a regular file. The file itself
+    /// is released once its last link is gone.
     pub fn unlink(&self, name: &str) -> Result<(), ()> {
-        let _ = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut dirent = DirEntry::empty();
-            for i in 0..file_count {
-                disk_inode.read_at(
-                    DIRENT_SZ * i,
-                    dirent.as_bytes_mut(),
-                    &self.block_device,
-                );
-                if dirent.name() == name {
-                    if dirent.inode_number() == 0 {
-                        return Err(());
-                    } else {
-                        let new_dirent = DirEntry::empty();
-                        disk_inode.write_at(
-                            DIRENT_SZ * i,
-                            new_dirent.as_bytes(),
-                            &self.block_device,
-                        );
-                        return Ok(());
-                    }
+        self.transaction(|fs| {
+            let inode_id = match self.read_disk_inode(|dir_inode| {
+                if dir_inode.is_dir() {
+                    self.find_inode_id(name, dir_inode)
+                } else {
+                    None
                 }
+            }) {
+                Some(id) => id,
+                None => return Err(()),
+            };
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            let target = get_block_cache(
+                block_id as usize,
+                Arc::clone(&self.block_device),
+            );
+            if target.lock().read(block_offset, |disk_inode: &DiskInode| disk_inode.is_dir()) {
+                return Err(());
             }
-            Err(())
+            self.modify_disk_inode(|dir_inode| {
+                self.remove_dirent(name, dir_inode);
+            });
+            let nlink = target.lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.nlink -= 1;
+                if disk_inode.nlink == 0 {
+                    self.release_blocks(disk_inode, fs);
+                }
+                disk_inode.nlink
+            });
+            if nlink == 0 {
+                fs.dealloc_inode(inode_id);
+            }
+            Ok(())
         })
     }
-           
+
+    /// Remove the empty subdirectory `name`.
+    pub fn rmdir(&self, name: &str) -> Result<(), ()> {
+        if name == "." || name == ".." {
+            return Err(());
+        }
+        self.transaction(|fs| {
+            let inode_id = match self.read_disk_inode(|dir_inode| {
+                if dir_inode.is_dir() {
+                    self.find_inode_id(name, dir_inode)
+                } else {
+                    None
+                }
+            }) {
+                Some(id) => id,
+                None => return Err(()),
+            };
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            let target = get_block_cache(
+                block_id as usize,
+                Arc::clone(&self.block_device),
+            );
+            if !target.lock().read(block_offset, |disk_inode: &DiskInode| {
+                disk_inode.is_dir() && self.is_empty_dir(disk_inode)
+            }) {
+                return Err(());
+            }
+            self.modify_disk_inode(|dir_inode| {
+                self.remove_dirent(name, dir_inode);
+                dir_inode.nlink -= 1;
+            });
+            target.lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.nlink = 0;
+                self.release_blocks(disk_inode, fs);
+            });
+            fs.dealloc_inode(inode_id);
+            Ok(())
+        })
+    }
+
     pub fn ls(&self) -> Vec<String> {
         let _ = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
@@ -254,12 +409,69 @@ impl Inode {
                     ),
                     DIRENT_SZ,
                 );
-                v.push(String::from(dirent.name()));
+                if !dirent.is_empty() {
+                    v.push(String::from(dirent.name()));
+                }
             }
             v
         })
     }
 
+    /// Return the first live entry of this directory at or after byte
+    /// `offset`, as `(name, inode id, mode)` plus the offset of the slot
+    /// following it, so the directory can be walked one entry at a time.
+    pub fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        let fs = self.fs.lock();
+        let (name, inode_id, next) = self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            for i in (offset + DIRENT_SZ - 1) / DIRENT_SZ..file_count {
+                assert_eq!(
+                    disk_inode.read_at(
+                        i * DIRENT_SZ,
+                        dirent.as_bytes_mut(),
+                        &self.block_device,
+                    ),
+                    DIRENT_SZ,
+                );
+                if !dirent.is_empty() {
+                    return Some((
+                        String::from(dirent.name()),
+                        dirent.inode_number(),
+                        (i + 1) * DIRENT_SZ,
+                    ));
+                }
+            }
+            None
+        })?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let mode = get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device),
+        ).lock().read(block_offset, |disk_inode: &DiskInode| {
+            if disk_inode.is_dir() { StatMode::DIR } else { StatMode::FILE }
+        });
+        Some((name, inode_id, mode, next))
+    }
+
+    pub fn size(&self) -> usize {
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
+
+    /// How many more blocks the file takes up at `new_size`.
+    pub fn blocks_needed(&self, new_size: usize) -> u32 {
+        self.read_disk_inode(|disk_inode| {
+            if new_size as u32 > disk_inode.size {
+                disk_inode.blocks_num_needed(new_size as u32)
+            } else {
+                0
+            }
+        })
+    }
+
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _ = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
@@ -267,24 +479,65 @@ impl Inode {
         })
     }
 
+    /// Write `buf` at `offset`, growing the file as needed. Each chunk of
+    /// it is a transaction of its own, as is each chunk of a hole before
+    /// `offset`, so that all of them fit into the journal.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        })
+        loop {
+            let size = self.size();
+            if size >= offset {
+                break;
+            }
+            let new_size = offset.min(size + WRITE_CHUNK);
+            self.transaction(|fs| {
+                self.modify_disk_inode(|disk_inode| {
+                    self.increase_size(new_size as u32, disk_inode, fs);
+                });
+            });
+        }
+        let mut write_size = 0usize;
+        for chunk in buf.chunks(WRITE_CHUNK) {
+            let chunk_offset = offset + write_size;
+            write_size += self.transaction(|fs| {
+                self.modify_disk_inode(|disk_inode| {
+                    self.increase_size((chunk_offset + chunk.len()) as u32, disk_inode, fs);
+                    disk_inode.write_at(chunk_offset, chunk, &self.block_device)
+                })
+            });
+        }
+        write_size
     }
 
     pub fn clear(&self) {
-        let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc {
-                fs.dealloc_data(data_block);
-            }
-        });
+        self.transaction(|fs| {
+            self.modify_disk_inode(|disk_inode| {
+                self.release_blocks(disk_inode, fs);
+            });
+        })
+    }
+
+    /// Shrink the file to `new_size` in one transaction. Nothing happens
+    /// if it is not that large.
+    pub fn truncate(&self, new_size: usize) {
+        self.transaction(|fs| {
+            self.modify_disk_inode(|disk_inode| {
+                let size = disk_inode.size as usize;
+                if new_size >= size {
+                    return;
+                }
+                // the file may grow again, and then has to read zeros here
+                let tail_end = (new_size + (BLOCK_SZ - new_size % BLOCK_SZ) % BLOCK_SZ).min(size);
+                if tail_end > new_size {
+                    disk_inode.write_at(new_size, &[0u8; BLOCK_SZ][..tail_end - new_size], &self.block_device);
+                }
+                let data_blocks_dealloc = disk_inode.decrease_size(new_size as u32, &self.block_device);
+                assert!(data_blocks_dealloc.len()
+                    == (DiskInode::total_blocks(size as u32) - DiskInode::total_blocks(new_size as u32)) as usize);
+                for data_block in data_blocks_dealloc {
+                    fs.dealloc_data(data_block);
+                }
+            });
+        })
     }
 }
 
@@ -298,9 +551,16 @@ pub struct Stat {
     _pad: [u64; 7],
 }
 
+impl Stat {
+    pub fn new(dev: u64, ino: u64, mode: StatMode, nlink: u32) -> Self {
+        Self { dev, ino, mode, nlink, _pad: [0; 7] }
+    }
+}
+
 bitflags! {
     pub struct StatMode: u32 {
         const NULL = 0;
+        const CHR = 0o020000;
         const DIR = 0o040000;
         const FILE = 0o100000;
     }
@@ -10,6 +10,16 @@ pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
 pub const CLOCK_FREQ: usize = 12500000;
 
+/// Harts beyond these are left parked. A macro as well, since entry.asm
+/// needs the number as a literal.
+macro_rules! max_harts {
+    () => { 4 };
+}
+pub const MAX_HARTS: usize = max_harts!();
+
+pub const SWAP_SIZE: usize = 0x100_0000;
+
 pub const MMIO: &[(usize, usize)] = &[
     (0x10001000, 0x1000),
+    (0x10002000, 0x1000),
 ];
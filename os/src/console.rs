@@ -1,8 +1,12 @@
 use crate::sbi::console_putchar;
 use core::fmt::{self, Write};
+use spin::Mutex;
 
 struct Stdout;
 
+/// Keeps the lines printed by different harts apart.
+static PRINT_LOCK: Mutex<()> = Mutex::new(());
+
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -15,10 +19,20 @@ impl Write for Stdout {
 
 
 pub fn print(args: fmt::Arguments) {
+    let _guard = PRINT_LOCK.lock();
     Stdout.write_fmt(args).unwrap();
 }
 
 
+/// Write raw bytes, which need not be valid UTF-8, to the console.
+pub fn print_bytes(bytes: &[u8]) {
+    let _guard = PRINT_LOCK.lock();
+    for byte in bytes {
+        console_putchar(*byte as usize);
+    }
+}
+
+
 pub fn print_in_color(args: fmt::Arguments, color_code: u8) {
     print(format_args!("\x1b[{}m{}\x1b[0m", color_code, args));
 }
@@ -7,7 +7,16 @@ use easy_fs::BlockDevice;
 type BlockDeviceImpl = virtio_blk::VirtIOBlock;
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(
+        BlockDeviceImpl::new(virtio_blk::VIRTIO0)
+    );
+}
+
+lazy_static! {
+    /// A second disk holding nothing but swapped out user pages.
+    pub static ref SWAP_DEVICE: Arc<dyn BlockDevice> = Arc::new(
+        BlockDeviceImpl::new(virtio_blk::VIRTIO1)
+    );
 }
 
 #[allow(unused)]
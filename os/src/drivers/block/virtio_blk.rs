@@ -16,7 +16,9 @@ use alloc::vec::Vec;
 use lazy_static::*;
 
 #[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+pub const VIRTIO0: usize = 0x10001000;
+#[allow(unused)]
+pub const VIRTIO1: usize = 0x10002000;
 
 pub struct VirtIOBlock(Mutex<VirtIOBlk<'static>>);
 
@@ -36,9 +38,9 @@ impl BlockDevice for VirtIOBlock {
 
 impl VirtIOBlock {
     #[allow(unused)]
-    pub fn new() -> Self {
+    pub fn new(base: usize) -> Self {
         Self(Mutex::new(VirtIOBlk::new(
-            unsafe { &mut *(VIRTIO0 as *mut VirtIOHeader) }
+            unsafe { &mut *(base as *mut VirtIOHeader) }
         ).unwrap()))
     }
 }
@@ -1,4 +1,4 @@
 mod block;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
 
@@ -1,12 +1,25 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0 = hart id, which stays in tp
+    li t0, MAX_HARTS
+    bgeu a0, t0, park
+    mv tp, a0
+    # each hart gets a boot stack of its own
+    addi t0, a0, 1
+    slli t0, t0, 16
+    la sp, boot_stack
+    add sp, sp, t0
     call rust_main
 
+park:
+    wfi
+    j park
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    # 64KiB for each of the MAX_HARTS harts
+    .space 4096 * 16 * MAX_HARTS
     .globl boot_stack_top
 boot_stack_top:
@@ -0,0 +1,315 @@
+//! devfs names the devices, so that they can be opened like any file,
+//! e.g. as the target of a shell redirection. Opening one of its nodes
+//! gives the device itself rather than a file of the filesystem.
+
+use super::vfs::{VfsInode, FileSystem};
+use super::{File, SeekFrom, ROOT_EFS};
+use super::stdio::getchar;
+use crate::console::print_bytes;
+use crate::mm::UserBuffer;
+use crate::timer::get_time;
+use alloc::sync::Arc;
+use alloc::string::String;
+use core::any::Any;
+use easy_fs::{Stat, StatMode, BLOCK_SZ};
+use lazy_static::*;
+use spin::Mutex;
+
+const DEV_DEV: u64 = 2;
+
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        Arc::new(DevInode::Root)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum DeviceKind {
+    /// Reads nothing, swallows everything.
+    Null,
+    /// Reads zeros, swallows everything.
+    Zero,
+    /// Reads pseudo-random bytes; what is written is stirred into them.
+    Random,
+    /// The SBI console, like stdin and stdout together.
+    Console,
+    /// The disk easy-fs is on, block by block.
+    Disk,
+}
+
+const DEVICES: [(&str, DeviceKind); 5] = [
+    ("null", DeviceKind::Null),
+    ("zero", DeviceKind::Zero),
+    ("random", DeviceKind::Random),
+    ("console", DeviceKind::Console),
+    ("vda", DeviceKind::Disk),
+];
+
+pub enum DevInode {
+    Root,
+    Device(usize),
+}
+
+impl VfsInode for DevInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn stat(&self) -> Stat {
+        match self {
+            DevInode::Root => Stat::new(DEV_DEV, 0, StatMode::DIR, 2),
+            DevInode::Device(idx) => Stat::new(DEV_DEV, *idx as u64 + 1, StatMode::CHR, 1),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            DevInode::Device(idx) if DEVICES[*idx].1 == DeviceKind::Disk => disk_size(),
+            _ => 0,
+        }
+    }
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        match self {
+            DevInode::Root => {
+                let idx = DEVICES.iter().position(|(device, _)| *device == name)?;
+                Some(Arc::new(DevInode::Device(idx)))
+            }
+            DevInode::Device(_) => None,
+        }
+    }
+
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        match self {
+            DevInode::Root => {
+                let (name, _) = DEVICES.get(offset)?;
+                Some((String::from(*name), offset as u32 + 1, StatMode::CHR, offset + 1))
+            }
+            DevInode::Device(_) => None,
+        }
+    }
+
+    fn open_device(&self, readable: bool, writable: bool) -> Option<Arc<dyn File + Send + Sync>> {
+        match self {
+            DevInode::Device(idx) => Some(Arc::new(Device {
+                kind: DEVICES[*idx].1,
+                ino: *idx as u64 + 1,
+                readable,
+                writable,
+                offset: Mutex::new(0),
+            })),
+            DevInode::Root => None,
+        }
+    }
+}
+
+/// An opened device. Only the disk makes use of the offset.
+pub struct Device {
+    kind: DeviceKind,
+    ino: u64,
+    readable: bool,
+    writable: bool,
+    offset: Mutex<usize>,
+}
+
+lazy_static! {
+    static ref RANDOM_STATE: Mutex<u64> = Mutex::new(get_time() as u64 | 1);
+}
+
+/// The next number of a xorshift64* generator.
+fn next_random() -> u64 {
+    let mut state = RANDOM_STATE.lock();
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+fn stir_random(byte: u8) {
+    let mut state = RANDOM_STATE.lock();
+    *state = state.rotate_left(8) ^ byte as u64;
+    // the generator must not get stuck at zero
+    if *state == 0 {
+        *state = 1;
+    }
+}
+
+fn disk_size() -> usize {
+    ROOT_EFS.lock().total_blocks() as usize * BLOCK_SZ
+}
+
+fn read_disk(mut offset: usize, buf: UserBuffer) -> usize {
+    let efs = ROOT_EFS.lock();
+    let size = efs.total_blocks() as usize * BLOCK_SZ;
+    let mut block = [0u8; BLOCK_SZ];
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers {
+        let mut pos = 0;
+        while pos < slice.len() && offset < size {
+            efs.read_raw_block((offset / BLOCK_SZ) as u32, &mut block);
+            let block_offset = offset % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(slice.len() - pos);
+            slice[pos..pos + len].copy_from_slice(&block[block_offset..block_offset + len]);
+            pos += len;
+            offset += len;
+            total_read_size += len;
+        }
+    }
+    total_read_size
+}
+
+fn write_disk(mut offset: usize, buf: UserBuffer) -> usize {
+    let efs = ROOT_EFS.lock();
+    let size = efs.total_blocks() as usize * BLOCK_SZ;
+    let mut block = [0u8; BLOCK_SZ];
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers {
+        let mut pos = 0;
+        while pos < slice.len() && offset < size {
+            let block_id = (offset / BLOCK_SZ) as u32;
+            let block_offset = offset % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(slice.len() - pos);
+            // keep the rest of a block written in part
+            if len < BLOCK_SZ {
+                efs.read_raw_block(block_id, &mut block);
+            }
+            block[block_offset..block_offset + len].copy_from_slice(&slice[pos..pos + len]);
+            efs.write_raw_block(block_id, &block);
+            pos += len;
+            offset += len;
+            total_write_size += len;
+        }
+    }
+    total_write_size
+}
+
+impl Device {
+    fn read_from(&self, offset: usize, buf: UserBuffer) -> usize {
+        match self.kind {
+            DeviceKind::Null => 0,
+            DeviceKind::Zero => {
+                let len = buf.len();
+                for byte in buf {
+                    unsafe { byte.write_volatile(0); }
+                }
+                len
+            }
+            DeviceKind::Random => {
+                let len = buf.len();
+                for byte in buf {
+                    unsafe { byte.write_volatile(next_random() as u8); }
+                }
+                len
+            }
+            // whatever has been typed, one character at a time like a tty
+            DeviceKind::Console => {
+                if buf.len() == 0 {
+                    return 0;
+                }
+                match getchar() {
+                    Some(ch) => {
+                        unsafe { buf.into_iter().next().unwrap().write_volatile(ch); }
+                        1
+                    }
+                    None => 0,
+                }
+            }
+            DeviceKind::Disk => read_disk(offset, buf),
+        }
+    }
+
+    fn write_to(&self, offset: usize, buf: UserBuffer) -> usize {
+        match self.kind {
+            DeviceKind::Null | DeviceKind::Zero => buf.len(),
+            DeviceKind::Random => {
+                let len = buf.len();
+                for byte in buf {
+                    stir_random(unsafe { *byte });
+                }
+                len
+            }
+            DeviceKind::Console => {
+                for slice in buf.buffers.iter() {
+                    print_bytes(*slice);
+                }
+                buf.len()
+            }
+            DeviceKind::Disk => write_disk(offset, buf),
+        }
+    }
+}
+
+impl File for Device {
+    fn readable(&self) -> bool { self.readable }
+    fn writable(&self) -> bool { self.writable }
+
+    // the console blocks for input, so the offset is only locked where used
+    fn read(&self, buf: UserBuffer) -> usize {
+        if self.kind != DeviceKind::Disk {
+            return self.read_from(0, buf);
+        }
+        let mut offset = self.offset.lock();
+        let read_size = self.read_from(*offset, buf);
+        *offset += read_size;
+        read_size
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        if self.kind != DeviceKind::Disk {
+            return self.write_to(0, buf);
+        }
+        let mut offset = self.offset.lock();
+        let write_size = self.write_to(*offset, buf);
+        *offset += write_size;
+        write_size
+    }
+
+    fn fstat(&self) -> Option<Stat> {
+        Some(Stat::new(DEV_DEV, self.ino, StatMode::CHR, 1))
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        if self.kind != DeviceKind::Disk {
+            return None;
+        }
+        let mut offset = self.offset.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => offset as isize,
+            SeekFrom::Current(delta) => *offset as isize + delta,
+            SeekFrom::End(delta) => disk_size() as isize + delta,
+        };
+        if new_offset < 0 {
+            return None;
+        }
+        *offset = new_offset as usize;
+        Some(*offset)
+    }
+
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        match self.kind {
+            DeviceKind::Disk => Some(read_disk(offset, buf)),
+            _ => None,
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        match self.kind {
+            DeviceKind::Disk => Some(write_disk(offset, buf)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::vfs::{VfsInode, FileSystem};
+use alloc::sync::Arc;
+use alloc::string::String;
+use core::any::Any;
+use easy_fs::{EasyFileSystem, Inode, Stat, StatMode};
+use spin::Mutex;
+
+/// easy-fs on a block device, the root filesystem.
+pub struct EasyFs {
+    efs: Arc<Mutex<EasyFileSystem>>,
+}
+
+impl EasyFs {
+    pub fn new(efs: Arc<Mutex<EasyFileSystem>>) -> Self {
+        Self { efs }
+    }
+}
+
+impl FileSystem for EasyFs {
+    fn fs_type(&self) -> &'static str {
+        "easyfs"
+    }
+
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        Arc::new(EasyFileSystem::root_inode(&self.efs))
+    }
+}
+
+impl VfsInode for Inode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn stat(&self) -> Stat {
+        Inode::stat(self)
+    }
+
+    fn size(&self) -> usize {
+        Inode::size(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        Inode::write_at(self, offset, buf)
+    }
+
+    fn clear(&self) {
+        Inode::clear(self)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode: Arc<dyn VfsInode> = self.find(name)?;
+        Some(inode)
+    }
+
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode: Arc<dyn VfsInode> = Inode::create(self, name)?;
+        Some(inode)
+    }
+
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode: Arc<dyn VfsInode> = Inode::mkdir(self, name)?;
+        Some(inode)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn VfsInode>) -> Result<(), ()> {
+        let inode = inode.as_any().downcast_ref::<Inode>().ok_or(())?;
+        Inode::link(self, name, inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), ()> {
+        Inode::unlink(self, name)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), ()> {
+        Inode::rmdir(self, name)
+    }
+
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        Inode::read_dir(self, offset)
+    }
+
+    fn is_dir(&self) -> bool {
+        Inode::is_dir(self)
+    }
+
+    fn is_file(&self) -> bool {
+        Inode::is_file(self)
+    }
+}
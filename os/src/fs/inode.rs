@@ -1,37 +1,45 @@
-use easy_fs::{
-    EasyFileSystem,
-    Inode,
-    Stat,
-};
-use crate::drivers::BLOCK_DEVICE;
+use easy_fs::{Stat, StatMode};
 use alloc::sync::Arc;
-use lazy_static::*;
 use bitflags::*;
 use alloc::vec::Vec;
+use alloc::string::String;
 use spin::Mutex;
-use super::File;
+use super::{File, SeekFrom};
+use super::vfs::{
+    VfsInode,
+    lookup_path,
+    split_path,
+    normalize_path,
+    is_mount_point,
+    is_read_only,
+    same_mount,
+};
 use crate::mm::UserBuffer;
 
+/// A file or directory of a mounted filesystem, opened at `path`.
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    path: String,
     inner: Mutex<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
+    inode: Arc<dyn VfsInode>,
 }
 
 impl OSInode {
     pub fn new(
         readable: bool,
         writable: bool,
-        inode: Arc<Inode>,
+        path: String,
+        inode: Arc<dyn VfsInode>,
     ) -> Self {
         Self {
             readable,
             writable,
+            path,
             inner: Mutex::new(OSInodeInner {
                 offset: 0,
                 inode,
@@ -55,17 +63,13 @@ impl OSInode {
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
-}
-
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    let root = lookup_path("/").unwrap();
+    let mut offset = 0;
+    while let Some((name, _, _, next)) = root.read_dir(offset) {
+        println!("{}", name);
+        offset = next;
     }
     println!("**************/");
 }
@@ -92,83 +96,215 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Resolve `path` from the directory at the absolute path `base`.
+pub fn find_inode(base: &str, path: &str) -> Option<Arc<dyn VfsInode>> {
+    lookup_path(normalize_path(base, path).as_str())
+}
+
+/// The directory `path` is in, resolved from `base`, with the absolute
+/// path of the entry and its name.
+fn find_parent(base: &str, path: &str) -> Option<(Arc<dyn VfsInode>, String, String)> {
+    let abs_path = normalize_path(base, path);
+    if abs_path == "/" {
+        return None;
+    }
+    let (parent_path, name) = split_path(abs_path.as_str());
+    let name = String::from(name);
+    let parent = lookup_path(parent_path)?;
+    if parent.is_dir() {
+        Some((parent, abs_path, name))
+    } else {
+        None
+    }
+}
+
+pub fn open_file(base: &str, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
+    let abs_path = normalize_path(base, path);
+    if (writable || flags.contains(OpenFlags::CREATE) || flags.contains(OpenFlags::TRUNC))
+        && is_read_only(abs_path.as_str()) {
+        return None;
+    }
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = lookup_path(abs_path.as_str()) {
+            if inode.is_dir() {
+                return None;
+            }
             inode.clear();
             Some(Arc::new(OSInode::new(
                 readable,
                 writable,
+                abs_path,
                 inode,
             )))
         } else {
-            ROOT_INODE.create(name)
+            let (parent, abs_path, name) = find_parent(base, path)?;
+            parent.create(name.as_str())
                 .map(|inode| {
                     Arc::new(OSInode::new(
                         readable,
                         writable,
+                        abs_path,
                         inode,
                     ))
                 })
         }
     } else {
-        ROOT_INODE.find(name)
-            .map(|inode| {
+        lookup_path(abs_path.as_str())
+            .and_then(|inode| {
+                if inode.is_dir() {
+                    // Directories can only be opened to be used as dirfds
+                    if writable || flags.contains(OpenFlags::TRUNC) {
+                        return None;
+                    }
+                    return Some(Arc::new(OSInode::new(false, false, abs_path, inode)));
+                }
                 if flags.contains(OpenFlags::TRUNC) {
                     inode.clear();
                 }
-                Arc::new(OSInode::new(
+                Some(Arc::new(OSInode::new(
                     readable,
                     writable,
+                    abs_path,
                     inode,
-                ))
+                )))
             })
     }
 }
 
-pub fn link_file(old_name: &str, new_name: &str) -> Result<(), ()> {
-    ROOT_INODE.link(old_name, new_name)
+/// Open `path` for the `open` syscall, which gets the device itself for
+/// the node of a device.
+pub fn open_node(base: &str, path: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    let file = open_file(base, path, flags)?;
+    let inode = file.inner.lock().inode.clone();
+    match inode.open_device(file.readable, file.writable) {
+        Some(device) => Some(device),
+        None => Some(file),
+    }
 }
 
-pub fn unlink_file(name: &str) -> Result<(), ()> {
-    ROOT_INODE.unlink(name)
+pub fn link_file(
+    old_base: &str,
+    old_path: &str,
+    new_base: &str,
+    new_path: &str,
+) -> Result<(), ()> {
+    let old_path = normalize_path(old_base, old_path);
+    let inode = lookup_path(old_path.as_str()).ok_or(())?;
+    let (parent, new_path, name) = find_parent(new_base, new_path).ok_or(())?;
+    if !same_mount(old_path.as_str(), new_path.as_str()) || is_read_only(new_path.as_str()) {
+        return Err(());
+    }
+    parent.link(name.as_str(), &inode)
+}
+
+pub fn unlink_file(base: &str, path: &str) -> Result<(), ()> {
+    let (parent, abs_path, name) = find_parent(base, path).ok_or(())?;
+    if is_read_only(abs_path.as_str()) {
+        return Err(());
+    }
+    parent.unlink(name.as_str())
+}
+
+pub fn make_dir(base: &str, path: &str) -> Result<(), ()> {
+    let (parent, abs_path, name) = find_parent(base, path).ok_or(())?;
+    if is_read_only(abs_path.as_str()) {
+        return Err(());
+    }
+    parent.mkdir(name.as_str()).map(|_| ()).ok_or(())
+}
+
+pub fn remove_dir(base: &str, path: &str) -> Result<(), ()> {
+    let (parent, abs_path, name) = find_parent(base, path).ok_or(())?;
+    // something is mounted on it
+    if is_mount_point(abs_path.as_str()) || is_read_only(abs_path.as_str()) {
+        return Err(());
+    }
+    parent.rmdir(name.as_str())
+}
+
+fn read_from(inode: &Arc<dyn VfsInode>, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, *slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    total_read_size
+}
+
+fn write_to(inode: &Arc<dyn VfsInode>, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = inode.write_at(offset, *slice);
+        offset += write_size;
+        total_write_size += write_size;
+        // the filesystem is full
+        if write_size < slice.len() {
+            break;
+        }
+    }
+    total_write_size
 }
 
 impl File for OSInode {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
 
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.lock();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
-            }
-            inner.offset += read_size;
-            total_read_size += read_size;
-        }
-        total_read_size
+        let read_size = read_from(&inner.inode, inner.offset, buf);
+        inner.offset += read_size;
+        read_size
     }
 
     fn write(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.lock();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
-        }
-        total_write_size
+        let write_size = write_to(&inner.inode, inner.offset, buf);
+        inner.offset += write_size;
+        write_size
     }
 
     fn fstat(&self) -> Option<Stat> {
-        let inner = self.inner.lock();
-        let inode = inner.inode.clone();
-        drop(inner);
-        Some(ROOT_INODE.get_stat(inode))
+        Some(self.inner.lock().inode.stat())
+    }
+
+    fn inode(&self) -> Option<Arc<dyn VfsInode>> {
+        Some(self.inner.lock().inode.clone())
+    }
+
+    fn path(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => offset as isize,
+            SeekFrom::Current(offset) => inner.offset as isize + offset,
+            SeekFrom::End(offset) => inner.inode.size() as isize + offset,
+        };
+        if new_offset < 0 {
+            return None;
+        }
+        inner.offset = new_offset as usize;
+        Some(inner.offset)
+    }
+
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        let inode = self.inner.lock().inode.clone();
+        Some(read_from(&inode, offset, buf))
+    }
+
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        let inode = self.inner.lock().inode.clone();
+        Some(write_to(&inode, offset, buf))
+    }
+
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        self.inner.lock().inode.read_dir(offset)
     }
 }
@@ -1,19 +1,111 @@
+mod devfs;
+mod efs;
 mod inode;
 mod mailbox;
 mod pipe;
+mod procfs;
 mod stdio;
+mod tmpfs;
+mod vfs;
 
 use crate::mm::UserBuffer;
-use easy_fs::Stat;
+use crate::drivers::BLOCK_DEVICE;
+use alloc::sync::Arc;
+use alloc::string::String;
+use easy_fs::{EasyFileSystem, Stat, StatMode};
+use lazy_static::*;
+use spin::Mutex;
+use devfs::DevFs;
+use efs::EasyFs;
+use procfs::ProcFs;
+use tmpfs::TmpFs;
+
 pub trait File : Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
     fn fstat(&self) -> Option<Stat>;
+    /// The inode behind the file, for files of a mounted filesystem.
+    fn inode(&self) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Move the file offset. Files without an offset, such as pipes
+    /// and stdio, return `None`, as do positioned reads and writes.
+    fn seek(&self, _pos: SeekFrom) -> Option<usize> {
+        None
+    }
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// The first entry of a directory at or after `offset`, as `(name,
+    /// inode id, mode)` plus the offset after it.
+    fn read_dir(&self, _offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        None
+    }
+    /// The absolute path the file was opened at, for files of a mounted
+    /// filesystem.
+    fn path(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
 }
 
-pub use inode::{OSInode, open_file, link_file, unlink_file, OpenFlags, list_apps};
+pub use inode::{
+    OSInode,
+    find_inode,
+    open_file,
+    open_node,
+    link_file,
+    unlink_file,
+    make_dir,
+    remove_dir,
+    OpenFlags,
+    list_apps,
+};
 pub use mailbox::{Mailbox, MAX_MAIL_LEN, find_mailbox, remove_mailbox};
 pub use pipe::{Pipe, make_pipe};
-pub use stdio::{Stdin, Stdout};
+pub use stdio::{Stdin, Stdout, poll_stdin};
+pub use vfs::{VfsInode, FileSystem, mount, umount, normalize_path};
+
+lazy_static! {
+    /// easy-fs on the disk, which /dev/vda reads through as well.
+    static ref ROOT_EFS: Arc<Mutex<EasyFileSystem>> = EasyFileSystem::open(BLOCK_DEVICE.clone());
+}
+
+/// Mount easy-fs from the disk at / and the other filesystems where they
+/// belong.
+pub fn init() {
+    mount("/", Arc::new(EasyFs::new(ROOT_EFS.clone()))).unwrap();
+    mount_at_boot("/proc", Arc::new(ProcFs));
+    mount_at_boot("/tmp", Arc::new(TmpFs::new()));
+    mount_at_boot("/dev", Arc::new(DevFs));
+}
+
+fn mount_at_boot(path: &str, fs: Arc<dyn FileSystem>) {
+    // an image packed without the mount point gets it on the first boot
+    if find_inode("/", path).is_none() {
+        make_dir("/", path).unwrap();
+    }
+    mount(path, fs).unwrap();
+}
+
+/// A new filesystem of type `fs_type`, for the `mount` syscall. The disk
+/// is mounted at / already, so easy-fs is not among them.
+pub fn create_fs(fs_type: &str) -> Option<Arc<dyn FileSystem>> {
+    match fs_type {
+        "proc" => Some(Arc::new(ProcFs)),
+        "tmpfs" => Some(Arc::new(TmpFs::new())),
+        "devfs" => Some(Arc::new(DevFs)),
+        _ => None,
+    }
+}
@@ -4,7 +4,7 @@ use spin::Mutex;
 use crate::mm::{
     UserBuffer,
 };
-use crate::task::suspend_current_and_run_next;
+use crate::task::{block_current_and_run_next, WaitQueue};
 use easy_fs::Stat;
 
 pub struct Pipe {
@@ -46,6 +46,8 @@ pub struct PipeRingBuffer {
     tail: usize,
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>,
+    read_queue: WaitQueue,
+    write_queue: WaitQueue,
 }
 
 impl PipeRingBuffer {
@@ -56,6 +58,8 @@ impl PipeRingBuffer {
             tail: 0,
             status: RingBufferStatus::EMPTY,
             write_end: None,
+            read_queue: WaitQueue::new(),
+            write_queue: WaitQueue::new(),
         }
     }
 
@@ -108,6 +112,16 @@ impl PipeRingBuffer {
 }
 
 
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // the readers see the end of the pipe once the write end is gone
+        if self.writable {
+            self.buffer.lock().read_queue.wake_all();
+        }
+    }
+}
+
+
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
     let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
     let read_end = Arc::new(
@@ -136,10 +150,16 @@ impl File for Pipe {
                 if ring_buffer.all_write_ends_closed() {
                     return read_size;
                 }
+                ring_buffer.read_queue.add_current();
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                if block_current_and_run_next() {
+                    self.buffer.lock().read_queue.remove_current();
+                    return read_size;
+                }
                 continue;
             }
+            // there is room for the writers once we are done
+            ring_buffer.write_queue.wake_all();
             for _ in 0..loop_read {
                 if let Some(byte_ref) = buf_iter.next() {
                     unsafe { *byte_ref = ring_buffer.read_byte(); }
@@ -159,10 +179,15 @@ impl File for Pipe {
             let mut ring_buffer = self.buffer.lock();
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
+                ring_buffer.write_queue.add_current();
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                if block_current_and_run_next() {
+                    self.buffer.lock().write_queue.remove_current();
+                    return write_size;
+                }
                 continue;
             }
+            ring_buffer.read_queue.wake_all();
             for _ in 0..loop_write {
                 if let Some(byte_ref) = buf_iter.next() {
                     ring_buffer.write_byte(unsafe { *byte_ref });
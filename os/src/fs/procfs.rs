@@ -0,0 +1,260 @@
+//! A read-only view of kernel state. Every file is a text snapshot taken
+//! when it is opened:
+//!
+//! - `meminfo`: free frames and swap slots
+//! - `schedstat`: the scheduling policy and its counters
+//! - `mounts`: the mounted filesystems
+//! - `<pid>/status`: the state of the process and of each of its threads
+//! - `<pid>/maps`: the areas of its address space
+//! - `<pid>/fd`: its open files
+//!
+//! `self` stands for the pid of the process looking. It is mounted at
+//! `/proc` at boot.
+
+use super::vfs::{VfsInode, FileSystem, list_mounts};
+use crate::mm::{frame_stats, swap_stats};
+use crate::task::{
+    ProcessControlBlock,
+    TaskStatus,
+    current_process,
+    pid2process,
+    list_pids,
+    sched_stat,
+    describe_ready_task,
+};
+use crate::config::PAGE_SIZE;
+use crate::timer::to_clock_ticks;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::string::{String, ToString};
+use core::any::Any;
+use core::fmt::Write;
+use easy_fs::{Stat, StatMode};
+
+/// Set apart from the inode numbers of easy-fs in what `fstat` returns.
+const PROC_DEV: u64 = 1;
+
+const PID_ENTRIES: [&str; 3] = ["status", "maps", "fd"];
+
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        Arc::new(ProcInode::Root(root_entries()))
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+/// A file or directory of /proc. Looking one up takes the snapshot it
+/// shows, so that reading it in pieces gives a consistent text.
+pub enum ProcInode {
+    /// The names, with whether each is a directory.
+    Root(Vec<(String, bool)>),
+    Process(usize),
+    Text(Vec<u8>),
+}
+
+impl VfsInode for ProcInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn stat(&self) -> Stat {
+        match self {
+            ProcInode::Text(_) => Stat::new(PROC_DEV, 0, StatMode::FILE, 1),
+            _ => Stat::new(PROC_DEV, 0, StatMode::DIR, 2),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ProcInode::Text(content) => content.len(),
+            _ => 0,
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let content = match self {
+            ProcInode::Text(content) if offset < content.len() => &content[offset..],
+            _ => return 0,
+        };
+        let len = content.len().min(buf.len());
+        buf[..len].copy_from_slice(&content[..len]);
+        len
+    }
+
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode = match self {
+            ProcInode::Root(_) => match name {
+                "meminfo" => ProcInode::Text(meminfo().into_bytes()),
+                "schedstat" => ProcInode::Text(schedstat().into_bytes()),
+                "mounts" => ProcInode::Text(mounts().into_bytes()),
+                "self" => ProcInode::Process(current_process().getpid()),
+                _ => {
+                    let pid = name.parse::<usize>().ok()?;
+                    pid2process(pid)?;
+                    ProcInode::Process(pid)
+                }
+            },
+            ProcInode::Process(pid) => {
+                let process = pid2process(*pid)?;
+                let text = match name {
+                    "status" => status(&process),
+                    "maps" => maps(&process),
+                    "fd" => fds(&process),
+                    _ => return None,
+                };
+                ProcInode::Text(text.into_bytes())
+            }
+            ProcInode::Text(_) => return None,
+        };
+        Some(Arc::new(inode))
+    }
+
+    /// The offset in a directory is the index of its next entry.
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        let (name, is_dir) = match self {
+            ProcInode::Root(entries) => entries.get(offset)?.clone(),
+            ProcInode::Process(_) => (PID_ENTRIES.get(offset)?.to_string(), false),
+            ProcInode::Text(_) => return None,
+        };
+        let mode = if is_dir { StatMode::DIR } else { StatMode::FILE };
+        Some((name, 0, mode, offset + 1))
+    }
+}
+
+fn root_entries() -> Vec<(String, bool)> {
+    let mut entries = Vec::new();
+    entries.push((String::from("meminfo"), false));
+    entries.push((String::from("schedstat"), false));
+    entries.push((String::from("mounts"), false));
+    entries.push((String::from("self"), true));
+    for pid in list_pids() {
+        entries.push((pid.to_string(), true));
+    }
+    entries
+}
+
+fn meminfo() -> String {
+    let (frames, free_frames) = frame_stats();
+    let (slots, free_slots) = swap_stats();
+    let mut text = String::new();
+    writeln!(text, "MemTotal: {} kB", frames * PAGE_SIZE / 1024).unwrap();
+    writeln!(text, "MemFree: {} kB", free_frames * PAGE_SIZE / 1024).unwrap();
+    writeln!(text, "SwapTotal: {} kB", slots * PAGE_SIZE / 1024).unwrap();
+    writeln!(text, "SwapFree: {} kB", free_slots * PAGE_SIZE / 1024).unwrap();
+    text
+}
+
+fn schedstat() -> String {
+    let stat = sched_stat();
+    let mut text = String::new();
+    writeln!(text, "policy: {}", stat.policy).unwrap();
+    writeln!(text, "ready: {}", stat.ready).unwrap();
+    writeln!(text, "switches: {}", stat.switches).unwrap();
+    text
+}
+
+fn mounts() -> String {
+    let mut text = String::new();
+    for (path, fs_type) in list_mounts() {
+        writeln!(text, "{} {}", fs_type, path).unwrap();
+    }
+    text
+}
+
+fn status(process: &Arc<ProcessControlBlock>) -> String {
+    let inner = process.acquire_inner_lock();
+    let ppid = inner.parent.as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid());
+    let state = if inner.is_zombie {
+        "zombie"
+    } else if inner.exiting {
+        "exiting"
+    } else if inner.frozen {
+        "stopped"
+    } else {
+        "alive"
+    };
+    let (utime, stime) = inner.cpu_times();
+    let mut text = String::new();
+    writeln!(text, "pid: {}", process.getpid()).unwrap();
+    writeln!(text, "ppid: {}", ppid).unwrap();
+    writeln!(text, "state: {}", state).unwrap();
+    writeln!(text, "threads: {}", inner.thread_count()).unwrap();
+    writeln!(text, "utime: {}", to_clock_ticks(utime)).unwrap();
+    writeln!(text, "stime: {}", to_clock_ticks(stime)).unwrap();
+    let tasks: Vec<_> = inner.tasks.iter()
+        .enumerate()
+        .filter_map(|(tid, task)| task.clone().map(|task| (tid, task)))
+        .collect();
+    drop(inner);
+
+    for (tid, task) in tasks {
+        let task_inner = task.acquire_inner_lock();
+        let task_status = task_inner.task_status;
+        let (utime, stime) = (task_inner.utime, task_inner.stime);
+        let sched = task_inner.describe_sched_block();
+        drop(task_inner);
+        // the block of a ready thread is with the scheduler
+        let sched = match sched {
+            Some(sched) => Some(sched),
+            None if task_status == TaskStatus::Ready => describe_ready_task(task.kernel_stack.0),
+            None => None,
+        };
+        writeln!(
+            text,
+            "tid {}: {:?} utime {} stime {} {}",
+            tid,
+            task_status,
+            to_clock_ticks(utime),
+            to_clock_ticks(stime),
+            sched.unwrap_or_default(),
+        ).unwrap();
+    }
+    text
+}
+
+fn maps(process: &Arc<ProcessControlBlock>) -> String {
+    process.acquire_inner_lock().memory_set.maps()
+}
+
+fn fds(process: &Arc<ProcessControlBlock>) -> String {
+    let inner = process.acquire_inner_lock();
+    let files: Vec<_> = inner.fd_table.iter()
+        .enumerate()
+        .filter_map(|(fd, file)| file.clone().map(|file| (fd, file)))
+        .collect();
+    drop(inner);
+
+    let mut text = String::new();
+    for (fd, file) in files {
+        let kind = match file.fstat() {
+            Some(stat) if stat.mode.contains(StatMode::DIR) => "dir",
+            Some(stat) if stat.mode.contains(StatMode::CHR) => "device",
+            Some(_) => "file",
+            None => "stream",
+        };
+        writeln!(
+            text,
+            "{}: {}{} {}",
+            fd,
+            if file.readable() { 'r' } else { '-' },
+            if file.writable() { 'w' } else { '-' },
+            kind,
+        ).unwrap();
+    }
+    text
+}
@@ -1,11 +1,63 @@
 use super::File;
 use crate::mm::{UserBuffer};
 use crate::sbi::console_getchar;
-use crate::task::suspend_current_and_run_next;
+use crate::task::{block_current_and_run_next, WaitQueue};
+use alloc::collections::VecDeque;
+use spin::Mutex;
+use lazy_static::*;
 use easy_fs::Stat;
 
 pub struct Stdin;
 
+/// Characters read from the console on behalf of the threads blocked on
+/// stdin, which have no interrupt to wake them up.
+struct StdinBuffer {
+    chars: VecDeque<u8>,
+    wait_queue: WaitQueue,
+}
+
+lazy_static! {
+    static ref STDIN_BUFFER: Mutex<StdinBuffer> = Mutex::new(StdinBuffer {
+        chars: VecDeque::new(),
+        wait_queue: WaitQueue::new(),
+    });
+}
+
+/// Check the console for a thread blocked on stdin. Called on every timer
+/// tick and while there is nothing else to run.
+pub fn poll_stdin() {
+    let mut stdin_buffer = STDIN_BUFFER.lock();
+    if stdin_buffer.wait_queue.is_empty() {
+        return;
+    }
+    let c = console_getchar();
+    if c != 0 {
+        stdin_buffer.chars.push_back(c as u8);
+        stdin_buffer.wait_queue.wake_one();
+    }
+}
+
+/// Wait for a character from the console. Returns None if a signal comes
+/// first.
+pub fn getchar() -> Option<u8> {
+    loop {
+        let mut stdin_buffer = STDIN_BUFFER.lock();
+        if let Some(ch) = stdin_buffer.chars.pop_front() {
+            return Some(ch);
+        }
+        let c = console_getchar();
+        if c != 0 {
+            return Some(c as u8);
+        }
+        stdin_buffer.wait_queue.add_current();
+        drop(stdin_buffer);
+        if block_current_and_run_next() {
+            STDIN_BUFFER.lock().wait_queue.remove_current();
+            return None;
+        }
+    }
+}
+
 pub struct Stdout;
 
 impl File for Stdin {
@@ -14,19 +66,13 @@ impl File for Stdin {
 
     fn read(&self, mut user_buf: UserBuffer) -> usize {
         assert_eq!(user_buf.len(), 1);
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
+        match getchar() {
+            Some(ch) => {
+                unsafe { user_buf.buffers[0].as_mut_ptr().write_volatile(ch); }
+                1
             }
+            None => 0,
         }
-        let ch = c as u8;
-        unsafe { user_buf.buffers[0].as_mut_ptr().write_volatile(ch); }
-        1
     }
 
     fn write(&self, _user_buf: UserBuffer) -> usize {
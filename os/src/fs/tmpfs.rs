@@ -0,0 +1,283 @@
+//! tmpfs keeps its files and directories in memory, the contents of each
+//! file in frames of its own, for scratch data that need not go through
+//! the block cache to the disk.
+
+use super::vfs::{VfsInode, FileSystem};
+use crate::config::PAGE_SIZE;
+use crate::mm::{FrameTracker, frame_alloc};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::string::String;
+use core::any::Any;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use easy_fs::{Stat, StatMode};
+use spin::Mutex;
+
+/// easy-fs is device 0, /proc device 1 and /dev device 2; each tmpfs gets
+/// one of its own.
+static NEXT_DEV: AtomicU64 = AtomicU64::new(3);
+
+const ROOT_INO: u32 = 1;
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        let dev = NEXT_DEV.fetch_add(1, Ordering::Relaxed);
+        let next_ino = Arc::new(AtomicU32::new(ROOT_INO));
+        Self {
+            root: TmpInode::new(dev, next_ino, Content::Dir(Vec::new())),
+        }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+}
+
+/// A file or directory of a tmpfs. Directories own their entries, so a
+/// file lives, and keeps its frames, until it is unlinked from the last
+/// of them and closed everywhere.
+pub struct TmpInode {
+    dev: u64,
+    ino: u32,
+    next_ino: Arc<AtomicU32>,
+    inner: Mutex<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    nlink: u32,
+    content: Content,
+}
+
+enum Content {
+    File {
+        size: usize,
+        frames: Vec<FrameTracker>,
+    },
+    Dir(Vec<(String, Arc<TmpInode>)>),
+}
+
+impl TmpInode {
+    fn new(dev: u64, next_ino: Arc<AtomicU32>, content: Content) -> Arc<Self> {
+        let ino = next_ino.fetch_add(1, Ordering::Relaxed);
+        let nlink = match content {
+            Content::File { .. } => 1,
+            Content::Dir(_) => 2,
+        };
+        Arc::new(Self {
+            dev,
+            ino,
+            next_ino,
+            inner: Mutex::new(TmpInodeInner { nlink, content }),
+        })
+    }
+
+    /// Add a new file or directory called `name` to this directory.
+    fn add(&self, name: &str, content: Content) -> Option<Arc<TmpInode>> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        let is_dir = matches!(content, Content::Dir(_));
+        let entries = match &mut inner.content {
+            Content::Dir(entries) => entries,
+            _ => return None,
+        };
+        if entries.iter().any(|(entry, _)| entry == name) {
+            return None;
+        }
+        let inode = TmpInode::new(self.dev, self.next_ino.clone(), content);
+        entries.push((String::from(name), inode.clone()));
+        // ".." of the new directory
+        if is_dir {
+            inner.nlink += 1;
+        }
+        Some(inode)
+    }
+
+    /// Take the entry `name` out of this directory if `removable` agrees
+    /// to what it refers to.
+    fn remove(&self, name: &str, removable: impl Fn(&TmpInodeInner) -> bool) -> Result<Arc<TmpInode>, ()> {
+        let mut inner = self.inner.lock();
+        let entries = match &mut inner.content {
+            Content::Dir(entries) => entries,
+            _ => return Err(()),
+        };
+        let idx = entries.iter().position(|(entry, _)| entry == name).ok_or(())?;
+        if !removable(&entries[idx].1.inner.lock()) {
+            return Err(());
+        }
+        let (_, inode) = entries.remove(idx);
+        Ok(inode)
+    }
+}
+
+impl VfsInode for TmpInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn stat(&self) -> Stat {
+        let inner = self.inner.lock();
+        let mode = match inner.content {
+            Content::File { .. } => StatMode::FILE,
+            Content::Dir(_) => StatMode::DIR,
+        };
+        Stat::new(self.dev, self.ino as u64, mode, inner.nlink)
+    }
+
+    fn size(&self) -> usize {
+        match self.inner.lock().content {
+            Content::File { size, .. } => size,
+            Content::Dir(_) => 0,
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.lock();
+        let (size, frames) = match &inner.content {
+            Content::File { size, frames } => (*size, frames),
+            Content::Dir(_) => return 0,
+        };
+        let end = size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page = frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&page[page_offset..page_offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let (size, frames) = match &mut inner.content {
+            Content::File { size, frames } => (size, frames),
+            Content::Dir(_) => return 0,
+        };
+        let mut end = offset + buf.len();
+        while frames.len() * PAGE_SIZE < end {
+            match frame_alloc() {
+                Some(frame) => frames.push(frame),
+                // out of memory, write what fits
+                None => {
+                    end = frames.len() * PAGE_SIZE;
+                    break;
+                }
+            }
+        }
+        if offset >= end {
+            return 0;
+        }
+        let mut pos = offset;
+        while pos < end {
+            let page = frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            page[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if end > *size {
+            *size = end;
+        }
+        end - offset
+    }
+
+    fn clear(&self) {
+        if let Content::File { size, frames } = &mut self.inner.lock().content {
+            *size = 0;
+            frames.clear();
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        match &self.inner.lock().content {
+            Content::Dir(entries) => {
+                let inode: Arc<dyn VfsInode> = entries.iter()
+                    .find(|(entry, _)| entry == name)?
+                    .1.clone();
+                Some(inode)
+            }
+            Content::File { .. } => None,
+        }
+    }
+
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode: Arc<dyn VfsInode> = self.add(name, Content::File {
+            size: 0,
+            frames: Vec::new(),
+        })?;
+        Some(inode)
+    }
+
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode: Arc<dyn VfsInode> = self.add(name, Content::Dir(Vec::new()))?;
+        Some(inode)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn VfsInode>) -> Result<(), ()> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(());
+        }
+        // the new entry shares the Arc of the inode
+        let file = inode.clone().as_any_arc().downcast::<TmpInode>().map_err(|_| ())?;
+        if file.dev != self.dev || !file.is_file() {
+            return Err(());
+        }
+        let mut inner = self.inner.lock();
+        let entries = match &mut inner.content {
+            Content::Dir(entries) => entries,
+            _ => return Err(()),
+        };
+        if entries.iter().any(|(entry, _)| entry == name) {
+            return Err(());
+        }
+        file.inner.lock().nlink += 1;
+        entries.push((String::from(name), file));
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), ()> {
+        let file = self.remove(name, |inner| matches!(inner.content, Content::File { .. }))?;
+        // the frames go with the last reference, which may be an open file
+        file.inner.lock().nlink -= 1;
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), ()> {
+        self.remove(name, |inner| matches!(&inner.content, Content::Dir(entries) if entries.is_empty()))?;
+        self.inner.lock().nlink -= 1;
+        Ok(())
+    }
+
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)> {
+        let inner = self.inner.lock();
+        let entries = match &inner.content {
+            Content::Dir(entries) => entries,
+            Content::File { .. } => return None,
+        };
+        let (name, inode) = entries.get(offset)?;
+        let mode = match inode.inner.lock().content {
+            Content::File { .. } => StatMode::FILE,
+            Content::Dir(_) => StatMode::DIR,
+        };
+        Some((name.clone(), inode.ino, mode, offset + 1))
+    }
+}
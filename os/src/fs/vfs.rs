@@ -0,0 +1,205 @@
+//! The layer between the syscalls and the filesystems. Each filesystem is
+//! mounted at an absolute path and hands out its files and directories as
+//! `VfsInode`s; a path is looked up in the filesystem mounted closest
+//! above it.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::string::String;
+use core::any::Any;
+use super::File;
+use easy_fs::{Stat, StatMode};
+use lazy_static::*;
+use spin::Mutex;
+
+/// A file or directory of some filesystem. The operations on directories
+/// fail on files, and the other way around.
+pub trait VfsInode: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    /// `as_any` for the inode to be downcast keeping the reference.
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+    fn stat(&self) -> Stat;
+    fn size(&self) -> usize;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// The defaults of the methods that change the filesystem fail, for
+    /// those that cannot be written to.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Truncate the file to nothing.
+    fn clear(&self) {}
+    /// The entry called `name` in this directory.
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>>;
+    fn create(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn mkdir(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Add an entry called `name` for `inode`, which has to be a file of
+    /// the same filesystem.
+    fn link(&self, _name: &str, _inode: &Arc<dyn VfsInode>) -> Result<(), ()> {
+        Err(())
+    }
+    fn unlink(&self, _name: &str) -> Result<(), ()> {
+        Err(())
+    }
+    fn rmdir(&self, _name: &str) -> Result<(), ()> {
+        Err(())
+    }
+    /// The first entry at or after `offset`, as `(name, inode id, mode)`
+    /// plus the offset after it.
+    fn read_dir(&self, offset: usize) -> Option<(String, u32, StatMode, usize)>;
+
+    /// What opening it gives instead of a file of its filesystem, for the
+    /// nodes of devices.
+    fn open_device(&self, _readable: bool, _writable: bool) -> Option<Arc<dyn File + Send + Sync>> {
+        None
+    }
+
+    fn is_dir(&self) -> bool {
+        self.stat().mode.contains(StatMode::DIR)
+    }
+
+    fn is_file(&self) -> bool {
+        self.stat().mode.contains(StatMode::FILE)
+    }
+}
+
+/// What the mount table knows about a filesystem.
+pub trait FileSystem: Send + Sync {
+    fn fs_type(&self) -> &'static str;
+    fn root_inode(&self) -> Arc<dyn VfsInode>;
+
+    /// Nothing on it can be opened for writing.
+    fn read_only(&self) -> bool {
+        false
+    }
+}
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static! {
+    static ref MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+}
+
+/// Whether `path` is `dir` or below it, both absolute and normalized.
+fn is_under(path: &str, dir: &str) -> bool {
+    dir == "/" || path == dir || (path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/')
+}
+
+/// The filesystem mounted closest above `path`, and the rest of `path`
+/// below its root.
+fn find_mount(path: &str) -> Option<(Arc<dyn FileSystem>, &str)> {
+    let mounts = MOUNTS.lock();
+    let mount = mounts.iter()
+        .filter(|mount| is_under(path, mount.path.as_str()))
+        .max_by_key(|mount| mount.path.len())?;
+    let rest = if mount.path == "/" { path } else { &path[mount.path.len()..] };
+    Some((mount.fs.clone(), rest))
+}
+
+/// Mount `fs` at the absolute, normalized `path`, which has to be a
+/// directory that nothing is mounted at yet. The first mount is the root.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), ()> {
+    if path != "/" {
+        match lookup_path(path) {
+            Some(inode) if inode.is_dir() => {}
+            _ => return Err(()),
+        }
+    }
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) || (mounts.is_empty() != (path == "/")) {
+        return Err(());
+    }
+    mounts.push(Mount {
+        path: String::from(path),
+        fs,
+    });
+    Ok(())
+}
+
+/// Undo the mount at `path`. The root stays, as do filesystems with
+/// others mounted below them.
+pub fn umount(path: &str) -> Result<(), ()> {
+    let mut mounts = MOUNTS.lock();
+    if path == "/" || mounts.iter().any(|mount| mount.path != path && is_under(mount.path.as_str(), path)) {
+        return Err(());
+    }
+    let idx = mounts.iter().position(|mount| mount.path == path).ok_or(())?;
+    mounts.remove(idx);
+    Ok(())
+}
+
+/// The mount points with the type of what is mounted there, in the order
+/// they were mounted.
+pub fn list_mounts() -> Vec<(String, &'static str)> {
+    MOUNTS.lock().iter()
+        .map(|mount| (mount.path.clone(), mount.fs.fs_type()))
+        .collect()
+}
+
+pub fn is_mount_point(path: &str) -> bool {
+    MOUNTS.lock().iter().any(|mount| mount.path == path)
+}
+
+/// Whether `a` and `b` are on the same mounted filesystem.
+pub fn same_mount(a: &str, b: &str) -> bool {
+    match (find_mount(a), find_mount(b)) {
+        (Some((fs_a, _)), Some((fs_b, _))) => {
+            Arc::as_ptr(&fs_a) as *const u8 == Arc::as_ptr(&fs_b) as *const u8
+        }
+        _ => false,
+    }
+}
+
+/// Whether the filesystem `path` is on may be written to.
+pub fn is_read_only(path: &str) -> bool {
+    find_mount(path).map_or(true, |(fs, _)| fs.read_only())
+}
+
+/// The inode at the absolute, normalized `path`.
+pub fn lookup_path(path: &str) -> Option<Arc<dyn VfsInode>> {
+    let (fs, rest) = find_mount(path)?;
+    let mut inode = fs.root_inode();
+    for name in rest.split('/').filter(|name| !name.is_empty()) {
+        inode = inode.lookup(name)?;
+    }
+    Some(inode)
+}
+
+/// Split a path into its parent directory and the last component,
+/// e.g. "a/b/c" into ("a/b", "c") and "c" into ("", "c").
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(idx) => (&path[..idx + 1], &path[idx + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Turn `path` into an absolute path without "." or ".." components,
+/// taking `cwd` as the starting point of relative paths.
+pub fn normalize_path(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let start = if path.starts_with('/') { "" } else { cwd };
+    for name in start.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => { components.pop(); }
+            _ => components.push(name),
+        }
+    }
+    let mut normalized = String::new();
+    for name in components {
+        normalized.push('/');
+        normalized.push_str(name);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
@@ -8,11 +8,14 @@
 
 extern crate alloc;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 #[macro_use]
 extern crate bitflags;
 
 #[macro_use]
 mod console;
+#[macro_use]
 mod config;
 mod drivers;
 mod fs;
@@ -20,13 +23,15 @@ mod lang_items;
 mod logger;
 mod mm;
 mod sbi;
+mod smp;
+mod sync;
 mod syscall;
 mod task;
 mod timer;
 mod trap;
 
 
-global_asm!(include_str!("entry.asm"));
+global_asm!(concat!(".equ MAX_HARTS, ", max_harts!(), "\n", include_str!("entry.asm")));
 global_asm!(include_str!("link_app.S"));
 
 fn clear_bss() {
@@ -38,8 +43,14 @@ fn clear_bss() {
 }
 
 
+/// Set by the boot hart once the kernel is set up for the others.
+static BOOTED: AtomicBool = AtomicBool::new(false);
+
 #[no_mangle]
 pub fn rust_main() -> ! {
+    if BOOTED.load(Ordering::Acquire) {
+        other_hart_main();
+    }
     clear_bss();
     //println!("[kernel] Hello, world!");
     mm::init();
@@ -50,10 +61,25 @@ pub fn rust_main() -> ! {
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
+    fs::init();
     fs::list_apps();
     task::add_initproc();
+    smp::set_hart_started();
+    BOOTED.store(true, Ordering::Release);
+    smp::start_other_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
 
+/// Where the harts started by the boot hart go.
+fn other_hart_main() -> ! {
+    mm::init_other_hart();
+    trap::init();
+    trap::enable_timer_interrupt();
+    timer::set_next_trigger();
+    smp::set_hart_started();
+    task::run_tasks();
+    panic!("Unreachable in other_hart_main!");
+}
+
 
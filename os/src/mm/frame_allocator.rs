@@ -1,4 +1,5 @@
 use super::{PhysAddr, PhysPageNum};
+use super::swap::swap_out_one;
 use alloc::vec::Vec;
 use spin::Mutex;
 use crate::config::MEMORY_END;
@@ -39,6 +40,7 @@ trait FrameAllocator {
 }
 
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     recycled: Vec<usize>,
@@ -46,14 +48,21 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
     }
+
+    /// The number of frames in all and of those free.
+    pub fn stats(&self) -> (usize, usize) {
+        (self.end - self.start, self.end - self.current + self.recycled.len())
+    }
 }
 
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -104,10 +113,20 @@ pub fn init_frame_allocator() {
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .lock()
-        .alloc()
-        .map(|ppn| FrameTracker::new(ppn))
+    loop {
+        let ppn = FRAME_ALLOCATOR.lock().alloc();
+        if let Some(ppn) = ppn {
+            return Some(FrameTracker::new(ppn));
+        }
+        // out of memory: make room by swapping a user page out
+        if !swap_out_one() {
+            return None;
+        }
+    }
+}
+
+pub fn frame_stats() -> (usize, usize) {
+    FRAME_ALLOCATOR.lock().stats()
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
@@ -1,19 +1,21 @@
 use super::{PageTable, PageTableEntry, PTEFlags};
 use super::{VirtPageNum, VirtAddr, PhysPageNum, PhysAddr};
-use super::{FrameTracker, frame_alloc};
+use super::{Page, frame_alloc};
 use super::{VPNRange, StepByOne};
 use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use alloc::string::String;
+use core::fmt::Write;
 use riscv::register::satp;
 use alloc::sync::Arc;
+use crate::fs::VfsInode;
 use lazy_static::*;
 use spin::Mutex;
+use crate::smp::tlb_shootdown;
 use crate::config::{
     MEMORY_END,
     PAGE_SIZE,
     TRAMPOLINE,
-    TRAP_CONTEXT,
-    USER_STACK_SIZE,
     MMIO,
 };
 
@@ -43,6 +45,8 @@ pub fn kernel_token() -> usize {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    heap_bottom: usize,
+    brk: usize,
 }
 
 impl MemorySet {
@@ -50,6 +54,8 @@ impl MemorySet {
         Some(Self {
             page_table: PageTable::new()?,
             areas: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
         })
     }
 
@@ -66,17 +72,214 @@ impl MemorySet {
         ), None)
     }
 
+    /// Reserve `[start_va, end_va)` without backing it with frames yet;
+    /// each page gets a zeroed frame on its first access through
+    /// `handle_page_fault`. Fails if the range overlaps an existing area.
+    pub fn insert_lazy_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> Result<(), ()> {
+        let start_vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        }) {
+            return Err(());
+        }
+        self.push(MapArea::new(
+            start_va,
+            end_va,
+            MapType::Lazy,
+            permission,
+        ), None)
+    }
+
+    /// Map `[start_va, end_va)` to `inode` starting at `offset`. Pages are
+    /// read from the file on first access like a lazy area; with `shared`
+    /// set, dirty pages are written back on `msync`, `munmap` and exit.
+    pub fn insert_file_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        inode: Arc<dyn VfsInode>,
+        offset: usize,
+        shared: bool,
+    ) -> Result<(), ()> {
+        let start_vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        }) {
+            return Err(());
+        }
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Lazy,
+            permission,
+        );
+        map_area.backing = Some(FileBacking {
+            inode,
+            offset,
+            base_vpn: start_vpn,
+            shared,
+        });
+        self.push(map_area, None)
+    }
+
+    /// Write the dirty pages of shared file mappings in `[start_va, end_va)`
+    /// back to their files.
+    pub fn msync(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> Result<(), ()> {
+        if !self.is_area_mapped(start_va, end_va, MapPermission::U) {
+            return Err(());
+        }
+        let start_vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        for area in self.areas.iter() {
+            for vpn in area.vpn_range {
+                if start_vpn <= vpn && vpn < end_vpn {
+                    area.sync_one(&mut self.page_table, vpn);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve a fault on the page containing `va`: back it with a frame if
+    /// it lies in a lazy area and has not been touched yet, bring it back if
+    /// it has been swapped out, or, for a write, give it a private copy if
+    /// it is still shared copy-on-write after a fork. Returns false if there
+    /// is nothing to do, so the caller can treat the fault as a real one.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, write: bool) -> bool {
+        let vpn = va.floor();
+        let area = match self.areas.iter_mut().find(|area| {
+            area.map_type != MapType::Identical
+                && area.vpn_range.get_start() <= vpn
+                && vpn < area.vpn_range.get_end()
+        }) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.data_frames.contains_key(&vpn) {
+            if area.map_type != MapType::Lazy
+                || area.map_one(&mut self.page_table, vpn).is_err() {
+                return false;
+            }
+            area.make_swappable(vpn);
+            return true;
+        }
+        let page = &area.data_frames[&vpn];
+        if !page.is_resident() {
+            return page.with_frame(|_| ()).is_some();
+        }
+        if !write
+            || !area.map_perm.contains(MapPermission::W)
+            || self.page_table.translate(vpn).unwrap().writable() {
+            return false;
+        }
+        area.copy_on_write(&mut self.page_table, vpn).is_ok()
+    }
+
+    /// Whether every page of `[start_va, end_va)` belongs to some area
+    /// with all of `permission`, whether or not it is backed yet.
+    pub fn is_area_mapped(&self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> bool {
+        let end_vpn = end_va.ceil();
+        let mut vpn = start_va.floor();
+        while vpn < end_vpn {
+            if !self.areas.iter().any(|area| {
+                area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+                    && area.map_perm.contains(permission)
+            }) {
+                return false;
+            }
+            vpn.step();
+        }
+        true
+    }
+
+    /// Move the program break to `new_brk`, growing or shrinking the heap
+    /// area (which is backed lazily like an anonymous mmap) to match.
+    /// Returns the break after the call, which is left unchanged if
+    /// `new_brk` is below the heap or would run into another area.
+    pub fn set_brk(&mut self, new_brk: usize) -> usize {
+        if new_brk < self.heap_bottom {
+            return self.brk;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if self.areas.iter().any(|area| {
+            !area.heap
+                && area.vpn_range.get_start() < new_end
+                && heap_start < area.vpn_range.get_end()
+        }) {
+            return self.brk;
+        }
+        let heap = match self.areas.iter_mut().find(|area| area.heap) {
+            Some(area) => area,
+            None => return self.brk,
+        };
+        let mut vpn = new_end;
+        if vpn < heap.vpn_range.get_end() {
+            let mut pages = Vec::new();
+            while vpn < heap.vpn_range.get_end() {
+                pages.extend(heap.unmap_one(&mut self.page_table, vpn));
+                vpn.step();
+            }
+            tlb_shootdown();
+            drop(pages);
+        }
+        heap.vpn_range = VPNRange::new(heap_start, new_end);
+        self.brk = new_brk;
+        self.brk
+    }
+
+    /// One line for each area: its range, permissions, how it is backed
+    /// and the pages it has in memory or swapped out.
+    pub fn maps(&self) -> String {
+        let mut maps = String::new();
+        for area in self.areas.iter() {
+            let start: VirtAddr = area.vpn_range.get_start().into();
+            let end: VirtAddr = area.vpn_range.get_end().into();
+            let perm = area.map_perm;
+            let kind = match (&area.backing, area.map_type) {
+                (Some(backing), _) if backing.shared => "file shared",
+                (Some(_), _) => "file private",
+                (None, _) if area.heap => "heap",
+                (None, MapType::Lazy) => "anonymous",
+                (None, MapType::Framed) => "framed",
+                (None, MapType::Identical) => "identical",
+            };
+            writeln!(
+                maps,
+                "{:#x}-{:#x} {}{}{}{} {} {} pages",
+                usize::from(start),
+                usize::from(end),
+                if perm.contains(MapPermission::R) { 'r' } else { '-' },
+                if perm.contains(MapPermission::W) { 'w' } else { '-' },
+                if perm.contains(MapPermission::X) { 'x' } else { '-' },
+                if perm.contains(MapPermission::U) { 'u' } else { '-' },
+                kind,
+                area.data_frames.len(),
+            ).unwrap();
+        }
+        maps
+    }
+
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self.areas.iter_mut().enumerate()
             .find(|(_, area)| area.vpn_range.get_start() == start_vpn) {
-            area.unmap(&mut self.page_table);
+            let pages = area.unmap(&mut self.page_table);
             self.areas.remove(idx);
+            // other threads of the process may be running elsewhere
+            if !pages.is_empty() {
+                tlb_shootdown();
+            }
         }
     }
 
     pub fn unmap_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr) {
         let mut start_vpn = start_va.floor();
         let end_vpn = end_va.ceil();
+        let mut pages = Vec::new();
         while start_vpn < end_vpn {
             let (idx, _) = self.areas
                 .iter()
@@ -84,16 +287,18 @@ impl MemorySet {
                 .find(|(_, area)| area.vpn_range.get_start() <= start_vpn && area.vpn_range.get_end() > start_vpn)
                 .unwrap();
             let mut old_area = self.areas.remove(idx);
-            old_area.unmap_one(&mut self.page_table, start_vpn);
+            pages.extend(old_area.unmap_one(&mut self.page_table, start_vpn));
             let (left, right) = old_area.split_by(start_vpn);
-            if left.data_frames.len() > 0 {
+            if left.vpn_range.get_start() < left.vpn_range.get_end() {
                 self.areas.push(left);
             }
-            if right.data_frames.len() > 0 {
+            if right.vpn_range.get_start() < right.vpn_range.get_end() {
                 self.areas.push(right);
             }
             start_vpn.step();
         }
+        tlb_shootdown();
+        drop(pages);
     }
 
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), ()> {
@@ -195,7 +400,7 @@ impl MemorySet {
         Some(memory_set)
     }
 
-    pub fn from_elf(elf_data: &[u8]) -> Option<(Self, usize, usize)> {
+    pub fn from_elf(elf_data: &[u8]) -> Option<(Self, usize)> {
         let mut memory_set = Self::new_bare()?;
 
         match memory_set.map_trampoline() {
@@ -236,47 +441,86 @@ impl MemorySet {
             }
         }
 
+        // the heap starts out empty above a guard page; the user stacks and
+        // trap contexts belong to the threads and are mapped along with them
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        match memory_set.push(MapArea::new(
-            user_stack_bottom.into(),
-            user_stack_top.into(),
-            MapType::Framed,
+        let heap_bottom = usize::from(max_end_va) + PAGE_SIZE;
+        let mut heap = MapArea::new(
+            heap_bottom.into(),
+            heap_bottom.into(),
+            MapType::Lazy,
             MapPermission::R | MapPermission::W | MapPermission::U,
-        ), None) {
+        );
+        heap.heap = true;
+        match memory_set.push(heap, None) {
             Ok(_) => (),
             Err(_) => return None,
         };
+        memory_set.heap_bottom = heap_bottom;
+        memory_set.brk = heap_bottom;
 
-        match memory_set.push(MapArea::new(
-            TRAP_CONTEXT.into(),
-            TRAMPOLINE.into(),
-            MapType::Framed,
-            MapPermission::R | MapPermission::W,
-        ), None) {
-            Ok(_) => (),
-            Err(_) => return None,
-        };
-        Some((memory_set, user_stack_top, elf.header.pt2.entry_point() as usize))
+        Some((memory_set, elf.header.pt2.entry_point() as usize))
     }
 
-    pub fn from_existed_user(user_space: &MemorySet) -> Option<MemorySet> {
+    /// Duplicate a user address space for `fork`. Pages accessible from
+    /// user mode are shared with the parent and mapped read-only in both,
+    /// to be copied by `handle_page_fault` on the first write; the rest
+    /// (the trap context) is copied right away since the kernel writes it
+    /// through its physical address.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Option<MemorySet> {
         let mut memory_set = Self::new_bare()?;
         match memory_set.map_trampoline() {
             Ok(_) => (),
             Err(_) => return None,
         };
-        for area in user_space.areas.iter() {
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        for area in user_space.areas.iter_mut() {
+            if area.is_shared() {
+                // both sides have to end up with the same frames, so
+                // the whole mapping is brought in before sharing it
+                let mut new_area = MapArea::from_another(area);
+                for vpn in area.vpn_range {
+                    if !area.data_frames.contains_key(&vpn) {
+                        match area.map_one(&mut user_space.page_table, vpn) {
+                            Ok(_) => (),
+                            Err(_) => return None,
+                        };
+                    }
+                    let page = &area.data_frames[&vpn];
+                    let flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+                    match page.map(&mut memory_set.page_table, vpn, flags) {
+                        Ok(_) => (),
+                        Err(_) => return None,
+                    };
+                    new_area.data_frames.insert(vpn, Arc::clone(page));
+                }
+                memory_set.areas.push(new_area);
+                continue;
+            }
+            if area.map_perm.contains(MapPermission::U) {
+                let mut new_area = MapArea::from_another(area);
+                let flags = PTEFlags::from_bits(
+                    (area.map_perm - MapPermission::W).bits
+                ).unwrap();
+                for (vpn, page) in area.data_frames.iter() {
+                    match page.map(&mut memory_set.page_table, *vpn, flags) {
+                        Ok(_) => (),
+                        Err(_) => return None,
+                    };
+                    page.set_flags(&mut user_space.page_table, *vpn, flags);
+                    new_area.data_frames.insert(*vpn, Arc::clone(page));
+                }
+                memory_set.areas.push(new_area);
+                continue;
+            }
             let new_area = MapArea::from_another(area);
             match memory_set.push(new_area, None) {
                 Ok(_) => (),
                 Err(_) => return None,
             };
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                let src_ppn = user_space.page_table.translate(vpn).unwrap().ppn();
                 let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
                 dst_ppn.get_bytes_array().copy_from_slice(src_ppn.get_bytes_array());
             }
@@ -296,17 +540,57 @@ impl MemorySet {
         self.page_table.translate(vpn)
     }
 
+    /// Let the user pages mapped so far be swapped out. Until then the
+    /// kernel may still fill them in through their physical addresses.
+    pub fn make_swappable(&self) {
+        for area in self.areas.iter() {
+            for vpn in area.vpn_range {
+                area.make_swappable(vpn);
+            }
+        }
+    }
+
     pub fn recycle_data_pages(&mut self) {
+        for area in self.areas.iter_mut() {
+            area.unmap(&mut self.page_table);
+        }
         self.areas.clear();
     }
 }
 
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        self.recycle_data_pages();
+    }
+}
+
+
+/// The file behind a file-backed `MapArea`: the page at `base_vpn` holds
+/// the bytes of `inode` starting at `offset`.
+#[derive(Clone)]
+struct FileBacking {
+    inode: Arc<dyn VfsInode>,
+    offset: usize,
+    base_vpn: VirtPageNum,
+    shared: bool,
+}
+
+impl FileBacking {
+    fn file_offset(&self, vpn: VirtPageNum) -> usize {
+        self.offset + (vpn.0 - self.base_vpn.0) * PAGE_SIZE
+    }
+}
+
 
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<Page>>,
     map_type: MapType,
     map_perm: MapPermission,
+    backing: Option<FileBacking>,
+    /// Whether this is the heap that `set_brk` moves the end of. Other
+    /// areas may start where it does while it is empty.
+    heap: bool,
 }
 
 impl MapArea {
@@ -323,6 +607,8 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            backing: None,
+            heap: false,
         }
     }
 
@@ -332,40 +618,130 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            backing: another.backing.clone(),
+            heap: another.heap,
         }
     }
 
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), ()> {
-        let ppn: PhysPageNum;
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         match self.map_type {
             MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
+                page_table.map(vpn, PhysPageNum(vpn.0), pte_flags)
             }
-            MapType::Framed => {
+            MapType::Framed | MapType::Lazy => {
                 let frame = match frame_alloc() {
                     Some(frame) => frame,
                     None => return Err(()),
                 };
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                if let Some(backing) = &self.backing {
+                    backing.inode.read_at(backing.file_offset(vpn), frame.ppn.get_bytes_array());
+                }
+                let page = Arc::new(Page::new(frame));
+                page.map(page_table, vpn, pte_flags)?;
+                self.data_frames.insert(vpn, page);
+                Ok(())
             }
         }
+    }
+
+    /// Let the page at `vpn` be swapped out if it is an ordinary user page.
+    /// Shared file mappings stay resident, they go back to their file.
+    fn make_swappable(&self, vpn: VirtPageNum) {
+        if !self.map_perm.contains(MapPermission::U) || self.is_shared() {
+            return;
+        }
+        if let Some(page) = self.data_frames.get(&vpn) {
+            page.make_swappable();
+        }
+    }
+
+    /// Give `vpn` a page of its own with the area's full permissions,
+    /// copying the contents if the current page is shared with another
+    /// address space.
+    fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), ()> {
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags)
+        let page = self.data_frames.get(&vpn).unwrap();
+        if Arc::strong_count(page) == 1 {
+            page.set_flags(page_table, vpn, pte_flags);
+            return Ok(());
+        }
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => return Err(()),
+        };
+        match page.with_frame(|ppn| {
+            frame.ppn.get_bytes_array().copy_from_slice(ppn.get_bytes_array());
+        }) {
+            Some(_) => (),
+            None => return Err(()),
+        };
+        page.unmap(page_table, vpn);
+        let new_page = Arc::new(Page::new(frame));
+        new_page.map(page_table, vpn, pte_flags)?;
+        // the other address spaces may let go of the old page any time, so
+        // it is kept until no thread here has it cached
+        let old_page = self.data_frames.insert(vpn, new_page);
+        self.make_swappable(vpn);
+        tlb_shootdown();
+        drop(old_page);
+        Ok(())
+    }
+
+    fn is_shared(&self) -> bool {
+        self.backing.as_ref().map_or(false, |backing| backing.shared)
+    }
+
+    /// Write `vpn` back to the file if this is a shared file mapping and
+    /// the page has been written since it was last synced.
+    fn sync_one(&self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let backing = match &self.backing {
+            Some(backing) if backing.shared => backing,
+            _ => return,
+        };
+        let page = match self.data_frames.get(&vpn) {
+            Some(page) => page,
+            None => return,
+        };
+        let pte = page_table.translate(vpn).unwrap();
+        if !pte.dirty() {
+            return;
+        }
+        // the file is not extended to cover the tail of the last page
+        let offset = backing.file_offset(vpn);
+        let size = backing.inode.size();
+        if offset < size {
+            let len = PAGE_SIZE.min(size - offset);
+            page.with_frame(|ppn| {
+                backing.inode.write_at(offset, &ppn.get_bytes_array()[..len]);
+            });
+        }
+        page_table.remap(vpn, pte.ppn(), pte.flags() - PTEFlags::D);
     }
 
     #[allow(unused)]
-    pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Unmap `vpn`, returning its page if it had one. The frame must not
+    /// be freed before the other harts have flushed their TLBs.
+    pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<Arc<Page>> {
+        self.sync_one(page_table, vpn);
         match self.map_type {
-            MapType::Framed => {
-                self.data_frames.remove(&vpn);
+            MapType::Framed | MapType::Lazy => {
+                // untouched lazy pages were never put into the page table
+                let page = self.data_frames.remove(&vpn)?;
+                page.unmap(page_table, vpn);
+                Some(page)
+            }
+            MapType::Identical => {
+                page_table.unmap(vpn);
+                None
             }
-            _ => {}
         }
-        page_table.unmap(vpn);
     }
 
     pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), ()> {
+        if self.map_type == MapType::Lazy {
+            return Ok(());
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn)?;
         }
@@ -373,10 +749,12 @@ impl MapArea {
     }
 
     #[allow(unused)]
-    pub fn unmap(&mut self, page_table: &mut PageTable) {
+    pub fn unmap(&mut self, page_table: &mut PageTable) -> Vec<Arc<Page>> {
+        let mut pages = Vec::new();
         for vpn in self.vpn_range {
-            self.unmap_one(page_table, vpn);
+            pages.extend(self.unmap_one(page_table, vpn));
         }
+        pages
     }
 
     pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
@@ -408,6 +786,8 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: self.map_type,
             map_perm: self.map_perm,
+            backing: self.backing.clone(),
+            heap: self.heap,
         };
         temp_vpn.step();
         let mut right = Self {
@@ -415,6 +795,8 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: self.map_type,
             map_perm: self.map_perm,
+            backing: self.backing,
+            heap: false,
         };
         for (vpn, frame) in self.data_frames.into_iter() {
             if vpn < split_vpn {
@@ -432,6 +814,7 @@ impl MapArea {
 pub enum MapType {
     Identical,
     Framed,
+    Lazy,
 }
 
 bitflags! {
@@ -3,11 +3,12 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod swap;
 
 use page_table::PTEFlags;
 use address::VPNRange;
 pub use address::{PhysAddr, VirtAddr, PhysPageNum, VirtPageNum, StepByOne};
-pub use frame_allocator::{FrameTracker, frame_alloc, frame_dealloc};
+pub use frame_allocator::{FrameTracker, frame_alloc, frame_dealloc, frame_stats};
 pub use page_table::{
     PageTable,
     PageTableEntry,
@@ -20,11 +21,19 @@ pub use page_table::{
     UserBufferIterator,
 };
 pub use memory_set::{MemorySet, KERNEL_SPACE, MapPermission, kernel_token};
+use swap::Page;
+pub use swap::{swap_stats, PinnedPage};
 //pub use memory_set::remap_test;
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
+    swap::init();
+}
+
+/// Turn on paging on a hart started after the boot hart ran `init`.
+pub fn init_other_hart() {
+    KERNEL_SPACE.lock().activate();
 }
 
@@ -8,6 +8,8 @@ use super::{
     StepByOne,
     MapPermission,
 };
+use super::swap::pin;
+use crate::task::pin_page_current;
 use alloc::vec::Vec;
 use alloc::vec;
 use alloc::string::String;
@@ -65,6 +67,10 @@ impl PageTableEntry {
         (self.flags() & PTEFlags::W) != PTEFlags::empty()
     }
 
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
@@ -148,13 +154,59 @@ impl PageTable {
         *pte = PageTableEntry::empty();
     }
 
+    /// Point an already mapped `vpn` at `ppn` with new `flags`.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// Overwrite the entry of a `vpn` that has been mapped before, which
+    /// may have been made invalid since by swapping its page out.
+    pub fn set_entry(&mut self, vpn: VirtPageNum, pte: PageTableEntry) {
+        *self.find_pte_create(vpn).unwrap() = pte;
+    }
+
+    /// `translate` for user memory the current thread is about to access
+    /// in a syscall: a page that has been swapped out is brought back in
+    /// first, and kept in until the syscall returns. The kernel goes through
+    /// the physical address, which the hardware does not track, so the page
+    /// is marked accessed, and dirty if it is to be written, by hand.
+    pub fn translate_resident(&mut self, vpn: VirtPageNum, write: bool) -> Option<PageTableEntry> {
+        let mut pte = self.translate(vpn)?;
+        if let Some(pinned) = pin(self.token(), vpn) {
+            pin_page_current(pinned);
+            pte = self.translate(vpn).unwrap();
+        }
+        if pte.is_valid() {
+            let mut flags = pte.flags() | PTEFlags::A;
+            if write {
+                flags |= PTEFlags::D;
+            }
+            pte = PageTableEntry::new(pte.ppn(), flags);
+            self.set_entry(vpn, pte);
+        }
+        Some(pte)
+    }
+
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn)
             .map(|pte| { pte.clone() })
     }
 
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor())
+        self.translate(va.clone().floor())
+            .map(|pte| {
+                let aligned_pa: PhysAddr = pte.ppn().into();
+                let offset = va.page_offset();
+                let aligned_pa_usize: usize = aligned_pa.into();
+                (aligned_pa_usize + offset).into()
+            })
+    }
+
+    /// `translate_va` through `translate_resident`.
+    fn translate_user_va(&mut self, va: VirtAddr, write: bool) -> Option<PhysAddr> {
+        self.translate_resident(va.clone().floor(), write)
             .map(|pte| {
                 let aligned_pa: PhysAddr = pte.ppn().into();
                 let offset = va.page_offset();
@@ -168,8 +220,10 @@ impl PageTable {
     }
 }
 
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
+/// The user buffer at `ptr`, page by page. `write` tells whether the kernel
+/// is going to write to it.
+pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize, write: bool) -> Vec<&'static mut [u8]> {
+    let mut page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
     let end = start + len;
     let mut v = Vec::new();
@@ -177,7 +231,7 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
         let ppn = page_table
-            .translate(vpn)
+            .translate_resident(vpn, write)
             .unwrap()
             .ppn();
         vpn.step();
@@ -191,11 +245,11 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
 
 
 pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
-    let page_table = PageTable::from_token(token);
+    let mut page_table = PageTable::from_token(token);
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(match page_table.translate_va(VirtAddr::from(va)) {
+        let ch: u8 = *(match page_table.translate_user_va(VirtAddr::from(va), false) {
             Some(pa) => pa.get_mut(),
             None => return None,
         });
@@ -211,22 +265,22 @@ pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
 
 
 pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table.translate_va(VirtAddr::from(ptr as usize)).unwrap().get_ref()
+    let mut page_table = PageTable::from_token(token);
+    page_table.translate_user_va(VirtAddr::from(ptr as usize), false).unwrap().get_ref()
 }
 
 
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
+    let mut page_table = PageTable::from_token(token);
     let va = ptr as usize;
-    page_table.translate_va(VirtAddr::from(va)).unwrap().get_mut()
+    page_table.translate_user_va(VirtAddr::from(va), true).unwrap().get_mut()
 }
 
 
 pub fn is_mapped(token: usize, va: usize, permission: MapPermission) -> bool {
-    let page_table = PageTable::from_token(token);
+    let mut page_table = PageTable::from_token(token);
     let vpn: VirtPageNum = VirtAddr::from(va).floor();
-    let pte = match page_table.translate(vpn) {
+    let pte = match page_table.translate_resident(vpn, false) {
         Some(pte) => pte,
         None => return false,
     };
@@ -0,0 +1,307 @@
+use super::{PageTable, PageTableEntry, PTEFlags};
+use super::{PhysPageNum, VirtPageNum};
+use super::{FrameTracker, frame_alloc};
+use alloc::collections::BTreeMap;
+use core::ops::Bound::{Excluded, Unbounded};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use lazy_static::*;
+use easy_fs::BLOCK_SZ;
+use crate::config::{PAGE_SIZE, SWAP_SIZE};
+use crate::drivers::SWAP_DEVICE;
+use crate::smp::tlb_shootdown;
+
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SZ;
+
+struct SlotAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SlotAllocator {
+    fn new() -> Self {
+        Self {
+            current: 0,
+            end: SWAP_SIZE / PAGE_SIZE,
+            recycled: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        self.recycled.push(slot);
+    }
+}
+
+/// The number of swap slots in all and of those free.
+pub fn swap_stats() -> (usize, usize) {
+    let slots = SWAP_SLOTS.lock();
+    (slots.end, slots.end - slots.current + slots.recycled.len())
+}
+
+lazy_static! {
+    static ref SWAP_SLOTS: Mutex<SlotAllocator> = Mutex::new(SlotAllocator::new());
+}
+
+lazy_static! {
+    /// Every mapping of a swappable page, keyed by (page table token, vpn).
+    /// The clock hand sweeps over it in key order.
+    static ref SWAPPABLE: Mutex<BTreeMap<(usize, VirtPageNum), Weak<Page>>> =
+        Mutex::new(BTreeMap::new());
+    static ref CLOCK_HAND: Mutex<(usize, VirtPageNum)> = Mutex::new((0, VirtPageNum(0)));
+}
+
+pub fn init() {
+    // bring the device up now; doing it on the first swap out would
+    // allocate its queue frames exactly when there are none left
+    lazy_static::initialize(&SWAP_DEVICE);
+}
+
+
+/// A page of data that lives either in a frame or, once swapped out, in a
+/// slot of the swap device. It is shared by every page table entry that
+/// maps it, which are kept in `mappers` so they can all be updated when
+/// the page moves.
+pub struct Page {
+    inner: Mutex<PageInner>,
+}
+
+struct PageInner {
+    frame: Option<FrameTracker>,
+    /// A copy on the swap device, still up to date unless a mapping of the
+    /// resident page is dirty.
+    slot: Option<usize>,
+    mappers: Vec<(usize, VirtPageNum)>,
+    swappable: bool,
+    /// How many `PinnedPage`s keep the page resident.
+    pins: usize,
+}
+
+impl Page {
+    pub fn new(frame: FrameTracker) -> Self {
+        Self {
+            inner: Mutex::new(PageInner {
+                frame: Some(frame),
+                slot: None,
+                mappers: Vec::new(),
+                swappable: false,
+                pins: 0,
+            }),
+        }
+    }
+
+    pub fn is_resident(&self) -> bool {
+        self.inner.lock().frame.is_some()
+    }
+
+    /// Let the page be chosen for swapping out from now on.
+    pub fn make_swappable(self: &Arc<Self>) {
+        let mut inner = self.inner.lock();
+        if inner.swappable {
+            return;
+        }
+        inner.swappable = true;
+        let mut swappable = SWAPPABLE.lock();
+        for key in inner.mappers.iter() {
+            swappable.insert(*key, Arc::downgrade(self));
+        }
+    }
+
+    /// Map `vpn` to this page in `page_table`. If the page is swapped out
+    /// the entry is written with `flags` but left invalid.
+    pub fn map(self: &Arc<Self>, page_table: &mut PageTable, vpn: VirtPageNum, flags: PTEFlags) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        match &inner.frame {
+            Some(frame) => page_table.map(vpn, frame.ppn, flags)?,
+            None => {
+                page_table.map(vpn, PhysPageNum(0), flags)?;
+                page_table.set_entry(vpn, PageTableEntry::new(PhysPageNum(0), flags));
+            }
+        }
+        let key = (page_table.token(), vpn);
+        inner.mappers.push(key);
+        if inner.swappable {
+            SWAPPABLE.lock().insert(key, Arc::downgrade(self));
+        }
+        Ok(())
+    }
+
+    pub fn unmap(&self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let mut inner = self.inner.lock();
+        let key = (page_table.token(), vpn);
+        inner.mappers.retain(|mapper| *mapper != key);
+        if inner.swappable {
+            SWAPPABLE.lock().remove(&key);
+        }
+        page_table.set_entry(vpn, PageTableEntry::empty());
+    }
+
+    /// Change the permissions `page_table` maps `vpn` with, keeping it
+    /// valid or not as it is.
+    pub fn set_flags(&self, page_table: &mut PageTable, vpn: VirtPageNum, flags: PTEFlags) {
+        let _inner = self.inner.lock();
+        let pte = page_table.translate(vpn).unwrap();
+        let kept = pte.flags() & (PTEFlags::V | PTEFlags::A | PTEFlags::D);
+        page_table.set_entry(vpn, PageTableEntry::new(pte.ppn(), flags | kept));
+    }
+
+    /// Run `f` on the frame holding this page, swapping it in first if
+    /// necessary. The page stays resident while `f` runs. Returns None if
+    /// no frame could be found to swap it into.
+    pub fn with_frame<V>(&self, f: impl FnOnce(PhysPageNum) -> V) -> Option<V> {
+        let mut inner = self.inner.lock();
+        if inner.frame.is_none() {
+            inner.swap_in()?;
+        }
+        Some(f(inner.frame.as_ref().unwrap().ppn))
+    }
+}
+
+impl PageInner {
+    fn swap_in(&mut self) -> Option<()> {
+        let frame = frame_alloc()?;
+        let slot = self.slot.unwrap();
+        for (i, block) in frame.ppn.get_bytes_array().chunks_mut(BLOCK_SZ).enumerate() {
+            SWAP_DEVICE.read_block(slot * BLOCKS_PER_SLOT + i, block);
+        }
+        for (token, vpn) in self.mappers.iter() {
+            let mut page_table = PageTable::from_token(*token);
+            let pte = page_table.translate(*vpn).unwrap();
+            let flags = (pte.flags() - PTEFlags::A - PTEFlags::D) | PTEFlags::V;
+            page_table.set_entry(*vpn, PageTableEntry::new(frame.ppn, flags));
+        }
+        self.frame = Some(frame);
+        Some(())
+    }
+
+    /// Give the page a second chance if any mapping has used it since the
+    /// clock hand last came by.
+    fn test_and_clear_accessed(&mut self) -> bool {
+        let mut accessed = false;
+        for (token, vpn) in self.mappers.iter() {
+            let mut page_table = PageTable::from_token(*token);
+            let pte = page_table.translate(*vpn).unwrap();
+            if (pte.flags() & PTEFlags::A) != PTEFlags::empty() {
+                accessed = true;
+                page_table.set_entry(*vpn, PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A));
+            }
+        }
+        accessed
+    }
+
+    fn swap_out(&mut self) -> bool {
+        let slot = match self.slot.or_else(|| SWAP_SLOTS.lock().alloc()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        // Unmap the page everywhere before it is written out: once the
+        // other harts have flushed their TLBs nothing can change it any more.
+        let mut dirty = false;
+        for (token, vpn) in self.mappers.iter() {
+            let mut page_table = PageTable::from_token(*token);
+            let pte = page_table.translate(*vpn).unwrap();
+            dirty |= pte.dirty();
+            let flags = pte.flags() - PTEFlags::V - PTEFlags::A - PTEFlags::D;
+            page_table.set_entry(*vpn, PageTableEntry::new(pte.ppn(), flags));
+        }
+        tlb_shootdown();
+        if self.slot.is_none() || dirty {
+            let ppn = self.frame.as_ref().unwrap().ppn;
+            for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SZ).enumerate() {
+                SWAP_DEVICE.write_block(slot * BLOCKS_PER_SLOT + i, block);
+            }
+            self.slot = Some(slot);
+        }
+        self.frame = None;
+        true
+    }
+}
+
+impl Drop for PageInner {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            SWAP_SLOTS.lock().dealloc(slot);
+        }
+    }
+}
+
+
+/// Free a frame by moving one resident swappable page out to the swap
+/// device, chosen with the clock algorithm over the accessed bits.
+/// Returns false if there is nothing that can be swapped out.
+pub fn swap_out_one() -> bool {
+    let mut swappable = SWAPPABLE.lock();
+    let mut hand = CLOCK_HAND.lock();
+    // two sweeps: the first one may do nothing but clear accessed bits
+    let mut remaining = 2 * swappable.len();
+    let mut dead: Vec<(usize, VirtPageNum)> = Vec::new();
+    let mut result = false;
+    while remaining > 0 {
+        let next = swappable.range((Excluded(*hand), Unbounded)).next()
+            .or_else(|| swappable.iter().next())
+            .map(|(key, page)| (*key, page.clone()));
+        let (key, page) = match next {
+            Some(entry) => entry,
+            None => break,
+        };
+        *hand = key;
+        remaining -= 1;
+        let page = match page.upgrade() {
+            Some(page) => page,
+            None => {
+                dead.push(key);
+                continue;
+            }
+        };
+        // pages busy elsewhere, e.g. being swapped in, are left alone
+        let mut inner = match page.inner.try_lock() {
+            Some(inner) => inner,
+            None => continue,
+        };
+        if inner.frame.is_none() || inner.pins > 0 || inner.test_and_clear_accessed() {
+            continue;
+        }
+        result = inner.swap_out();
+        break;
+    }
+    for key in dead.iter() {
+        swappable.remove(key);
+    }
+    result
+}
+
+/// A swappable page the kernel is using through its frame, which stays
+/// resident until this is dropped.
+pub struct PinnedPage(Arc<Page>);
+
+impl Drop for PinnedPage {
+    fn drop(&mut self) {
+        self.0.inner.lock().pins -= 1;
+    }
+}
+
+/// Bring back the page `token` maps at `vpn` if it has been swapped out,
+/// and keep it from being swapped out again while the pin is held.
+/// Returns None if there is no such page or it could not be swapped in.
+pub fn pin(token: usize, vpn: VirtPageNum) -> Option<PinnedPage> {
+    let page = SWAPPABLE.lock().get(&(token, vpn)).and_then(|page| page.upgrade())?;
+    let mut inner = page.inner.lock();
+    if inner.frame.is_none() {
+        inner.swap_in()?;
+    }
+    inner.pins += 1;
+    drop(inner);
+    Some(PinnedPage(page))
+}
@@ -10,6 +10,9 @@ const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
 
+const SBI_EXT_HSM: usize = 0x48534d;
+const SBI_HSM_HART_START: usize = 0;
+
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let mut ret;
@@ -25,6 +28,23 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
 }
 
 
+/// A call to an SBI extension, which takes the function in a6 and returns
+/// an error code in a0.
+#[inline(always)]
+fn sbi_call_ext(extension: usize, function: usize, arg0: usize, arg1: usize, arg2: usize) -> isize {
+    let mut error;
+    unsafe {
+        llvm_asm!("ecall"
+            : "={x10}" (error)
+            : "{x10}" (arg0), "{x11}" (arg1), "{x12}" (arg2), "{x16}" (function), "{x17}" (extension)
+            : "memory"
+            : "volatile"
+        );
+    }
+    error
+}
+
+
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
 }
@@ -40,6 +60,18 @@ pub fn console_getchar() -> usize {
 }
 
 
+/// Raise a supervisor software interrupt on the harts in `hart_mask`.
+pub fn send_ipi(hart_mask: usize) {
+    sbi_call(SBI_SEND_IPI, &hart_mask as *const usize as usize, 0, 0);
+}
+
+
+/// Start hart `hart_id` at `start_addr` with `opaque` in a1.
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> isize {
+    sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_START, hart_id, start_addr, opaque)
+}
+
+
 pub fn shutdown() -> ! {
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
     panic!("It should shutdown!");
@@ -0,0 +1,80 @@
+use crate::config::MAX_HARTS;
+use crate::sbi::{hart_start, send_ipi};
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// The harts that have come up, one bit each.
+static STARTED_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+const USER_EPOCH_INIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Bumped by each hart on its way into user mode and again on its way
+/// out, so that it is odd while the hart may use user mappings.
+static USER_EPOCHS: [AtomicUsize; MAX_HARTS] = [USER_EPOCH_INIT; MAX_HARTS];
+
+/// The id of the hart we run on, kept in tp while in the kernel.
+pub fn hart_id() -> usize {
+    let hart_id: usize;
+    unsafe { llvm_asm!("mv $0, tp" : "=r"(hart_id) ::: "volatile"); }
+    hart_id
+}
+
+pub fn set_hart_started() {
+    STARTED_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+}
+
+/// Ask SBI to start the other harts at `_start`. Those that are missing
+/// or already running are skipped.
+pub fn start_other_harts() {
+    extern "C" {
+        fn _start();
+    }
+    for id in (0..MAX_HARTS).filter(|id| *id != hart_id()) {
+        if hart_start(id, _start as usize, 0) != 0 {
+            println!("[kernel] hart {} not started", id);
+        }
+    }
+}
+
+/// Called right before the trampoline switches to the user page table
+/// and flushes the TLB.
+pub fn enter_user() {
+    USER_EPOCHS[hart_id()].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Called first thing in the kernel after a trap from user mode, with the
+/// TLB flushed by the trampoline.
+pub fn leave_user() {
+    USER_EPOCHS[hart_id()].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Make the other harts drop the user mappings they may have cached, and
+/// wait until they have: only then may the frames behind mappings changed
+/// before be freed or reused. A hart in the kernel flushes the TLB on its
+/// way back to user mode anyway, one running user code is interrupted and
+/// flushes it on its way in.
+pub fn tlb_shootdown() {
+    // the page tables must be changed before we look at the epochs
+    fence(Ordering::SeqCst);
+    let started = STARTED_HARTS.load(Ordering::SeqCst);
+    let mut epochs = [None; MAX_HARTS];
+    let mut hart_mask = 0;
+    for id in (0..MAX_HARTS).filter(|id| *id != hart_id() && started & (1 << id) != 0) {
+        let epoch = USER_EPOCHS[id].load(Ordering::SeqCst);
+        if epoch % 2 == 1 {
+            epochs[id] = Some(epoch);
+            hart_mask |= 1 << id;
+        }
+    }
+    if hart_mask == 0 {
+        return;
+    }
+    send_ipi(hart_mask);
+    for (id, epoch) in epochs.iter().enumerate() {
+        if let Some(epoch) = epoch {
+            while USER_EPOCHS[id].load(Ordering::SeqCst) == *epoch {
+                spin_loop();
+            }
+        }
+    }
+}
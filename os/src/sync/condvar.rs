@@ -0,0 +1,37 @@
+use crate::task::{block_current_and_run_next, WaitQueue};
+use super::Mutex;
+use alloc::sync::Arc;
+
+pub struct Condvar {
+    wait_queue: spin::Mutex<WaitQueue>,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            wait_queue: spin::Mutex::new(WaitQueue::new()),
+        }
+    }
+
+    /// Wake up one of the waiting threads. A signal nobody waits for is lost.
+    pub fn signal(&self) {
+        self.wait_queue.lock().wake_one();
+    }
+
+    /// Release `mutex` and block until signalled, then take `mutex` again.
+    /// Fails without waiting if `mutex` is not locked, and fails if a signal
+    /// comes first, after taking `mutex` again if it can.
+    pub fn wait(&self, mutex: Arc<dyn Mutex>) -> Result<(), ()> {
+        let mut wait_queue = self.wait_queue.lock();
+        mutex.unlock()?;
+        wait_queue.add_current();
+        drop(wait_queue);
+        let interrupted = block_current_and_run_next()
+            && self.wait_queue.lock().remove_current();
+        mutex.lock()?;
+        if interrupted {
+            return Err(());
+        }
+        Ok(())
+    }
+}
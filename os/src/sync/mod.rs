@@ -0,0 +1,7 @@
+mod condvar;
+mod mutex;
+mod semaphore;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexSpin, MutexBlocking};
+pub use semaphore::Semaphore;
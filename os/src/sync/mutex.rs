@@ -0,0 +1,104 @@
+use crate::task::{
+    suspend_current_and_run_next,
+    block_current_and_run_next,
+    signal_pending_current,
+    WaitQueue,
+};
+
+/// A lock user threads take through the mutex syscalls.
+pub trait Mutex: Sync + Send {
+    /// Fails if a signal comes before the lock is taken.
+    fn lock(&self) -> Result<(), ()>;
+    /// Fails if the mutex is not locked.
+    fn unlock(&self) -> Result<(), ()>;
+}
+
+/// Waits for the lock by giving up the CPU and trying again.
+pub struct MutexSpin {
+    locked: spin::Mutex<bool>,
+}
+
+impl MutexSpin {
+    pub fn new() -> Self {
+        Self {
+            locked: spin::Mutex::new(false),
+        }
+    }
+}
+
+impl Mutex for MutexSpin {
+    fn lock(&self) -> Result<(), ()> {
+        loop {
+            let mut locked = self.locked.lock();
+            if *locked {
+                drop(locked);
+                if signal_pending_current() {
+                    return Err(());
+                }
+                suspend_current_and_run_next();
+                continue;
+            }
+            *locked = true;
+            return Ok(());
+        }
+    }
+
+    fn unlock(&self) -> Result<(), ()> {
+        let mut locked = self.locked.lock();
+        if !*locked {
+            return Err(());
+        }
+        *locked = false;
+        Ok(())
+    }
+}
+
+/// Blocks the threads waiting for the lock until it is handed to them.
+pub struct MutexBlocking {
+    inner: spin::Mutex<MutexBlockingInner>,
+}
+
+struct MutexBlockingInner {
+    locked: bool,
+    wait_queue: WaitQueue,
+}
+
+impl MutexBlocking {
+    pub fn new() -> Self {
+        Self {
+            inner: spin::Mutex::new(MutexBlockingInner {
+                locked: false,
+                wait_queue: WaitQueue::new(),
+            }),
+        }
+    }
+}
+
+impl Mutex for MutexBlocking {
+    fn lock(&self) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        if inner.locked {
+            inner.wait_queue.add_current();
+            drop(inner);
+            // the lock is ours once we are woken up from the queue
+            if block_current_and_run_next() && self.inner.lock().wait_queue.remove_current() {
+                return Err(());
+            }
+        } else {
+            inner.locked = true;
+        }
+        Ok(())
+    }
+
+    fn unlock(&self) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        if !inner.locked {
+            return Err(());
+        }
+        // pass the lock on to the first waiter, if any
+        if !inner.wait_queue.wake_one() {
+            inner.locked = false;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use crate::task::{block_current_and_run_next, WaitQueue};
+use spin::Mutex;
+
+/// A counting semaphore. A negative count is the number of threads
+/// blocked in `down`.
+pub struct Semaphore {
+    inner: Mutex<SemaphoreInner>,
+}
+
+struct SemaphoreInner {
+    count: isize,
+    wait_queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub fn new(res_count: usize) -> Self {
+        Self {
+            inner: Mutex::new(SemaphoreInner {
+                count: res_count as isize,
+                wait_queue: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn up(&self) {
+        let mut inner = self.inner.lock();
+        inner.count += 1;
+        if inner.count <= 0 {
+            inner.wait_queue.wake_one();
+        }
+    }
+
+    /// Fails if a signal comes before the semaphore is taken.
+    pub fn down(&self) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        inner.count -= 1;
+        if inner.count < 0 {
+            inner.wait_queue.add_current();
+            drop(inner);
+            if block_current_and_run_next() {
+                let mut inner = self.inner.lock();
+                // unless `up` has woken us up meanwhile
+                if inner.wait_queue.remove_current() {
+                    inner.count += 1;
+                    return Err(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
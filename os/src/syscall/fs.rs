@@ -3,24 +3,61 @@ use crate::mm::{
     MapPermission,
     translated_byte_buffer,
     translated_str,
-    translated_refmut,
-    is_mapped};
-use crate::task::{current_user_token, current_task};
+    translated_refmut};
+use crate::task::{current_user_token, current_process, is_mapped_current, signal_pending_current};
 use crate::config::PAGE_SIZE;
 use crate::fs::{
     File,
+    SeekFrom,
     MAX_MAIL_LEN,
     find_mailbox,
     make_pipe,
     OpenFlags,
-    open_file,
+    find_inode,
+    normalize_path,
+    open_node,
     link_file,
     unlink_file,
+    make_dir,
+    remove_dir,
+    create_fs,
+    mount,
+    umount,
 };
 use alloc::sync::Arc;
-use easy_fs::Stat;
+use alloc::vec::Vec;
+use alloc::string::String;
+use easy_fs::{Stat, StatMode};
 
-pub fn sys_linkat(_oldfd: usize, olddir: *const u8, _newfd: usize, newdir: *const u8, _flags: u32) -> isize {
+pub const AT_FDCWD: usize = -100isize as usize;
+const AT_REMOVEDIR: u32 = 0x200;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+/// The absolute path of the directory a relative path passed along with
+/// `dirfd` starts from: the current working directory for `AT_FDCWD`,
+/// otherwise the directory opened as `dirfd`.
+pub fn dir_path(dirfd: usize) -> Option<String> {
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    if dirfd == AT_FDCWD {
+        Some(inner.cwd.clone())
+    } else {
+        if dirfd >= inner.fd_table.len() {
+            return None;
+        }
+        let file = inner.fd_table[dirfd].as_ref()?.clone();
+        drop(inner);
+        if !file.inode()?.is_dir() {
+            return None;
+        }
+        file.path()
+    }
+}
+
+pub fn sys_linkat(oldfd: usize, olddir: *const u8, newfd: usize, newdir: *const u8, _flags: u32) -> isize {
     let token = current_user_token();
     let olddir = match translated_str(token, olddir) {
         Some(dir) => dir,
@@ -30,39 +67,147 @@ pub fn sys_linkat(_oldfd: usize, olddir: *const u8, _newfd: usize, newdir: *cons
         Some(dir) => dir,
         None => return -1,
     };
+    let (old_base, new_base) = match (dir_path(oldfd), dir_path(newfd)) {
+        (Some(old_base), Some(new_base)) => (old_base, new_base),
+        _ => return -1,
+    };
 
-    match link_file(olddir.as_str(), newdir.as_str()) {
+    match link_file(old_base.as_str(), olddir.as_str(), new_base.as_str(), newdir.as_str()) {
         Ok(_) => 0,
         Err(_) => -1,
     }
 }
 
-pub fn sys_unlinkat(_fd: usize, dir: *const u8, _flags: u32) -> isize {
+pub fn sys_unlinkat(fd: usize, dir: *const u8, flags: u32) -> isize {
     let token = current_user_token();
     let dir = match translated_str(token, dir) {
         Some(dir) => dir,
         None => return -1,
     };
-    match unlink_file(dir.as_str()) {
+    let base = match dir_path(fd) {
+        Some(base) => base,
+        None => return -1,
+    };
+    let result = if flags & AT_REMOVEDIR != 0 {
+        remove_dir(base.as_str(), dir.as_str())
+    } else {
+        unlink_file(base.as_str(), dir.as_str())
+    };
+    match result {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_mkdirat(fd: usize, path: *const u8, _mode: u32) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let base = match dir_path(fd) {
+        Some(base) => base,
+        None => return -1,
+    };
+    match make_dir(base.as_str(), path.as_str()) {
         Ok(_) => 0,
         Err(_) => -1,
     }
 }
 
+/// Mount a new filesystem of type `fs_type` at the directory `target`.
+/// None of the filesystems it can make has a device behind it, so
+/// `source` is ignored.
+pub fn sys_mount(_source: *const u8, target: *const u8, fs_type: *const u8, _flags: u32, _data: usize) -> isize {
+    let token = current_user_token();
+    let target = match translated_str(token, target) {
+        Some(target) => target,
+        None => return -1,
+    };
+    let fs = match translated_str(token, fs_type).and_then(|fs_type| create_fs(fs_type.as_str())) {
+        Some(fs) => fs,
+        None => return -1,
+    };
+    let cwd = current_process().acquire_inner_lock().cwd.clone();
+    match mount(normalize_path(cwd.as_str(), target.as_str()).as_str(), fs) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_umount2(target: *const u8, _flags: u32) -> isize {
+    let token = current_user_token();
+    let target = match translated_str(token, target) {
+        Some(target) => target,
+        None => return -1,
+    };
+    let cwd = current_process().acquire_inner_lock().cwd.clone();
+    match umount(normalize_path(cwd.as_str(), target.as_str()).as_str()) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let process = current_process();
+    let cwd = process.acquire_inner_lock().cwd.clone();
+    let new_cwd = normalize_path(cwd.as_str(), path.as_str());
+    match find_inode("/", new_cwd.as_str()) {
+        Some(inode) if inode.is_dir() => {
+            process.acquire_inner_lock().cwd = new_cwd;
+            0
+        }
+        _ => -1,
+    }
+}
+
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let cwd = process.acquire_inner_lock().cwd.clone();
+    // the terminating '\0' is copied as well
+    let cwd_len = cwd.len() + 1;
+    if len < cwd_len {
+        return -1;
+    }
+
+    let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = buf as usize + cwd_len;
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+
+    let bytes = cwd.as_bytes().iter().chain(core::iter::once(&0u8));
+    for (byte_ref, byte) in UserBuffer::new(translated_byte_buffer(token, buf, cwd_len, true))
+        .into_iter()
+        .zip(bytes) {
+        unsafe { *byte_ref = *byte; }
+    }
+    cwd_len as isize
+}
+
 pub fn sys_fstat(fd: usize, st: usize) -> isize {
     let token = current_user_token();
 
     let mut start = st / PAGE_SIZE * PAGE_SIZE;
     let end = start + core::mem::size_of::<Stat>();
     while start < end {
-        if !is_mapped(token, start, MapPermission::U | MapPermission::W) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
             return -1;
         }
         start += PAGE_SIZE;
     }
     
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -80,20 +225,30 @@ pub fn sys_fstat(fd: usize, st: usize) -> isize {
     }
 }
 
+/// What `sys_read` or `sys_write` returns having moved `len` of `requested`
+/// bytes: -1 if a signal made a pipe or the console give up before any.
+fn transferred(len: usize, requested: usize) -> isize {
+    if len == 0 && requested != 0 && signal_pending_current() {
+        -1
+    } else {
+        len as isize
+    }
+}
+
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
 
     let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
     let end = start + len;
     while start < end {
-        if !is_mapped(token, start, MapPermission::U | MapPermission::R) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::R) {
             return -1;
         }
         start += PAGE_SIZE;
     }
 
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -103,9 +258,10 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         }
         let file = file.clone();
         drop(inner);
-        file.write(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        let written = file.write(
+            UserBuffer::new(translated_byte_buffer(token, buf, len, false))
+        );
+        transferred(written, len)
     } else {
         -1
     }
@@ -117,14 +273,14 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
     let end = start + len;
     while start < end {
-        if !is_mapped(token, start, MapPermission::U | MapPermission::W) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
             return -1;
         }
         start += PAGE_SIZE;
     }
 
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -137,26 +293,188 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         }
         let file = file.clone();
         drop(inner);
-        file.read(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        let read = file.read(
+            UserBuffer::new(translated_byte_buffer(token, buf, len, true))
+        );
+        transferred(read, len)
     } else {
         -1
     }
 }
 
-pub fn sys_open(path: *const u8, flags: u32) -> isize {
-    let task = current_task().unwrap();
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+/// Fill `buf` with `linux_dirent64` records read from the directory open as
+/// `fd`, starting at the fd's offset, and advance the offset past them.
+/// Returns the number of bytes written, 0 at the end of the directory, or -1
+/// if not even one record fits.
+pub fn sys_getdents64(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = current_user_token();
+
+    let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = buf as usize + len;
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match inner.fd_table[fd].as_ref() {
+        Some(file) => Arc::clone(file),
+        None => return -1,
+    };
+    drop(inner);
+    match file.fstat() {
+        Some(stat) if stat.mode.contains(StatMode::DIR) => {}
+        _ => return -1,
+    }
+
+    let mut offset = file.seek(SeekFrom::Current(0)).unwrap();
+    let mut records: Vec<u8> = Vec::new();
+    while let Some((name, inode_id, mode, next)) = file.read_dir(offset) {
+        // d_ino, d_off, d_reclen, d_type, then the NUL-terminated name
+        let reclen = (8 + 8 + 2 + 1 + name.len() + 1 + 7) / 8 * 8;
+        if records.len() + reclen > len {
+            if records.is_empty() {
+                return -1;
+            }
+            break;
+        }
+        let d_type = if mode.contains(StatMode::DIR) { DT_DIR } else { DT_REG };
+        records.extend_from_slice(&(inode_id as u64).to_ne_bytes());
+        records.extend_from_slice(&(next as u64).to_ne_bytes());
+        records.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        records.push(d_type);
+        records.extend_from_slice(name.as_bytes());
+        records.resize(records.len() + reclen - (8 + 8 + 2 + 1 + name.len()), 0);
+        offset = next;
+    }
+    file.seek(SeekFrom::Start(offset));
+
+    let user_buf = UserBuffer::new(translated_byte_buffer(token, buf, records.len(), true));
+    for (dst, src) in user_buf.into_iter().zip(records.iter()) {
+        unsafe { *dst = *src; }
+    }
+    records.len() as isize
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return -1,
+    };
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match inner.fd_table[fd].as_ref() {
+        Some(file) => Arc::clone(file),
+        None => return -1,
+    };
+    drop(inner);
+    match file.seek(pos) {
+        Some(offset) => offset as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_pread(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+
+    let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = buf as usize + len;
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.readable() {
+            return -1;
+        }
+        let file = file.clone();
+        drop(inner);
+        match file.read_at(
+            offset,
+            UserBuffer::new(translated_byte_buffer(token, buf, len, true)),
+        ) {
+            Some(read_size) => read_size as isize,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_pwrite(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+
+    let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = buf as usize + len;
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::R) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.writable() {
+            return -1;
+        }
+        let file = file.clone();
+        drop(inner);
+        match file.write_at(
+            offset,
+            UserBuffer::new(translated_byte_buffer(token, buf, len, false)),
+        ) {
+            Some(write_size) => write_size as isize,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_openat(fd: usize, path: *const u8, flags: u32, _mode: u32) -> isize {
+    let process = current_process();
     let token = current_user_token();
     let path = match translated_str(token, path) {
         Some(path) => path,
         None => return -1,
     };
-    if let Some(inode) = open_file(
+    let base = match dir_path(fd) {
+        Some(base) => base,
+        None => return -1,
+    };
+    if let Some(inode) = open_node(
+        base.as_str(),
         path.as_str(),
         OpenFlags::from_bits(flags).unwrap(),
     ) {
-        let mut inner = task.acquire_inner_lock();
+        let mut inner = process.acquire_inner_lock();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
         fd as isize
@@ -166,8 +484,8 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
 }
 
 pub fn sys_close(fd: usize) -> isize {
-    let task = current_task().unwrap();
-    let mut inner = task.acquire_inner_lock();
+    let process = current_process();
+    let mut inner = process.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -179,19 +497,19 @@ pub fn sys_close(fd: usize) -> isize {
 }
 
 pub fn sys_pipe(pipe: *mut usize) -> isize {
-    let task = current_task().unwrap();
+    let process = current_process();
     let token = current_user_token();
 
     let mut start = pipe as usize / PAGE_SIZE * PAGE_SIZE;
     let end = start + 2 * core::mem::size_of::<usize>();
     while start < end {
-        if !is_mapped(token, start, MapPermission::U | MapPermission::W) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
             return -1;
         }
         start += PAGE_SIZE;
     }
 
-    let mut inner = task.acquire_inner_lock();
+    let mut inner = process.acquire_inner_lock();
     let (pipe_read, pipe_write) = make_pipe();
     let read_fd = inner.alloc_fd();
     inner.fd_table[read_fd] = Some(pipe_read);
@@ -203,8 +521,8 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
 }
 
 pub fn sys_dup(fd: usize) -> isize {
-    let task = current_task().unwrap();
-    let mut inner = task.acquire_inner_lock();
+    let process = current_process();
+    let mut inner = process.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -220,8 +538,8 @@ pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
     let token = current_user_token();
     let len = len.min(MAX_MAIL_LEN);
 
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
     let mailbox = inner.mailbox.clone();
     drop(inner);
 
@@ -235,12 +553,12 @@ pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
         let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
         let end = start + len;
         while start < end {
-            if !is_mapped(token, start, MapPermission::U | MapPermission::W) {
+            if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
                 return -1;
             }
             start += PAGE_SIZE;
         }
-        mailbox.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        mailbox.read(UserBuffer::new(translated_byte_buffer(token, buf, len, true))) as isize
     }
 }
 
@@ -248,10 +566,10 @@ pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
     let token = current_user_token();
     let len = len.min(MAX_MAIL_LEN);
 
-    let task = current_task().unwrap();
-    let inner = task.acquire_inner_lock();
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
     let mailbox;
-    if task.pid.0 == pid {
+    if process.getpid() == pid {
         mailbox = inner.mailbox.clone();
     } else {
         mailbox = match find_mailbox(pid) {
@@ -271,11 +589,11 @@ pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
         let mut start = buf as usize / PAGE_SIZE * PAGE_SIZE;
         let end = start + len;
         while start < end {
-            if !is_mapped(token, start, MapPermission::U | MapPermission::R) {
+            if !is_mapped_current(start, MapPermission::U | MapPermission::R) {
                 return -1;
             }
             start += PAGE_SIZE;
         }
-        mailbox.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        mailbox.write(UserBuffer::new(translated_byte_buffer(token, buf, len, false))) as isize
     }
 }
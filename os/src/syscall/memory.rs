@@ -1,10 +1,29 @@
 use crate::mm::{VirtAddr, MapPermission, is_mapped};
-use crate::task::{current_user_token, mmap_current, munmap_current};
+use crate::task::{
+    current_process,
+    current_user_token,
+    brk_current,
+    mmap_current,
+    mmap_file_current,
+    msync_current,
+    munmap_current,
+};
 use crate::config::PAGE_SIZE;
+use alloc::sync::Arc;
 
 const MAX_LEN: usize = 1 << 30;
 
-pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// Set the program break to `new_brk` and return the resulting break;
+/// `brk(0)` just queries it.
+pub fn sys_brk(new_brk: usize) -> isize {
+    brk_current(new_brk) as isize
+}
+
+pub fn sys_mmap(start: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
     if start % PAGE_SIZE != 0 || len > MAX_LEN {
         return -1;
     }
@@ -13,6 +32,19 @@ pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
         return -1;
     }
 
+    if (flags & !(MAP_SHARED | MAP_PRIVATE | MAP_ANONYMOUS)) != 0 {
+        return -1;
+    }
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return -1,
+    };
+    // anonymous memory is only ever private to the process
+    if shared && (flags & MAP_ANONYMOUS) != 0 {
+        return -1;
+    }
+
     if len == 0 {
         return 0;
     }
@@ -41,7 +73,32 @@ pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
         permission |= MapPermission::X;
     }
 
-    match mmap_current(start_va, end_va, permission) {
+    let result = if (flags & MAP_ANONYMOUS) != 0 {
+        mmap_current(start_va, end_va, permission)
+    } else {
+        if offset % PAGE_SIZE != 0 {
+            return -1;
+        }
+        let process = current_process();
+        let inner = process.acquire_inner_lock();
+        if fd >= inner.fd_table.len() {
+            return -1;
+        }
+        let file = match inner.fd_table[fd].as_ref() {
+            Some(file) => Arc::clone(file),
+            None => return -1,
+        };
+        drop(inner);
+        if !file.readable() || (shared && (prot & 0x2) != 0 && !file.writable()) {
+            return -1;
+        }
+        let inode = match file.inode() {
+            Some(inode) if inode.is_file() => inode,
+            _ => return -1,
+        };
+        mmap_file_current(start_va, end_va, permission, inode, offset, shared)
+    };
+    match result {
         Ok(_) => (cur - start) as isize,
         Err(_) => -1,
     }
@@ -56,20 +113,23 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
         return 0;
     }
 
-    let mut cur = start;
-    let end = start + len;
-    let token = current_user_token();
+    let start_va = VirtAddr::from(start);
+    let end_va = VirtAddr::from(start + len);
+    match munmap_current(start_va, end_va) {
+        Ok(_) => ((len + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE) as isize,
+        Err(_) => -1,
+    }
+}
 
-    while cur < end {
-        if !is_mapped(token, cur, MapPermission::U) {
-            return -1;
-        }
-        cur += PAGE_SIZE;
+pub fn sys_msync(start: usize, len: usize, _flags: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len > MAX_LEN {
+        return -1;
     }
 
     let start_va = VirtAddr::from(start);
-    let end_va = VirtAddr::from(end);
-    munmap_current(start_va, end_va);
-
-    (cur - start) as isize
+    let end_va = VirtAddr::from(start + len);
+    match msync_current(start_va, end_va) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
 }
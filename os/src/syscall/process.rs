@@ -1,10 +1,17 @@
 use crate::task::{
     suspend_current_and_run_next,
+    prepare_block_current,
+    block_current_and_run_next,
     exit_current_and_run_next,
+    wakeup_task,
     current_task,
+    current_process,
     current_user_token,
     set_current_priority,
-    add_task,
+    is_mapped_current,
+    pid2process,
+    SignalFlags,
+    SignalAction,
     MIN_PRIORITY,
 };
 use crate::mm::{
@@ -12,15 +19,15 @@ use crate::mm::{
     translated_str,
     translated_ref,
     translated_refmut,
-    is_mapped,
 };
 use crate::fs::{
+    File,
     open_file,
     OpenFlags,
 };
-use crate::timer::{TimeVal, get_time_val};
+use super::fs::{dir_path, AT_FDCWD};
+use crate::timer::{TimeVal, TimeSpec, Tms, RUsage, get_time, get_time_val, to_clock_ticks, add_timer, remove_timer};
 use crate::config::PAGE_SIZE;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
 use alloc::string::String;
 
@@ -50,7 +57,7 @@ pub fn sys_get_time(buf: usize, _tz: usize) -> isize {
     let end = buf + core::mem::size_of::<TimeVal>();
     while start < end {
         
-        if !is_mapped(current_user_token(), start, MapPermission::U | MapPermission::W) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
             return -1;
         }
         start += PAGE_SIZE;
@@ -62,24 +69,101 @@ pub fn sys_get_time(buf: usize, _tz: usize) -> isize {
 }
 
 
-pub fn sys_getpid() -> isize {
-    current_task().unwrap().pid.0 as isize
+/// Fill in the CPU time of the current process and its children, and
+/// return the clock ticks since boot.
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let mut start = tms as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = tms as usize + core::mem::size_of::<Tms>();
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    let (utime, stime) = inner.cpu_times();
+    *translated_refmut(inner.get_user_token(), tms) = Tms {
+        utime: to_clock_ticks(utime),
+        stime: to_clock_ticks(stime),
+        cutime: to_clock_ticks(inner.cutime),
+        cstime: to_clock_ticks(inner.cstime),
+    };
+    to_clock_ticks(get_time()) as isize
 }
 
 
-pub fn sys_fork() -> isize {
-    let current_task = current_task().unwrap();
-    let new_task = match current_task.fork() {
-        Some(task) => task,
-        None => return -1,
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+/// The CPU time of the current process, of its children waited for, or
+/// of the current thread alone.
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    let mut start = usage as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = usage as usize + core::mem::size_of::<RUsage>();
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+    let process = current_process();
+    let inner = process.acquire_inner_lock();
+    let (utime, stime) = match who {
+        RUSAGE_SELF => inner.cpu_times(),
+        RUSAGE_CHILDREN => (inner.cutime, inner.cstime),
+        RUSAGE_THREAD => {
+            let task = current_task().unwrap();
+            let task_inner = task.acquire_inner_lock();
+            (task_inner.utime, task_inner.stime)
+        }
+        _ => return -1,
     };
-    let new_pid = new_task.pid.0;
+    *translated_refmut(inner.get_user_token(), usage) = RUsage {
+        utime: TimeVal::from_ticks(utime),
+        stime: TimeVal::from_ticks(stime),
+    };
+    0
+}
 
-    let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
-    trap_cx.x[10] = 0;
 
-    add_task(new_task);
-    new_pid as isize
+/// Block the current thread for the time in `req`. Returns -1 if a signal
+/// cuts it short, without writing what is left to `rem`.
+pub fn sys_nanosleep(req: *const TimeSpec, _rem: *mut TimeSpec) -> isize {
+    let mut start = req as usize / PAGE_SIZE * PAGE_SIZE;
+    let end = req as usize + core::mem::size_of::<TimeSpec>();
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::R) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+    let req = *translated_ref(current_user_token(), req);
+    if !req.is_valid() {
+        return -1;
+    }
+    let expire = get_time() + req.to_ticks();
+    let task = prepare_block_current();
+    add_timer(expire, task.clone());
+    if block_current_and_run_next() && remove_timer(expire, &task) {
+        return -1;
+    }
+    0
+}
+
+
+pub fn sys_getpid() -> isize {
+    current_process().getpid() as isize
+}
+
+
+pub fn sys_fork() -> isize {
+    let current_process = current_process();
+    match current_process.fork() {
+        Some(new_process) => new_process.getpid() as isize,
+        None => -1,
+    }
 }
 
 
@@ -94,7 +178,7 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
         let mut start = args as usize / PAGE_SIZE * PAGE_SIZE;
         let end = start + core::mem::size_of::<usize>();
         while start < end {
-            if !is_mapped(token, start, MapPermission::U | MapPermission::R) {
+            if !is_mapped_current(start, MapPermission::U | MapPermission::R) {
                 return -1;
             }
             start += PAGE_SIZE;
@@ -109,11 +193,16 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
         });
         unsafe { args = args.add(1); }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    let base = match dir_path(AT_FDCWD) {
+        Some(base) => base,
+        None => return -1,
+    };
+    if let Some(app_inode) = open_file(base.as_str(), path.as_str(), OpenFlags::RDONLY)
+        .filter(|inode| inode.readable()) {
         let all_data = app_inode.read_all();
-        let task = current_task().unwrap();
+        let process = current_process();
         let argc = args_vec.len();
-        match task.exec(all_data.as_slice(), args_vec) {
+        match process.exec(all_data.as_slice(), args_vec) {
             Ok(_) => argc as isize,
             Err(_) => -1,
         }
@@ -126,38 +215,50 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     let mut start = exit_code_ptr as usize;
     let end = start + core::mem::size_of::<i32>();
     while start < end {
-        if !is_mapped(current_user_token(), start, MapPermission::U | MapPermission::W) {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
             return -3;
         }
         start += PAGE_SIZE;
     }
 
-    let task = current_task().unwrap();
-
-    let mut inner = task.acquire_inner_lock();
-    if inner.children
-        .iter()
-        .find(|p| { pid == -1 || pid as usize == p.getpid() })
-        .is_none() {
-        return -1;
-    }
-    let pair = inner.children
-        .iter()
-        .enumerate()
-        .find(|(_, p)| {
-            p.acquire_inner_lock().is_zombie() && (pid == -1 || pid as usize == p.getpid())
-        });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
+    let process = current_process();
 
-        assert_eq!(Arc::strong_count(&child), 1);
-        
-        let found_pid = child.getpid();
-        let exit_code = child.acquire_inner_lock().exit_code;
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
+    loop {
+        let mut inner = process.acquire_inner_lock();
+        if inner.children
+            .iter()
+            .find(|p| { pid == -1 || pid as usize == p.getpid() })
+            .is_none() {
+            return -1;
+        }
+        let pair = inner.children
+            .iter()
+            .enumerate()
+            .find(|(_, p)| {
+                // set by the last thread of the child as it goes, which may
+                // still hold the child for a while on another hart
+                p.acquire_inner_lock().is_zombie
+                    && (pid == -1 || pid as usize == p.getpid())
+            });
+        if let Some((idx, _)) = pair {
+            let child = inner.children.remove(idx);
+            let found_pid = child.getpid();
+            let child_inner = child.acquire_inner_lock();
+            let exit_code = child_inner.exit_code;
+            let (utime, stime) = child_inner.cpu_times();
+            inner.cutime += utime + child_inner.cutime;
+            inner.cstime += stime + child_inner.cstime;
+            drop(child_inner);
+            *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+            return found_pid as isize;
+        }
+        // sleep until a child exits
+        inner.exit_wait_queue.add_current();
+        drop(inner);
+        if block_current_and_run_next() {
+            process.acquire_inner_lock().exit_wait_queue.remove_current();
+            return -1;
+        }
     }
 }
 
@@ -169,20 +270,122 @@ pub fn sys_spawn(path: *const u8) -> isize {
         None => return -1,
     };
 
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    let base = match dir_path(AT_FDCWD) {
+        Some(base) => base,
+        None => return -1,
+    };
+    if let Some(inode) = open_file(base.as_str(), path.as_str(), OpenFlags::RDONLY)
+        .filter(|inode| inode.readable()) {
         let all_data = inode.read_all();
 
-        let current_task = current_task().unwrap();
-        let new_task = match current_task.spawn(all_data.as_slice()) {
-            Some(task) => task,
-            None => return -1,
-        };
-        let new_pid = new_task.pid.0;
-
-        add_task(new_task);
-        new_pid as isize
+        let current_process = current_process();
+        match current_process.spawn(all_data.as_slice()) {
+            Some(new_process) => new_process.getpid() as isize,
+            None => -1,
+        }
     } else {
         -1
     }
 }
         
+
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+fn is_mapped_range(start: usize, len: usize, permission: MapPermission) -> bool {
+    let mut va = start / PAGE_SIZE * PAGE_SIZE;
+    while va < start + len {
+        if !is_mapped_current(va, permission) {
+            return false;
+        }
+        va += PAGE_SIZE;
+    }
+    true
+}
+
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    let signal = match SignalFlags::from_signum(signum) {
+        Some(signal) => signal,
+        None => return -1,
+    };
+    if let Some(process) = pid2process(pid) {
+        let mut inner = process.acquire_inner_lock();
+        inner.signals |= signal;
+        // the blocked threads give up waiting to take it
+        if !inner.signal_mask.contains(signal) {
+            for task in inner.tasks.iter().flatten() {
+                wakeup_task(task.clone());
+            }
+        }
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_sigaction(signum: usize, action: *const SignalAction, old_action: *mut SignalAction) -> isize {
+    let signal = match SignalFlags::from_signum(signum) {
+        Some(signal) => signal,
+        None => return -1,
+    };
+    if SignalFlags::unmaskable().contains(signal) {
+        return -1;
+    }
+    let size = core::mem::size_of::<SignalAction>();
+    if (!action.is_null() && !is_mapped_range(action as usize, size, MapPermission::U | MapPermission::R))
+        || (!old_action.is_null() && !is_mapped_range(old_action as usize, size, MapPermission::U | MapPermission::W)) {
+        return -1;
+    }
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.acquire_inner_lock();
+    if !old_action.is_null() {
+        *translated_refmut(token, old_action) = inner.signal_actions.table[signum];
+    }
+    if !action.is_null() {
+        let mut action = *translated_ref(token, action);
+        action.mask.remove(SignalFlags::unmaskable());
+        inner.signal_actions.table[signum] = action;
+    }
+    0
+}
+
+pub fn sys_sigprocmask(how: usize, set: *const u32, old_set: *mut u32) -> isize {
+    let size = core::mem::size_of::<u32>();
+    if (!set.is_null() && !is_mapped_range(set as usize, size, MapPermission::U | MapPermission::R))
+        || (!old_set.is_null() && !is_mapped_range(old_set as usize, size, MapPermission::U | MapPermission::W)) {
+        return -1;
+    }
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.acquire_inner_lock();
+    if !old_set.is_null() {
+        *translated_refmut(token, old_set) = inner.signal_mask.bits();
+    }
+    if !set.is_null() {
+        let set = SignalFlags::from_bits_truncate(*translated_ref(token, set))
+            - SignalFlags::unmaskable();
+        match how {
+            SIG_BLOCK => inner.signal_mask |= set,
+            SIG_UNBLOCK => inner.signal_mask -= set,
+            SIG_SETMASK => inner.signal_mask = set,
+            _ => return -1,
+        }
+    }
+    0
+}
+
+pub fn sys_sigreturn() -> isize {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.acquire_inner_lock();
+    let mut task_inner = task.acquire_inner_lock();
+    match process_inner.signal_return(&mut task_inner) {
+        // the syscall result goes to a0, which has to keep the value
+        // it had when the handler was entered
+        Ok(_) => task_inner.get_trap_cx().x[10] as isize,
+        Err(_) => -1,
+    }
+}
@@ -0,0 +1,108 @@
+use crate::task::current_process;
+use crate::sync::{Mutex, MutexSpin, MutexBlocking, Semaphore, Condvar};
+use alloc::sync::Arc;
+
+/// Create a mutex that blocks the threads waiting for it, or lets them
+/// spin if `blocking` is 0. Returns its id.
+pub fn sys_mutex_create(blocking: usize) -> isize {
+    let mutex: Arc<dyn Mutex> = if blocking == 0 {
+        Arc::new(MutexSpin::new())
+    } else {
+        Arc::new(MutexBlocking::new())
+    };
+    let process = current_process();
+    let mut process_inner = process.acquire_inner_lock();
+    process_inner.mutex_list.push(Some(mutex));
+    (process_inner.mutex_list.len() - 1) as isize
+}
+
+fn get_mutex(mutex_id: usize) -> Option<Arc<dyn Mutex>> {
+    let process = current_process();
+    let process_inner = process.acquire_inner_lock();
+    process_inner.mutex_list.get(mutex_id).and_then(|mutex| mutex.clone())
+}
+
+pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+    match get_mutex(mutex_id) {
+        Some(mutex) => match mutex.lock() {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+    match get_mutex(mutex_id).map(|mutex| mutex.unlock()) {
+        Some(Ok(_)) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_semaphore_create(res_count: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.acquire_inner_lock();
+    process_inner.semaphore_list.push(Some(Arc::new(Semaphore::new(res_count))));
+    (process_inner.semaphore_list.len() - 1) as isize
+}
+
+fn get_semaphore(sem_id: usize) -> Option<Arc<Semaphore>> {
+    let process = current_process();
+    let process_inner = process.acquire_inner_lock();
+    process_inner.semaphore_list.get(sem_id).and_then(|sem| sem.clone())
+}
+
+pub fn sys_semaphore_up(sem_id: usize) -> isize {
+    match get_semaphore(sem_id) {
+        Some(sem) => {
+            sem.up();
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_semaphore_down(sem_id: usize) -> isize {
+    match get_semaphore(sem_id) {
+        Some(sem) => match sem.down() {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+pub fn sys_condvar_create() -> isize {
+    let process = current_process();
+    let mut process_inner = process.acquire_inner_lock();
+    process_inner.condvar_list.push(Some(Arc::new(Condvar::new())));
+    (process_inner.condvar_list.len() - 1) as isize
+}
+
+fn get_condvar(condvar_id: usize) -> Option<Arc<Condvar>> {
+    let process = current_process();
+    let process_inner = process.acquire_inner_lock();
+    process_inner.condvar_list.get(condvar_id).and_then(|condvar| condvar.clone())
+}
+
+pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+    match get_condvar(condvar_id) {
+        Some(condvar) => {
+            condvar.signal();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Wait on a condition variable with the mutex `mutex_id` held, which is
+/// released while waiting and held again on return.
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    match (get_condvar(condvar_id), get_mutex(mutex_id)) {
+        (Some(condvar), Some(mutex)) => match condvar.wait(mutex) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        _ => -1,
+    }
+}
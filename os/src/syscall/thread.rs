@@ -0,0 +1,96 @@
+use crate::task::{
+    current_task,
+    current_process,
+    is_mapped_current,
+    block_current_and_run_next,
+    add_task,
+    TaskControlBlock,
+    TaskUserRes,
+};
+use crate::trap::{TrapContext, trap_handler};
+use crate::mm::{KERNEL_SPACE, MapPermission, translated_refmut};
+use crate::config::PAGE_SIZE;
+use alloc::sync::Arc;
+
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    let process = current_process();
+    if process.acquire_inner_lock().exiting {
+        return -1;
+    }
+    let res = match TaskUserRes::new(&process) {
+        Some(res) => res,
+        None => return -1,
+    };
+    let tid = res.tid;
+    let ustack_top = res.ustack_top();
+    let new_task = match TaskControlBlock::new(&process, res) {
+        Some(task) => Arc::new(task),
+        None => return -1,
+    };
+    let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
+    *trap_cx = TrapContext::app_init_context(
+        entry,
+        ustack_top,
+        KERNEL_SPACE.lock().token(),
+        new_task.kernel_stack.get_top(),
+        trap_handler as usize,
+    );
+    trap_cx.x[10] = arg;
+
+    let mut process_inner = process.acquire_inner_lock();
+    while process_inner.tasks.len() <= tid {
+        process_inner.tasks.push(None);
+    }
+    process_inner.tasks[tid] = Some(new_task.clone());
+    process_inner.memory_set.make_swappable();
+    drop(process_inner);
+
+    add_task(new_task);
+    tid as isize
+}
+
+pub fn sys_gettid() -> isize {
+    current_task().unwrap().gettid() as isize
+}
+
+/// Wait for thread `tid` of the current process to exit and reap it.
+/// Returns `tid`, or -1 if there is no such thread other than the caller or
+/// a signal comes first.
+pub fn sys_waittid(tid: usize, exit_code_ptr: *mut i32) -> isize {
+    let mut start = exit_code_ptr as usize;
+    let end = start + core::mem::size_of::<i32>();
+    while start < end {
+        if !is_mapped_current(start, MapPermission::U | MapPermission::W) {
+            return -1;
+        }
+        start += PAGE_SIZE;
+    }
+
+    if current_task().unwrap().gettid() == tid {
+        return -1;
+    }
+    let process = current_process();
+    loop {
+        let mut process_inner = process.acquire_inner_lock();
+        let waited_task = match process_inner.get_task(tid) {
+            Some(task) => task,
+            None => return -1,
+        };
+        let waited_inner = waited_task.acquire_inner_lock();
+        let (exit_code, utime, stime) = (waited_inner.exit_code, waited_inner.utime, waited_inner.stime);
+        drop(waited_inner);
+        if let Some(exit_code) = exit_code {
+            process_inner.tasks[tid] = None;
+            process_inner.utime += utime;
+            process_inner.stime += stime;
+            *translated_refmut(process_inner.get_user_token(), exit_code_ptr) = exit_code;
+            return tid as isize;
+        }
+        process_inner.exit_wait_queue.add_current();
+        drop(process_inner);
+        if block_current_and_run_next() {
+            process.acquire_inner_lock().exit_wait_queue.remove_current();
+            return -1;
+        }
+    }
+}
@@ -19,6 +19,10 @@ impl<T: PartialOrd + Copy> BinaryHeap<T> {
         }
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.data.iter().flatten()
+    }
+
     pub fn insert(&mut self, ele: T) {
         // insert the new element to the tail of array
         let mut cur = self.data.len();
@@ -1,46 +1,79 @@
-use super::TaskControlBlock;
-use super::stride_scheduler::StrideScheduler;
+use super::{TaskControlBlock, ProcessControlBlock, TaskStatus};
+use super::scheduler::{Scheduler, SchedBlock, ActiveScheduler, ActiveSchedBlock};
 use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 use alloc::sync::Arc;
 use spin::Mutex;
 use lazy_static::*;
 
-pub struct TaskManager {
+/// The ready threads, by kernel stack id, and the scheduler choosing
+/// between them.
+pub struct TaskManager<S: Scheduler<Block = ActiveSchedBlock>> {
     map: BTreeMap<usize, Arc<TaskControlBlock>>,
-    scheduler: StrideScheduler,
+    scheduler: S,
+    /// The number of times a thread has been handed out to a hart.
+    switches: usize,
 }
 
-impl TaskManager {
+impl<S: Scheduler<Block = ActiveSchedBlock>> TaskManager<S> {
     pub fn new() -> Self {
         Self {
             map: BTreeMap::new(),
-            scheduler: StrideScheduler::new(),
+            scheduler: S::new(),
+            switches: 0,
         }
     }
 
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        let pid = task.pid.0;
+        let id = task.kernel_stack.0;
         let mut inner = task.acquire_inner_lock();
         if inner.sched_block.is_none() {
-            self.scheduler.init_sched_block(pid);
+            self.scheduler.init_sched_block(id);
         } else {
             self.scheduler.add_sched_block(inner.sched_block.take().unwrap());
         }
         core::mem::drop(inner);
-        self.map.insert(pid, task);
+        self.map.insert(id, task);
     }
 
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        let next_block = self.scheduler.get_next_sched_block()?;
-        let next_task = self.map.remove(&next_block.id).unwrap();
-        next_task.acquire_inner_lock().sched_block = Some(next_block);
-        Some(next_task)
+        loop {
+            let next_block = self.scheduler.get_next_sched_block()?;
+            let next_task = self.map.remove(&next_block.id()).unwrap();
+            let mut inner = next_task.acquire_inner_lock();
+            // the process of the thread has exited while it was waiting
+            if inner.task_status == TaskStatus::Zombie {
+                continue;
+            }
+            inner.sched_block = Some(next_block);
+            inner.task_status = TaskStatus::Running;
+            core::mem::drop(inner);
+            self.switches += 1;
+            return Some(next_task);
+        }
+    }
+
+    pub fn stat(&self) -> SchedStat {
+        SchedStat {
+            policy: S::NAME,
+            ready: self.map.len(),
+            switches: self.switches,
+        }
     }
 }
 
+pub struct SchedStat {
+    pub policy: &'static str,
+    pub ready: usize,
+    pub switches: usize,
+}
+
 
 lazy_static! {
-    pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
+    pub static ref TASK_MANAGER: Mutex<TaskManager<ActiveScheduler>> = Mutex::new(TaskManager::new());
+    /// Every process that has not exited.
+    static ref PID2PROCESS: Mutex<BTreeMap<usize, Arc<ProcessControlBlock>>> = Mutex::new(BTreeMap::new());
 }
 
 
@@ -52,3 +85,29 @@ pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     TASK_MANAGER.lock().fetch()
 }
 
+pub fn sched_stat() -> SchedStat {
+    TASK_MANAGER.lock().stat()
+}
+
+/// What the scheduler keeps about the ready thread with kernel stack `id`.
+/// The caller must not hold the lock of any thread.
+pub fn describe_ready_task(id: usize) -> Option<String> {
+    TASK_MANAGER.lock().scheduler.find_sched_block(id).map(|block| block.describe())
+}
+
+pub fn insert_into_pid2process(process: Arc<ProcessControlBlock>) {
+    PID2PROCESS.lock().insert(process.getpid(), process);
+}
+
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PROCESS.lock().get(&pid).map(|process| process.clone())
+}
+
+pub fn list_pids() -> Vec<usize> {
+    PID2PROCESS.lock().keys().copied().collect()
+}
+
+pub fn remove_from_pid2process(pid: usize) {
+    PID2PROCESS.lock().remove(&pid);
+}
+
@@ -2,81 +2,271 @@ mod binary_heap;
 mod context;
 mod manager;
 mod pid;
+mod process;
 mod processor;
-mod stride_scheduler;
+mod scheduler;
+mod signal;
 mod switch;
 mod task;
+mod wait_queue;
 
 use switch::__switch;
-use task::{TaskControlBlock, TaskStatus};
+use task::TaskControlBlockInner;
 use alloc::sync::Arc;
-use manager::fetch_task;
+use alloc::vec::Vec;
+use manager::{fetch_task, remove_from_pid2process};
 use lazy_static::*;
 use crate::fs::{open_file, OpenFlags, remove_mailbox};
+use crate::smp::tlb_shootdown;
 
 pub use context::TaskContext;
+pub use task::{TaskControlBlock, TaskStatus};
+pub use process::ProcessControlBlock;
 pub use processor::{
     run_tasks,
     current_task,
+    current_process,
     current_user_token,
     current_trap_cx,
-    take_current_task,
+    current_trap_cx_user_va,
     mmap_current,
     munmap_current,
+    mmap_file_current,
+    msync_current,
+    brk_current,
+    handle_page_fault_current,
+    is_mapped_current,
+    pin_page_current,
+    unpin_pages_current,
     set_current_priority,
+    charge_utime_current,
+    charge_stime_current,
     schedule,
 };
-pub use manager::add_task;
-pub use pid::{PidHandle, pid_alloc, KernelStack};
-pub use stride_scheduler::MIN_PRIORITY;
+pub use manager::{
+    add_task,
+    insert_into_pid2process,
+    pid2process,
+    list_pids,
+    sched_stat,
+    describe_ready_task,
+};
+pub use signal::{SignalFlags, SignalAction};
+pub use pid::{PidHandle, pid_alloc, KernelStack, RecycleAllocator, TaskUserRes, map_user_res};
+pub use scheduler::MIN_PRIORITY;
+pub use wait_queue::WaitQueue;
 
 pub fn suspend_current_and_run_next() {
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
 
     let mut task_inner = task.acquire_inner_lock();
     let task_cx_ptr2 = task_inner.get_task_cx_ptr2();
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
 
-    add_task(task);
+    drop(task);
     schedule(task_cx_ptr2);
 }
 
-pub fn exit_current_and_run_next(exit_code: i32) {
-    let task = take_current_task().unwrap();
-    
-    let mut inner = task.acquire_inner_lock();
-    inner.task_status = TaskStatus::Zombie;
-    inner.exit_code = exit_code;
+/// Mark the current thread as about to block and hand it out, for the
+/// caller to keep somewhere until it is passed to `wakeup_task`. This has
+/// to happen before anyone can find it there, as another hart may wake it
+/// up right away.
+pub fn prepare_block_current() -> Arc<TaskControlBlock> {
+    let task = current_task().unwrap();
+    task.acquire_inner_lock().task_status = TaskStatus::Blocking;
+    task
+}
 
-    {
-        let mut initproc_inner = INITPROC.acquire_inner_lock();
-        for child in inner.children.iter() {
-            child.acquire_inner_lock().parent = Some(Arc::downgrade(&INITPROC));
-            initproc_inner.children.push(child.clone());
+/// Give up the CPU without going back to the ready queue, after
+/// `prepare_block_current`. The thread runs again once woken up, or when a
+/// signal is sent to its process. Returns whether a signal is pending then,
+/// for the caller to stop waiting and take itself off where it was kept.
+pub fn block_current_and_run_next() -> bool {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let process_inner = process.acquire_inner_lock();
+    let (exiting, interrupted) = (process_inner.exiting, process_inner.has_pending_signals());
+    drop(process_inner);
+    drop(process);
+    // nobody may be left to wake up a thread of an exiting process
+    if exiting {
+        drop(task);
+        exit_current_and_run_next(0);
+    } else if interrupted {
+        // the signal came before the thread was ready to be woken up by it
+        task.acquire_inner_lock().task_status = TaskStatus::Running;
+        return true;
+    } else {
+        let task_cx_ptr2 = task.acquire_inner_lock().get_task_cx_ptr2();
+        drop(task);
+        schedule(task_cx_ptr2);
+    }
+    signal_pending_current()
+}
+
+/// Whether the current process has a signal for the current thread to
+/// give up waiting for.
+pub fn signal_pending_current() -> bool {
+    let process = current_process();
+    let pending = process.acquire_inner_lock().has_pending_signals();
+    pending
+}
+
+/// Make a blocked thread ready again. Returns false if it is not blocked,
+/// as when its process has exited meanwhile.
+pub fn wakeup_task(task: Arc<TaskControlBlock>) -> bool {
+    let mut task_inner = task.acquire_inner_lock();
+    match task_inner.task_status {
+        TaskStatus::Blocked => {
+            task_inner.task_status = TaskStatus::Ready;
+            drop(task_inner);
+            add_task(task);
+            true
+        }
+        // its hart puts it in the ready queue once it has left
+        TaskStatus::Blocking => {
+            task_inner.task_status = TaskStatus::Ready;
+            true
         }
+        _ => false,
     }
+}
 
-    inner.children.clear();
-    
-    inner.memory_set.recycle_data_pages();
-    remove_mailbox(task.pid.0);
-    drop(inner);
+/// Exit the current thread. When it is the main thread, or the process
+/// has been killed by a signal, the whole process exits with `exit_code`.
+/// Its threads running on other harts leave at their next trap, and the
+/// last thread to go turns the process into a zombie.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
 
+    let mut task_inner = task.acquire_inner_lock();
+    // gone already if the process has ended while the thread was blocking
+    let tid = task_inner.res.as_ref().map(|res| res.tid);
+    task_inner.task_status = TaskStatus::Zombie;
+    task_inner.exit_code = Some(exit_code);
+    let res = task_inner.res.take();
+    let pinned_pages = core::mem::take(&mut task_inner.pinned_pages);
+    drop(task_inner);
+    // giving back the user resources takes the lock of the process
+    drop(res);
+    drop(pinned_pages);
     drop(task);
 
+    let mut process_inner = process.acquire_inner_lock();
+    if !process_inner.exiting && (tid == Some(0) || process_inner.killed.is_some()) {
+        process_inner.exiting = true;
+        process_inner.exit_code = exit_code;
+
+        // the threads that are not running never run again, those that
+        // are still ready to are dropped by the task manager
+        let mut recycle_res = Vec::new();
+        let mut running = false;
+        for task in process_inner.tasks.iter().flatten() {
+            let mut task_inner = task.acquire_inner_lock();
+            if task_inner.task_status == TaskStatus::Running {
+                running = true;
+                continue;
+            }
+            task_inner.task_status = TaskStatus::Zombie;
+            if let Some(res) = task_inner.res.take() {
+                recycle_res.push(res);
+            }
+        }
+        drop(process_inner);
+        // freeing the stacks waits for the TLBs of the running threads
+        drop(recycle_res);
+        // and this drives them into the kernel, where they leave
+        if running {
+            tlb_shootdown();
+        }
+        process_inner = process.acquire_inner_lock();
+    }
+
+    let mut parent = None;
+    let mut children = Vec::new();
+    if !process_inner.exiting {
+        process_inner.exit_wait_queue.wake_all();
+    } else if !process_inner.is_zombie && process_inner.tasks.iter()
+        .flatten()
+        .all(|task| task.acquire_inner_lock().res.is_none()) {
+        process_inner.is_zombie = true;
+        children = core::mem::take(&mut process_inner.children);
+        process_inner.memory_set.recycle_data_pages();
+        process_inner.fd_table.clear();
+        remove_mailbox(process.getpid());
+        remove_from_pid2process(process.getpid());
+        parent = process_inner.parent.clone();
+    }
+    drop(process_inner);
+    drop(process);
+
+    // a parent is always locked before its children
+    if !children.is_empty() {
+        let mut initproc_inner = INITPROC.acquire_inner_lock();
+        for child in children.into_iter() {
+            child.acquire_inner_lock().parent = Some(Arc::downgrade(&INITPROC));
+            initproc_inner.children.push(child);
+        }
+        // some of them may have exited already
+        initproc_inner.exit_wait_queue.wake_all();
+    }
+    // the parent may have found the process a zombie already
+    if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+        parent.acquire_inner_lock().exit_wait_queue.wake_all();
+    }
+
     let _unused: usize = 0;
     schedule(&_unused as *const _);
 }
 
+/// Deliver the pending signals of the current process before the current
+/// thread goes back to user mode. A stopped thread gives up the CPU here
+/// until it is continued or killed.
+pub fn handle_signals() {
+    loop {
+        let task = current_task().unwrap();
+        let process = task.process.upgrade().unwrap();
+        let mut process_inner = process.acquire_inner_lock();
+        let mut task_inner = task.acquire_inner_lock();
+        process_inner.check_pending_signals(&mut task_inner);
+        let (frozen, killed, exiting) = (process_inner.frozen, process_inner.killed, process_inner.exiting);
+        drop(task_inner);
+        drop(process_inner);
+        drop(process);
+        drop(task);
+        if let Some(signum) = killed {
+            exit_current_and_run_next(-(signum as i32));
+        }
+        // another thread has ended the process
+        if exiting {
+            exit_current_and_run_next(0);
+        }
+        if !frozen {
+            break;
+        }
+        suspend_current_and_run_next();
+    }
+}
+
+pub fn force_signal_current(signal: SignalFlags) {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.acquire_inner_lock();
+    let task_inner = task.acquire_inner_lock();
+    process_inner.force_signal(signal, &task_inner);
+}
+
 lazy_static! {
-    pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+    pub static ref INITPROC: Arc<ProcessControlBlock> = {
+        let inode = open_file("/", "initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        TaskControlBlock::new(v.as_slice()).unwrap()
-    });
+        ProcessControlBlock::new(v.as_slice()).unwrap()
+    };
 }
 
 pub fn add_initproc() {
-    add_task(INITPROC.clone());
+    // creating it puts its main thread in the ready queue
+    let _initproc = INITPROC.clone();
 }
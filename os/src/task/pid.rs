@@ -1,48 +1,56 @@
 use alloc::vec::Vec;
+use alloc::sync::{Arc, Weak};
 use lazy_static::*;
 use spin::Mutex;
-use crate::mm::{KERNEL_SPACE, MapPermission, VirtAddr};
+use crate::mm::{KERNEL_SPACE, MemorySet, MapPermission, PhysPageNum, VirtAddr};
 use crate::config::{
     PAGE_SIZE,
     TRAMPOLINE,
+    TRAP_CONTEXT,
     KERNEL_STACK_SIZE,
+    USER_STACK_SIZE,
 };
+use super::ProcessControlBlock;
 
-struct PidAllocator {
+/// Hands out the smallest ids that are free again first, used for pids,
+/// kernel stacks and the tids within a process.
+#[derive(Clone)]
+pub struct RecycleAllocator {
     current: usize,
     recycled: Vec<usize>,
 }
 
-impl PidAllocator {
+impl RecycleAllocator {
     pub fn new() -> Self {
-        PidAllocator {
+        RecycleAllocator {
             current: 0,
             recycled: Vec::new(),
         }
     }
 
-    pub fn alloc(&mut self) -> PidHandle {
-        if let Some(pid) = self.recycled.pop() {
-            PidHandle(pid)
+    pub fn alloc(&mut self) -> usize {
+        if let Some(id) = self.recycled.pop() {
+            id
         } else {
             self.current += 1;
-            PidHandle(self.current - 1)
+            self.current - 1
         }
     }
 
-    pub fn dealloc(&mut self, pid: usize) {
-        assert!(pid < self.current);
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
         assert!(
-            self.recycled.iter().find(|ppid| **ppid == pid).is_none(),
-            "pid {} has been deallocated!", pid
+            self.recycled.iter().find(|i| **i == id).is_none(),
+            "id {} has been deallocated!", id
         );
-        self.recycled.push(pid);
+        self.recycled.push(id);
     }
 }
 
 
 lazy_static! {
-    static ref PID_ALLOCATOR: Mutex<PidAllocator> = Mutex::new(PidAllocator::new());
+    static ref PID_ALLOCATOR: Mutex<RecycleAllocator> = Mutex::new(RecycleAllocator::new());
+    static ref KSTACK_ALLOCATOR: Mutex<RecycleAllocator> = Mutex::new(RecycleAllocator::new());
 }
 
 
@@ -55,24 +63,24 @@ impl Drop for PidHandle {
 }
 
 pub fn pid_alloc() -> PidHandle {
-    PID_ALLOCATOR.lock().alloc()
+    PidHandle(PID_ALLOCATOR.lock().alloc())
 }
 
 
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
-    let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - kstack_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
 
-pub struct KernelStack {
-    pid: usize,
-}
+/// The kernel stack of a thread. Each one gets an id of its own, which
+/// also identifies the thread to the scheduler.
+pub struct KernelStack(pub usize);
 
 impl KernelStack {
-    pub fn new(pid_handle: &PidHandle) -> Option<Self> {
-        let pid = pid_handle.0;
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
+    pub fn new() -> Option<Self> {
+        let kstack_id = KSTACK_ALLOCATOR.lock().alloc();
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(kstack_id);
         match KERNEL_SPACE
             .lock()
             .insert_framed_area(
@@ -81,11 +89,12 @@ impl KernelStack {
                 MapPermission::R | MapPermission::W,
             ) {
             Ok(_) => (),
-            Err(_) => return None,
+            Err(_) => {
+                KSTACK_ALLOCATOR.lock().dealloc(kstack_id);
+                return None;
+            }
         };
-        Some(KernelStack {
-            pid: pid_handle.0,
-        })
+        Some(KernelStack(kstack_id))
     }
 
     pub fn push_on_top<T>(&self, value: T) -> *mut T where
@@ -97,17 +106,109 @@ impl KernelStack {
     }
 
     pub fn get_top(&self) -> usize {
-        let (_, kernel_stack_top) = kernel_stack_position(self.pid);
+        let (_, kernel_stack_top) = kernel_stack_position(self.0);
         kernel_stack_top
     }
 }
 
 impl Drop for KernelStack {
     fn drop(&mut self) {
-        let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
         let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
         KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        KSTACK_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+
+/// Every thread has a slot below the trampoline holding its trap context
+/// and, after a guard page, its user stack.
+const THREAD_SLOT_SIZE: usize = PAGE_SIZE + PAGE_SIZE + USER_STACK_SIZE;
+
+fn trap_cx_bottom_from_tid(tid: usize) -> usize {
+    TRAP_CONTEXT - tid * THREAD_SLOT_SIZE
+}
+
+fn ustack_top_from_tid(tid: usize) -> usize {
+    trap_cx_bottom_from_tid(tid) - PAGE_SIZE
+}
+
+/// Map the trap context and user stack of thread `tid` into `memory_set`.
+pub fn map_user_res(memory_set: &mut MemorySet, tid: usize) -> Result<(), ()> {
+    let ustack_top = ustack_top_from_tid(tid);
+    memory_set.insert_framed_area(
+        (ustack_top - USER_STACK_SIZE).into(),
+        ustack_top.into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    )?;
+    let trap_cx_bottom = trap_cx_bottom_from_tid(tid);
+    memory_set.insert_framed_area(
+        trap_cx_bottom.into(),
+        (trap_cx_bottom + PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W,
+    )
+}
+
+/// The tid of a thread, which also owns the trap context and user stack
+/// that go with it in the address space of its process. Both are given
+/// back when it is dropped.
+pub struct TaskUserRes {
+    pub tid: usize,
+    pub process: Weak<ProcessControlBlock>,
+}
+
+impl TaskUserRes {
+    pub fn new(process: &Arc<ProcessControlBlock>) -> Option<Self> {
+        let mut process_inner = process.acquire_inner_lock();
+        let tid = process_inner.alloc_tid();
+        let result = map_user_res(&mut process_inner.memory_set, tid);
+        drop(process_inner);
+        let res = TaskUserRes {
+            tid,
+            process: Arc::downgrade(process),
+        };
+        match result {
+            Ok(_) => Some(res),
+            Err(_) => None,
+        }
+    }
+
+    /// A `TaskUserRes` for a `tid` that is already allocated and mapped, as
+    /// in a child right after `fork`.
+    pub fn existing(process: &Arc<ProcessControlBlock>, tid: usize) -> Self {
+        TaskUserRes {
+            tid,
+            process: Arc::downgrade(process),
+        }
+    }
+
+    pub fn trap_cx_user_va(&self) -> usize {
+        trap_cx_bottom_from_tid(self.tid)
+    }
+
+    pub fn trap_cx_ppn(&self, memory_set: &MemorySet) -> PhysPageNum {
+        let trap_cx_bottom_va: VirtAddr = self.trap_cx_user_va().into();
+        memory_set.translate(trap_cx_bottom_va.into()).unwrap().ppn()
+    }
+
+    pub fn ustack_top(&self) -> usize {
+        ustack_top_from_tid(self.tid)
+    }
+}
+
+impl Drop for TaskUserRes {
+    fn drop(&mut self) {
+        let process = match self.process.upgrade() {
+            Some(process) => process,
+            None => return,
+        };
+        let mut process_inner = process.acquire_inner_lock();
+        let ustack_bottom_va: VirtAddr = (self.ustack_top() - USER_STACK_SIZE).into();
+        process_inner.memory_set.remove_area_with_start_vpn(ustack_bottom_va.into());
+        let trap_cx_bottom_va: VirtAddr = self.trap_cx_user_va().into();
+        process_inner.memory_set.remove_area_with_start_vpn(trap_cx_bottom_va.into());
+        process_inner.dealloc_tid(self.tid);
     }
 }
@@ -0,0 +1,419 @@
+use crate::mm::{
+    MemorySet,
+    KERNEL_SPACE,
+    VirtAddr,
+    MapPermission,
+    translated_refmut,
+};
+use crate::trap::{TrapContext, trap_handler};
+use super::{TaskControlBlock, TaskControlBlockInner, TaskUserRes};
+use super::{PidHandle, pid_alloc, RecycleAllocator, map_user_res};
+use super::{add_task, insert_into_pid2process, WaitQueue};
+use super::signal::{SignalFlags, SignalActions, DefaultAction, SIG_DFL, SIG_IGN, MAX_SIG};
+use alloc::sync::{Weak, Arc};
+use alloc::vec;
+use alloc::vec::Vec;
+use alloc::string::String;
+use spin::{Mutex, MutexGuard};
+use crate::fs::{File, Stdin, Stdout, Mailbox};
+use crate::sync::{self, Semaphore, Condvar};
+use crate::fs::VfsInode;
+
+/// What the threads of a process share: the address space, open files
+/// and signal state.
+pub struct ProcessControlBlock {
+    pub pid: PidHandle,
+    inner: Mutex<ProcessControlBlockInner>,
+}
+
+pub struct ProcessControlBlockInner {
+    /// Set by the last thread to exit, once the process is left for the
+    /// parent to reap.
+    pub is_zombie: bool,
+    pub memory_set: MemorySet,
+    pub parent: Option<Weak<ProcessControlBlock>>,
+    pub children: Vec<Arc<ProcessControlBlock>>,
+    pub exit_code: i32,
+    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    pub mailbox: Arc<Mailbox>,
+    pub cwd: String,
+    /// Signals sent to the process and not delivered yet.
+    pub signals: SignalFlags,
+    pub signal_mask: SignalFlags,
+    pub signal_actions: SignalActions,
+    /// Stopped by a signal until SIGCONT.
+    pub frozen: bool,
+    /// The signal that terminates the process, once delivered.
+    pub killed: Option<usize>,
+    /// Set once the process exits. Its threads still running leave at
+    /// their next trap, and it is a zombie when the last one is gone.
+    pub exiting: bool,
+    /// The threads, indexed by tid. An entry is cleared once the thread
+    /// has exited and been waited for.
+    pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
+    pub task_res_allocator: RecycleAllocator,
+    /// Threads waiting for a child process or another thread to exit.
+    pub exit_wait_queue: WaitQueue,
+    /// The synchronisation primitives of the process, indexed by the ids
+    /// handed out by the create syscalls.
+    pub mutex_list: Vec<Option<Arc<dyn sync::Mutex>>>,
+    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
+    pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// The CPU time of the threads reaped already, in ticks of `get_time`.
+    pub utime: usize,
+    pub stime: usize,
+    /// The CPU time of the children waited for, and of theirs.
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+
+impl ProcessControlBlockInner {
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.token()
+    }
+
+    pub fn insert_lazy_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> Result<(), ()> {
+        self.memory_set.insert_lazy_area(start_va, end_va, permission)
+    }
+
+    pub fn insert_file_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        inode: Arc<dyn VfsInode>,
+        offset: usize,
+        shared: bool,
+    ) -> Result<(), ()> {
+        self.memory_set.insert_file_area(start_va, end_va, permission, inode, offset, shared)
+    }
+
+    pub fn set_brk(&mut self, new_brk: usize) -> usize {
+        self.memory_set.set_brk(new_brk)
+    }
+
+    pub fn msync(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> Result<(), ()> {
+        self.memory_set.msync(start_va, end_va)
+    }
+
+    pub fn unmap_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> Result<(), ()> {
+        if !self.memory_set.is_area_mapped(start_va, end_va, MapPermission::U) {
+            return Err(());
+        }
+        self.memory_set.unmap_framed_area(start_va, end_va);
+        Ok(())
+    }
+
+    pub fn handle_page_fault(&mut self, va: VirtAddr, write: bool) -> bool {
+        self.memory_set.handle_page_fault(va, write)
+    }
+
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = (0..self.fd_table.len())
+            .find(|fd| self.fd_table[*fd].is_none()) {
+            fd
+        } else {
+            self.fd_table.push(None);
+            self.fd_table.len() - 1
+        }
+    }
+
+    pub fn alloc_tid(&mut self) -> usize {
+        self.task_res_allocator.alloc()
+    }
+
+    pub fn dealloc_tid(&mut self, tid: usize) {
+        self.task_res_allocator.dealloc(tid)
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+
+    pub fn get_task(&self, tid: usize) -> Option<Arc<TaskControlBlock>> {
+        self.tasks.get(tid).and_then(|task| task.clone())
+    }
+
+    /// The user and kernel time of all the threads of the process, both
+    /// those reaped and those still around.
+    pub fn cpu_times(&self) -> (usize, usize) {
+        self.tasks.iter().flatten().fold((self.utime, self.stime), |(utime, stime), task| {
+            let task_inner = task.acquire_inner_lock();
+            (utime + task_inner.utime, stime + task_inner.stime)
+        })
+    }
+
+    /// Deliver the pending signals that are not blocked, on behalf of the
+    /// thread `task_inner` on its way back to user mode. Default actions
+    /// are carried out here; a user handler is entered by redirecting the
+    /// trap context of the thread, and only one of them runs at a time.
+    pub fn check_pending_signals(&mut self, task_inner: &mut TaskControlBlockInner) {
+        for signum in 1..=MAX_SIG {
+            let signal = SignalFlags::from_signum(signum).unwrap();
+            if !self.signals.contains(signal) || self.signal_mask.contains(signal) {
+                continue;
+            }
+            // SIGCONT resumes the process whatever else it is set to do
+            if signal == SignalFlags::SIGCONT {
+                self.frozen = false;
+            }
+            let action = self.signal_actions.table[signum];
+            if action.handler == SIG_DFL {
+                self.signals.remove(signal);
+                match signal.default_action() {
+                    DefaultAction::Terminate => self.killed = Some(signum),
+                    DefaultAction::Stop => self.frozen = true,
+                    DefaultAction::Continue | DefaultAction::Ignore => {}
+                }
+            } else if action.handler == SIG_IGN {
+                self.signals.remove(signal);
+            } else if task_inner.signal_backup.is_none() && !self.frozen {
+                self.signals.remove(signal);
+                let trap_cx = task_inner.get_trap_cx();
+                task_inner.signal_backup = Some((*trap_cx, self.signal_mask));
+                self.signal_mask |= action.mask | signal;
+                trap_cx.sepc = action.handler;
+                trap_cx.x[10] = signum;
+            }
+        }
+    }
+
+    /// Whether a signal is to be delivered or has terminated the process,
+    /// which interrupts the blocking syscalls of its threads.
+    pub fn has_pending_signals(&self) -> bool {
+        self.killed.is_some() || !(self.signals - self.signal_mask).is_empty()
+    }
+
+    /// Raise a signal caused by the thread `task_inner` itself, such as a
+    /// fault. If it cannot be handled right away, returning to the thread
+    /// would only fault again, so the process is terminated instead.
+    pub fn force_signal(&mut self, signal: SignalFlags, task_inner: &TaskControlBlockInner) {
+        let handler = self.signal_actions.table[signal.signum()].handler;
+        if self.signal_mask.contains(signal) || handler == SIG_IGN || task_inner.signal_backup.is_some() {
+            self.killed = Some(signal.signum());
+        } else {
+            self.signals |= signal;
+        }
+    }
+
+    /// Leave the user signal handler the thread `task_inner` runs, going
+    /// back to where it was interrupted.
+    pub fn signal_return(&mut self, task_inner: &mut TaskControlBlockInner) -> Result<(), ()> {
+        let (trap_cx, signal_mask) = match task_inner.signal_backup.take() {
+            Some(backup) => backup,
+            None => return Err(()),
+        };
+        self.signal_mask = signal_mask;
+        *task_inner.get_trap_cx() = trap_cx;
+        Ok(())
+    }
+}
+
+impl ProcessControlBlock {
+    pub fn acquire_inner_lock(&self) -> MutexGuard<ProcessControlBlockInner> {
+        self.inner.lock()
+    }
+
+    fn new_inner(memory_set: MemorySet, mailbox: Arc<Mailbox>) -> ProcessControlBlockInner {
+        ProcessControlBlockInner {
+            is_zombie: false,
+            memory_set,
+            parent: None,
+            children: Vec::new(),
+            exit_code: 0,
+            fd_table: vec![
+                Some(Arc::new(Stdin)),
+                Some(Arc::new(Stdout)),
+                Some(Arc::new(Stdout)),
+            ],
+            mailbox,
+            cwd: String::from("/"),
+            signals: SignalFlags::empty(),
+            signal_mask: SignalFlags::empty(),
+            signal_actions: SignalActions::default(),
+            frozen: false,
+            killed: None,
+            exiting: false,
+            tasks: Vec::new(),
+            task_res_allocator: RecycleAllocator::new(),
+            exit_wait_queue: WaitQueue::new(),
+            mutex_list: Vec::new(),
+            semaphore_list: Vec::new(),
+            condvar_list: Vec::new(),
+            utime: 0,
+            stime: 0,
+            cutime: 0,
+            cstime: 0,
+        }
+    }
+
+    /// Create a process running `elf_data` in its main thread, which is
+    /// ready to be scheduled when this returns.
+    pub fn new(elf_data: &[u8]) -> Option<Arc<Self>> {
+        let (memory_set, entry_point) = MemorySet::from_elf(elf_data)?;
+        let pid_handle = pid_alloc();
+        let mailbox = Mailbox::new(pid_handle.0);
+        let process = Arc::new(Self {
+            pid: pid_handle,
+            inner: Mutex::new(Self::new_inner(memory_set, mailbox)),
+        });
+
+        let res = TaskUserRes::new(&process)?;
+        let ustack_top = res.ustack_top();
+        let task = Arc::new(TaskControlBlock::new(&process, res)?);
+        let trap_cx = task.acquire_inner_lock().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            ustack_top,
+            KERNEL_SPACE.lock().token(),
+            task.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        let mut process_inner = process.acquire_inner_lock();
+        process_inner.tasks.push(Some(task.clone()));
+        process_inner.memory_set.make_swappable();
+        drop(process_inner);
+
+        insert_into_pid2process(process.clone());
+        add_task(task);
+        Some(process)
+    }
+
+    /// Replace the image of a single-threaded process with `elf_data`,
+    /// keeping its thread.
+    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) -> Result<(), ()> {
+        let task = {
+            let inner = self.acquire_inner_lock();
+            if inner.thread_count() != 1 {
+                return Err(());
+            }
+            inner.tasks.iter().find_map(|task| task.clone()).unwrap()
+        };
+        let (mut memory_set, entry_point) = match MemorySet::from_elf(elf_data) {
+            Some(x) => x,
+            None => return Err(()),
+        };
+        let task_inner = task.acquire_inner_lock();
+        let res = task_inner.res.as_ref().unwrap();
+        map_user_res(&mut memory_set, res.tid)?;
+        let trap_cx_ppn = res.trap_cx_ppn(&memory_set);
+        let mut user_sp = res.ustack_top();
+        drop(task_inner);
+
+        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
+        let argv_base = user_sp;
+        let mut argv: Vec<_> = (0..=args.len())
+            .map(|arg| {
+                translated_refmut(
+                    memory_set.token(),
+                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
+                )
+            })
+            .collect();
+        *argv[args.len()] = 0;
+        for i in 0..args.len() {
+            user_sp -= args[i].len() + 1;
+            *argv[i] = user_sp;
+            let mut p = user_sp;
+            for c in args[i].as_bytes() {
+                *translated_refmut(memory_set.token(), p as *mut u8) = *c;
+                p += 1;
+            }
+            *translated_refmut(memory_set.token(), p as *mut u8) = 0;
+        }
+        user_sp -= user_sp % core::mem::size_of::<usize>();
+        // the arguments are in place, the pages may leave memory now
+        memory_set.make_swappable();
+
+        let mut inner = self.acquire_inner_lock();
+        inner.memory_set = memory_set;
+        // the handlers are gone with the old image
+        for action in inner.signal_actions.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = Default::default();
+            }
+        }
+        drop(inner);
+
+        let mut task_inner = task.acquire_inner_lock();
+        task_inner.trap_cx_ppn = trap_cx_ppn;
+        task_inner.signal_backup = None;
+        let mut trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.lock().token(),
+            task.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = args.len();
+        trap_cx.x[11] = argv_base;
+        *task_inner.get_trap_cx() = trap_cx;
+
+        Ok(())
+    }
+
+    /// Duplicate a single-threaded process. The child, whose thread is
+    /// ready to be scheduled, returns 0 from the syscall.
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
+        let mut parent_inner = self.acquire_inner_lock();
+        if parent_inner.thread_count() != 1 {
+            return None;
+        }
+        let tid = parent_inner.tasks.iter().position(|task| task.is_some()).unwrap();
+        let memory_set = MemorySet::from_existed_user(
+            &mut parent_inner.memory_set
+        )?;
+        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
+        for fd in parent_inner.fd_table.iter() {
+            if let Some(file) = fd {
+                new_fd_table.push(Some(file.clone()));
+            } else {
+                new_fd_table.push(None);
+            }
+        }
+        let pid_handle = pid_alloc();
+        let mailbox = Mailbox::new(pid_handle.0);
+        let mut inner = Self::new_inner(memory_set, mailbox);
+        inner.parent = Some(Arc::downgrade(self));
+        inner.fd_table = new_fd_table;
+        inner.cwd = parent_inner.cwd.clone();
+        inner.signal_mask = parent_inner.signal_mask;
+        inner.signal_actions = parent_inner.signal_actions.clone();
+        inner.task_res_allocator = parent_inner.task_res_allocator.clone();
+        inner.tasks = vec![None; tid + 1];
+        let child = Arc::new(Self {
+            pid: pid_handle,
+            inner: Mutex::new(inner),
+        });
+        drop(parent_inner);
+
+        // the trap context and user stack of the thread are copied already
+        let res = TaskUserRes::existing(&child, tid);
+        let task = Arc::new(TaskControlBlock::new(&child, res)?);
+        let trap_cx = task.acquire_inner_lock().get_trap_cx();
+        trap_cx.kernel_sp = task.kernel_stack.get_top();
+        trap_cx.x[10] = 0;
+        child.acquire_inner_lock().tasks[tid] = Some(task.clone());
+        self.acquire_inner_lock().children.push(child.clone());
+
+        insert_into_pid2process(child.clone());
+        add_task(task);
+        Some(child)
+    }
+
+    pub fn spawn(self: &Arc<Self>, elf_data: &[u8]) -> Option<Arc<Self>> {
+        let child = Self::new(elf_data)?;
+        let mut parent_inner = self.acquire_inner_lock();
+        parent_inner.children.push(child.clone());
+        let mut child_inner = child.acquire_inner_lock();
+        child_inner.parent = Some(Arc::downgrade(self));
+        child_inner.cwd = parent_inner.cwd.clone();
+        drop(child_inner);
+        Some(child)
+    }
+
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+}
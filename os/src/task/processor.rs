@@ -1,12 +1,20 @@
-use super::TaskControlBlock;
+use super::{TaskControlBlock, ProcessControlBlock};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::cell::RefCell;
 use lazy_static::*;
-use super::{fetch_task, TaskStatus};
+use super::{fetch_task, add_task, TaskStatus};
+use super::scheduler::SchedBlock;
 use super::__switch;
+use crate::config::MAX_HARTS;
+use crate::smp::hart_id;
 use crate::trap::TrapContext;
-use crate::mm::{VirtAddr, MapPermission};
+use crate::mm::{VirtAddr, MapPermission, PinnedPage, is_mapped};
+use crate::fs::poll_stdin;
+use crate::timer::{check_timer, get_time};
+use crate::fs::VfsInode;
 
+/// What a hart runs. Only the hart itself touches its `Processor`.
 pub struct Processor {
     inner: RefCell<ProcessorInner>,
 }
@@ -37,19 +45,42 @@ impl Processor {
         loop {
             if let Some(task) = fetch_task() {
                 let idle_task_cx_ptr2 = self.get_idle_task_cx_ptr2();
-
                 let mut task_inner = task.acquire_inner_lock();
                 let next_task_cx_ptr2 = task_inner.get_task_cx_ptr2();
-                task_inner.task_status = TaskStatus::Running;
+                task_inner.time_stamp = get_time();
                 drop(task_inner);
 
                 self.inner.borrow_mut().current = Some(task);
                 unsafe {
+                    // the kernel stack of the thread may have been mapped
+                    // anew since this hart last flushed its TLB
+                    llvm_asm!("sfence.vma" :::: "volatile");
                     __switch(
                         idle_task_cx_ptr2,
                         next_task_cx_ptr2,
                     );
                 }
+
+                // the thread has saved its context and left this hart, it
+                // is only now safe for another hart to pick it up
+                let task = self.take_current().unwrap();
+                let mut task_inner = task.acquire_inner_lock();
+                task_inner.charge_stime();
+                if let Some(sched_block) = task_inner.sched_block.as_mut() {
+                    sched_block.descheduled();
+                }
+                match task_inner.task_status {
+                    TaskStatus::Ready => {
+                        drop(task_inner);
+                        add_task(task);
+                    }
+                    TaskStatus::Blocking => task_inner.task_status = TaskStatus::Blocked,
+                    _ => {}
+                }
+            } else {
+                // every thread may be blocked, sleeping or waiting for input
+                check_timer();
+                poll_stdin();
             }
         }
     }
@@ -65,24 +96,28 @@ impl Processor {
 
 
 lazy_static! {
-    pub static ref PROCESSOR: Processor = Processor::new();
+    static ref PROCESSORS: Vec<Processor> = (0..MAX_HARTS).map(|_| Processor::new()).collect();
 }
 
-pub fn run_tasks() {
-    PROCESSOR.run();
+fn current_processor() -> &'static Processor {
+    &PROCESSORS[hart_id()]
 }
 
-pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.take_current()
+pub fn run_tasks() {
+    current_processor().run();
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.current()
+    current_processor().current()
+}
+
+pub fn current_process() -> Arc<ProcessControlBlock> {
+    current_task().unwrap().process.upgrade().unwrap()
 }
 
 pub fn current_user_token() -> usize {
-    let task = current_task().unwrap();
-    let token = task.acquire_inner_lock().get_user_token();
+    let process = current_process();
+    let token = process.acquire_inner_lock().get_user_token();
     token
 }
 
@@ -90,15 +125,90 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
     current_task().unwrap().acquire_inner_lock().get_trap_cx()
 }
 
+pub fn current_trap_cx_user_va() -> usize {
+    current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .res
+        .as_ref()
+        .unwrap()
+        .trap_cx_user_va()
+}
+
 pub fn mmap_current(start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> Result<(), ()> {
-    let task = current_task().unwrap();
-    let result = task.acquire_inner_lock().insert_framed_area(start_va, end_va, permission);
+    let process = current_process();
+    let result = process.acquire_inner_lock().insert_lazy_area(start_va, end_va, permission);
     result
 }
 
-pub fn munmap_current(start_va: VirtAddr, end_va: VirtAddr) {
-    let task = current_task().unwrap();
-    task.acquire_inner_lock().unmap_framed_area(start_va, end_va);
+pub fn mmap_file_current(
+    start_va: VirtAddr,
+    end_va: VirtAddr,
+    permission: MapPermission,
+    inode: Arc<dyn VfsInode>,
+    offset: usize,
+    shared: bool,
+) -> Result<(), ()> {
+    let process = current_process();
+    let result = process.acquire_inner_lock()
+        .insert_file_area(start_va, end_va, permission, inode, offset, shared);
+    result
+}
+
+pub fn brk_current(new_brk: usize) -> usize {
+    let process = current_process();
+    let result = process.acquire_inner_lock().set_brk(new_brk);
+    result
+}
+
+pub fn msync_current(start_va: VirtAddr, end_va: VirtAddr) -> Result<(), ()> {
+    let process = current_process();
+    let result = process.acquire_inner_lock().msync(start_va, end_va);
+    result
+}
+
+pub fn munmap_current(start_va: VirtAddr, end_va: VirtAddr) -> Result<(), ()> {
+    let process = current_process();
+    let result = process.acquire_inner_lock().unmap_framed_area(start_va, end_va);
+    result
+}
+
+pub fn handle_page_fault_current(va: usize, write: bool) -> bool {
+    let process = current_process();
+    let result = process.acquire_inner_lock().handle_page_fault(va.into(), write);
+    result
+}
+
+/// `is_mapped` for the current task, faulting in the page first if it lies
+/// in a lazily allocated area or, when `permission` asks for W, is still
+/// shared copy-on-write, so that the kernel can access user buffers as the
+/// task itself would.
+pub fn is_mapped_current(va: usize, permission: MapPermission) -> bool {
+    let process = current_process();
+    let mut inner = process.acquire_inner_lock();
+    inner.handle_page_fault(va.into(), permission.contains(MapPermission::W));
+    is_mapped(inner.get_user_token(), va, permission)
+}
+
+/// Keep a page the current syscall uses resident until it returns.
+pub fn pin_page_current(page: PinnedPage) {
+    current_task().unwrap().acquire_inner_lock().pinned_pages.push(page);
+}
+
+/// Called as a syscall of the current thread returns.
+pub fn unpin_pages_current() {
+    let pages = core::mem::take(&mut current_task().unwrap().acquire_inner_lock().pinned_pages);
+    drop(pages);
+}
+
+/// Called as the current thread traps into the kernel.
+pub fn charge_utime_current() {
+    current_task().unwrap().acquire_inner_lock().charge_utime();
+}
+
+/// Called as the current thread goes back to user mode.
+pub fn charge_stime_current() {
+    current_task().unwrap().acquire_inner_lock().charge_stime();
 }
 
 pub fn set_current_priority(priority: isize) {
@@ -106,8 +216,10 @@ pub fn set_current_priority(priority: isize) {
     task.acquire_inner_lock().set_priority(priority);
 }
 
+/// Switch from the current thread to the idle context of this hart, which
+/// puts the thread where its status says once it has left.
 pub fn schedule(switched_task_cx_ptr2: *const usize) {
-    let idle_task_cx_ptr2 = PROCESSOR.get_idle_task_cx_ptr2();
+    let idle_task_cx_ptr2 = current_processor().get_idle_task_cx_ptr2();
     unsafe {
         __switch(
             switched_task_cx_ptr2,
@@ -0,0 +1,101 @@
+use core::cmp::Ordering;
+use alloc::string::String;
+use alloc::format;
+use super::super::binary_heap::BinaryHeap;
+use super::{Scheduler, SchedBlock, INIT_PRIORITY};
+use crate::config::CLOCK_FREQ;
+use crate::timer::get_time;
+
+/// How far behind the others a thread coming back from a long sleep may
+/// be, so that it does not take the CPU for itself while catching up.
+const SCHED_LATENCY: u64 = (CLOCK_FREQ / 50) as u64;
+
+/// Completely fair: runs the thread with the least virtual runtime, the
+/// CPU time it has used scaled down by its priority as a weight.
+pub struct CfsScheduler {
+    heap: BinaryHeap<CfsBlock>,
+    /// The virtual runtime of the thread handed out last.
+    min_vruntime: u64,
+}
+
+#[derive(Copy, Clone)]
+pub struct CfsBlock {
+    id: usize,
+    vruntime: u64,
+    weight: u64,
+    /// When the thread was handed out last.
+    start: usize,
+}
+
+impl PartialOrd for CfsBlock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.vruntime.partial_cmp(&other.vruntime)
+    }
+}
+
+impl PartialEq for CfsBlock {
+    fn eq(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
+impl SchedBlock for CfsBlock {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn set_priority(&mut self, priority: isize) {
+        self.weight = priority as u64;
+    }
+
+    fn descheduled(&mut self) {
+        let runtime = (get_time() - self.start) as u64;
+        self.vruntime += runtime * INIT_PRIORITY as u64 / self.weight;
+    }
+
+    fn describe(&self) -> String {
+        format!("weight {} vruntime {}", self.weight, self.vruntime)
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    type Block = CfsBlock;
+    const NAME: &'static str = "cfs";
+
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    fn init_sched_block(&mut self, id: usize) {
+        self.heap.insert(CfsBlock {
+            id,
+            vruntime: self.min_vruntime,
+            weight: INIT_PRIORITY as u64,
+            start: 0,
+        });
+    }
+
+    fn add_sched_block(&mut self, mut block: CfsBlock) {
+        let floor = self.min_vruntime.saturating_sub(SCHED_LATENCY);
+        if block.vruntime < floor {
+            block.vruntime = floor;
+        }
+        self.heap.insert(block);
+    }
+
+    fn get_next_sched_block(&mut self) -> Option<CfsBlock> {
+        let mut block = self.heap.pop_min()?;
+        if block.vruntime > self.min_vruntime {
+            self.min_vruntime = block.vruntime;
+        }
+        block.start = get_time();
+        Some(block)
+    }
+
+    fn find_sched_block(&self, id: usize) -> Option<&CfsBlock> {
+        self.heap.iter().find(|block| block.id == id)
+    }
+}
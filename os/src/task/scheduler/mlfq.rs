@@ -0,0 +1,113 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::format;
+use super::{Scheduler, SchedBlock};
+use crate::config::CLOCK_FREQ;
+use crate::timer::get_time;
+
+const LEVELS: usize = 3;
+/// The CPU time a thread may use on each level before it moves down one.
+const ALLOTMENTS: [usize; LEVELS] = [CLOCK_FREQ / 100, CLOCK_FREQ / 50, CLOCK_FREQ / 25];
+/// How often all threads go back to the top level, so that those that
+/// have moved down are not starved.
+const BOOST_INTERVAL: usize = CLOCK_FREQ;
+
+/// Multi-level feedback queue: threads that use up their allotment move
+/// to a lower level, and a lower level only runs when those above it are
+/// empty.
+pub struct MlfqScheduler {
+    queues: [VecDeque<MlfqBlock>; LEVELS],
+    last_boost: usize,
+    /// Counts the boosts, for the threads that are not in a queue to
+    /// notice when they come back.
+    epoch: usize,
+}
+
+pub struct MlfqBlock {
+    id: usize,
+    level: usize,
+    /// CPU time used on the current level.
+    used: usize,
+    /// When the thread was handed out last.
+    start: usize,
+    epoch: usize,
+}
+
+impl SchedBlock for MlfqBlock {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn set_priority(&mut self, _priority: isize) {}
+
+    fn descheduled(&mut self) {
+        self.used += get_time() - self.start;
+    }
+
+    fn describe(&self) -> String {
+        format!("level {} used {}", self.level, self.used)
+    }
+}
+
+impl MlfqScheduler {
+    fn boost(&mut self) {
+        for level in 1..LEVELS {
+            while let Some(mut block) = self.queues[level].pop_front() {
+                block.level = 0;
+                block.used = 0;
+                self.queues[0].push_back(block);
+            }
+        }
+        self.epoch += 1;
+        self.last_boost = get_time();
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    type Block = MlfqBlock;
+    const NAME: &'static str = "mlfq";
+
+    fn new() -> Self {
+        Self {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            last_boost: 0,
+            epoch: 0,
+        }
+    }
+
+    fn init_sched_block(&mut self, id: usize) {
+        self.queues[0].push_back(MlfqBlock {
+            id,
+            level: 0,
+            used: 0,
+            start: 0,
+            epoch: self.epoch,
+        });
+    }
+
+    fn add_sched_block(&mut self, mut block: MlfqBlock) {
+        if block.epoch != self.epoch {
+            block.epoch = self.epoch;
+            block.level = 0;
+            block.used = 0;
+        } else if block.used >= ALLOTMENTS[block.level] && block.level + 1 < LEVELS {
+            block.level += 1;
+            block.used = 0;
+        }
+        self.queues[block.level].push_back(block);
+    }
+
+    fn get_next_sched_block(&mut self) -> Option<MlfqBlock> {
+        if get_time() - self.last_boost >= BOOST_INTERVAL {
+            self.boost();
+        }
+        let mut block = self.queues.iter_mut()
+            .find_map(|queue| queue.pop_front())?;
+        block.start = get_time();
+        Some(block)
+    }
+
+    fn find_sched_block(&self, id: usize) -> Option<&MlfqBlock> {
+        self.queues.iter().flatten().find(|block| block.id == id)
+    }
+}
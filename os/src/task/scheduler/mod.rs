@@ -0,0 +1,67 @@
+//! The policies `TaskManager` can pick the next thread with, one of which
+//! is chosen by cargo feature: `sched-stride` (the default), `sched-rr`,
+//! `sched-cfs` or `sched-mlfq`.
+
+mod cfs;
+mod mlfq;
+mod round_robin;
+mod stride;
+
+pub use cfs::CfsScheduler;
+pub use mlfq::MlfqScheduler;
+pub use round_robin::RoundRobinScheduler;
+pub use stride::StrideScheduler;
+
+use alloc::string::String;
+
+pub const MIN_PRIORITY: isize = 2;
+const INIT_PRIORITY: isize = 16;
+
+/// What a scheduler keeps about a thread. It travels with the thread
+/// while the thread is not in the ready queue.
+pub trait SchedBlock {
+    /// The kernel stack id of the thread.
+    fn id(&self) -> usize;
+    /// `priority` is at least `MIN_PRIORITY`. Policies without priorities
+    /// ignore it.
+    fn set_priority(&mut self, priority: isize);
+    /// The thread has just given up its hart.
+    fn descheduled(&mut self) {}
+    /// The state of the thread as the policy sees it, for /proc.
+    fn describe(&self) -> String;
+}
+
+/// Picks the thread to run next out of the ready ones, knowing them only
+/// by their `SchedBlock`.
+pub trait Scheduler {
+    type Block: SchedBlock;
+    const NAME: &'static str;
+
+    fn new() -> Self;
+    /// Add a thread that has never been scheduled.
+    fn init_sched_block(&mut self, id: usize);
+    /// Add a thread again, with the block it was handed out with.
+    fn add_sched_block(&mut self, block: Self::Block);
+    /// Hand out the block of the thread to run next.
+    fn get_next_sched_block(&mut self) -> Option<Self::Block>;
+    /// The block of a ready thread.
+    fn find_sched_block(&self, id: usize) -> Option<&Self::Block>;
+}
+
+#[cfg(any(
+    all(feature = "sched-rr", any(feature = "sched-cfs", feature = "sched-mlfq")),
+    all(feature = "sched-cfs", feature = "sched-mlfq"),
+))]
+compile_error!("more than one scheduler selected");
+
+#[cfg(feature = "sched-rr")]
+pub type ActiveScheduler = RoundRobinScheduler;
+#[cfg(feature = "sched-cfs")]
+pub type ActiveScheduler = CfsScheduler;
+#[cfg(feature = "sched-mlfq")]
+pub type ActiveScheduler = MlfqScheduler;
+// stride is on by default, the others take over when selected
+#[cfg(not(any(feature = "sched-rr", feature = "sched-cfs", feature = "sched-mlfq")))]
+pub type ActiveScheduler = StrideScheduler;
+
+pub type ActiveSchedBlock = <ActiveScheduler as Scheduler>::Block;
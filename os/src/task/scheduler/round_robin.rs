@@ -0,0 +1,51 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use super::{Scheduler, SchedBlock};
+
+/// Runs the ready threads in turn, whatever their priority.
+pub struct RoundRobinScheduler {
+    queue: VecDeque<RoundRobinBlock>,
+}
+
+pub struct RoundRobinBlock {
+    id: usize,
+}
+
+impl SchedBlock for RoundRobinBlock {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn set_priority(&mut self, _priority: isize) {}
+
+    fn describe(&self) -> String {
+        String::new()
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    type Block = RoundRobinBlock;
+    const NAME: &'static str = "round-robin";
+
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn init_sched_block(&mut self, id: usize) {
+        self.queue.push_back(RoundRobinBlock { id });
+    }
+
+    fn add_sched_block(&mut self, block: RoundRobinBlock) {
+        self.queue.push_back(block);
+    }
+
+    fn get_next_sched_block(&mut self) -> Option<RoundRobinBlock> {
+        self.queue.pop_front()
+    }
+
+    fn find_sched_block(&self, id: usize) -> Option<&RoundRobinBlock> {
+        self.queue.iter().find(|block| block.id == id)
+    }
+}
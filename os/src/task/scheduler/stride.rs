@@ -1,19 +1,21 @@
 use core::cmp::Ordering;
-use super::binary_heap::BinaryHeap;
-
-pub const MIN_PRIORITY: isize = 2;
+use alloc::string::String;
+use alloc::format;
+use super::super::binary_heap::BinaryHeap;
+use super::{Scheduler, SchedBlock, MIN_PRIORITY, INIT_PRIORITY};
 
 const BIG_STRIDE: u64 = u64::MAX;
-const INIT_PRIORITY: isize = 16;
 
 
+/// Runs the thread that is furthest behind, each step it takes being
+/// inversely proportional to its priority.
 pub struct StrideScheduler {
-    heap: BinaryHeap<SchedBlock>,
+    heap: BinaryHeap<StrideBlock>,
 }
 
 #[derive(Copy, Clone)]
-pub struct SchedBlock {
-    pub id: usize,
+pub struct StrideBlock {
+    id: usize,
     stride: Stride,
     pass: u64, 
 }
@@ -53,21 +55,25 @@ impl PartialEq for Stride {
 }
 
 
-impl PartialOrd for SchedBlock {
+impl PartialOrd for StrideBlock {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         self.stride.partial_cmp(&other.stride)
     }
 }
 
-impl PartialEq for SchedBlock {
+impl PartialEq for StrideBlock {
     fn eq(&self, _other: &Self) -> bool {
         false
     }
 }
 
 
-impl SchedBlock {
-    pub fn set_priority(&mut self, priority: isize) {
+impl SchedBlock for StrideBlock {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn set_priority(&mut self, priority: isize) {
         // The parameter should be checked in syscall module before invoking this function.
         // If it is still wrong here, just panic.
         if priority < MIN_PRIORITY {
@@ -75,19 +81,26 @@ impl SchedBlock {
         }
         self.pass = BIG_STRIDE / priority as u64;
     }
+
+    fn describe(&self) -> String {
+        format!("priority {} stride {} pass {}", BIG_STRIDE / self.pass, self.stride.0, self.pass)
+    }
 }
 
 
 
-impl StrideScheduler {
-    pub fn new() -> Self {
+impl Scheduler for StrideScheduler {
+    type Block = StrideBlock;
+    const NAME: &'static str = "stride";
+
+    fn new() -> Self {
         Self {
             heap: BinaryHeap::new(),
         }
     }
 
-    pub fn init_sched_block(&mut self, id: usize) {
-        let block = SchedBlock {
+    fn init_sched_block(&mut self, id: usize) {
+        let block = StrideBlock {
             id,
             stride: Stride(0),
             pass: BIG_STRIDE / INIT_PRIORITY as u64,
@@ -95,11 +108,11 @@ impl StrideScheduler {
         self.heap.insert(block);
     }
 
-    pub fn add_sched_block(&mut self, block: SchedBlock) {
+    fn add_sched_block(&mut self, block: StrideBlock) {
         self.heap.insert(block);
     }
 
-    pub fn get_next_sched_block(&mut self) -> Option<SchedBlock> {
+    fn get_next_sched_block(&mut self) -> Option<StrideBlock> {
         match self.heap.pop_min() {
             Some(mut block) => {
                 // Add pass to stride, so that the block can be
@@ -111,4 +124,8 @@ impl StrideScheduler {
             None => None,
         }
     }
+
+    fn find_sched_block(&self, id: usize) -> Option<&StrideBlock> {
+        self.heap.iter().find(|block| block.id == id)
+    }
 }
@@ -1,41 +1,42 @@
-use crate::mm::{
-    MemorySet,
-    PhysPageNum,
-    KERNEL_SPACE,
-    VirtAddr,
-    MapPermission,
-    translated_refmut,
-};
-use crate::trap::{TrapContext, trap_handler};
-use crate::config::{TRAP_CONTEXT};
+use crate::mm::{PhysPageNum, PinnedPage};
+use crate::trap::TrapContext;
 use super::TaskContext;
-use super::{PidHandle, pid_alloc, KernelStack};
-use super::stride_scheduler::SchedBlock;
+use super::{KernelStack, TaskUserRes, ProcessControlBlock};
+use super::scheduler::{SchedBlock, ActiveSchedBlock};
+use super::signal::SignalFlags;
+use crate::timer::get_time;
 use alloc::sync::{Weak, Arc};
-use alloc::vec;
-use alloc::vec::Vec;
 use alloc::string::String;
+use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
-use crate::fs::{File, Stdin, Stdout, Mailbox};
 
+/// A thread of a process, the unit the scheduler deals with.
 pub struct TaskControlBlock {
-    pub pid: PidHandle,
+    pub process: Weak<ProcessControlBlock>,
     pub kernel_stack: KernelStack,
     inner: Mutex<TaskControlBlockInner>,
 }
 
 pub struct TaskControlBlockInner {
+    /// Gone once the thread has exited.
+    pub res: Option<TaskUserRes>,
     pub trap_cx_ppn: PhysPageNum,
-    pub base_size: usize,
     pub task_cx_ptr: usize,
     pub task_status: TaskStatus,
-    pub memory_set: MemorySet,
-    pub parent: Option<Weak<TaskControlBlock>>,
-    pub children: Vec<Arc<TaskControlBlock>>,
-    pub exit_code: i32,
-    pub sched_block: Option<SchedBlock>,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
-    pub mailbox: Arc<Mailbox>,
+    pub exit_code: Option<i32>,
+    pub sched_block: Option<ActiveSchedBlock>,
+    /// The trap context and signal mask to go back to with `sigreturn`,
+    /// set while the thread runs a user signal handler.
+    pub signal_backup: Option<(TrapContext, SignalFlags)>,
+    /// The time spent in user and kernel mode, in ticks of `get_time`.
+    pub utime: usize,
+    pub stime: usize,
+    /// When the thread last got its hart or crossed between user and
+    /// kernel mode.
+    pub time_stamp: usize,
+    /// The user pages the current syscall uses, kept resident until it
+    /// returns.
+    pub pinned_pages: Vec<PinnedPage>,
 }
 
 
@@ -48,38 +49,30 @@ impl TaskControlBlockInner {
         self.trap_cx_ppn.get_mut()
     }
 
-    pub fn get_user_token(&self) -> usize {
-        self.memory_set.token()
-    }
-
-    pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> Result<(), ()> {
-        self.memory_set.insert_framed_area(start_va, end_va, permission)
-    }
-
-    pub fn unmap_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr) {
-        self.memory_set.unmap_framed_area(start_va, end_va);
-    }
-
     pub fn set_priority(&mut self, priority: isize) {
         self.sched_block.as_mut().unwrap().set_priority(priority);
     }
 
-    fn get_status(&self) -> TaskStatus {
-        self.task_status
+    /// What the scheduler keeps about the thread, unless it is in the
+    /// ready queue.
+    pub fn describe_sched_block(&self) -> Option<String> {
+        self.sched_block.as_ref().map(|block| block.describe())
     }
 
-    pub fn is_zombie(&self) -> bool {
-        self.get_status() == TaskStatus::Zombie
+    /// Charge the time since the last stamp to user mode, as the thread
+    /// traps into the kernel.
+    pub fn charge_utime(&mut self) {
+        let now = get_time();
+        self.utime += now - self.time_stamp;
+        self.time_stamp = now;
     }
 
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len())
-            .find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
-        }
+    /// Charge the time since the last stamp to kernel mode, as the thread
+    /// goes back to user mode or leaves its hart.
+    pub fn charge_stime(&mut self) {
+        let now = get_time();
+        self.stime += now - self.time_stamp;
+        self.time_stamp = now;
     }
 }
 
@@ -88,166 +81,43 @@ impl TaskControlBlock {
         self.inner.lock()
     }
 
-    pub fn new(elf_data: &[u8]) -> Option<Self> {
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-
-        let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle)?;
-        let kernel_stack_top = kernel_stack.get_top();
+    /// A thread of `process` using the trap context and user stack of
+    /// `res`. The trap context is left for the caller to fill in.
+    pub fn new(process: &Arc<ProcessControlBlock>, res: TaskUserRes) -> Option<Self> {
+        let trap_cx_ppn = res.trap_cx_ppn(&process.acquire_inner_lock().memory_set);
+        let kernel_stack = KernelStack::new()?;
         let task_cx_ptr = kernel_stack.push_on_top(TaskContext::goto_trap_return());
-        let mailbox = Mailbox::new(pid_handle.0);
-        let task_control_block = Self {
-            pid: pid_handle,
+        Some(Self {
+            process: Arc::downgrade(process),
             kernel_stack,
             inner: Mutex::new(TaskControlBlockInner {
+                res: Some(res),
                 trap_cx_ppn,
-                base_size: user_sp,
                 task_cx_ptr: task_cx_ptr as usize,
                 task_status: TaskStatus::Ready,
-                memory_set,
-                parent: None,
-                children: Vec::new(),
-                exit_code: 0,
+                exit_code: None,
                 sched_block: None,
-                fd_table: vec![
-                    Some(Arc::new(Stdin)),
-                    Some(Arc::new(Stdout)),
-                    Some(Arc::new(Stdout)),
-                ],
-                mailbox,
+                signal_backup: None,
+                utime: 0,
+                stime: 0,
+                time_stamp: 0,
+                pinned_pages: Vec::new(),
             }),
-        };
-
-        let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
-        *trap_cx = TrapContext::app_init_context(
-            entry_point,
-            user_sp,
-            KERNEL_SPACE.lock().token(),
-            kernel_stack_top,
-            trap_handler as usize,
-        );
-        Some(task_control_block)
-    }
-
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) -> Result<(), ()> {
-        let (memory_set, mut user_sp, entry_point) = match MemorySet::from_elf(elf_data) {
-            Some(x) => x,
-            None => return Err(()),
-        };
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    memory_set.token(),
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(memory_set.token(), p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(memory_set.token(), p as *mut u8) = 0;
-        }
-        user_sp -= user_sp % core::mem::size_of::<usize>();
-
-        let mut inner = self.acquire_inner_lock();
-        inner.memory_set = memory_set;
-        inner.trap_cx_ppn = trap_cx_ppn;
-        let mut trap_cx = TrapContext::app_init_context(
-            entry_point,
-            user_sp,
-            KERNEL_SPACE.lock().token(),
-            self.kernel_stack.get_top(),
-            trap_handler as usize,
-        );
-        trap_cx.x[10] = args.len();
-        trap_cx.x[11] = argv_base;
-        *inner.get_trap_cx() = trap_cx;
-
-        Ok(())
+        })
     }
 
-    pub fn fork(self: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
-        let mut parent_inner = self.acquire_inner_lock();
-        let memory_set = MemorySet::from_existed_user(
-            &parent_inner.memory_set
-        )?;
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-        let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle)?;
-        let kernel_stack_top = kernel_stack.get_top();
-        let task_cx_ptr = kernel_stack.push_on_top(TaskContext::goto_trap_return());
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent_inner.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
-        let mailbox = Mailbox::new(pid_handle.0);
-        let task_control_block = Arc::new(TaskControlBlock {
-            pid: pid_handle,
-            kernel_stack,
-            inner: Mutex::new(TaskControlBlockInner {
-                trap_cx_ppn,
-                base_size: parent_inner.base_size,
-                task_cx_ptr: task_cx_ptr as usize,
-                task_status: TaskStatus::Ready,
-                memory_set,
-                parent: Some(Arc::downgrade(self)),
-                children: Vec::new(),
-                exit_code: 0,
-                sched_block: None,
-                fd_table: new_fd_table,
-                mailbox,
-            }),
-        });
-
-        parent_inner.children.push(task_control_block.clone());
-        
-        let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
-        trap_cx.kernel_sp = kernel_stack_top;
-        Some(task_control_block)
-    }
-
-    pub fn spawn(self: &Arc<TaskControlBlock>, elf_data: &[u8]) -> Option<Arc<TaskControlBlock>> {
-        let task_control_block = Arc::new(TaskControlBlock::new(elf_data)?);
-        let mut parent_inner = self.acquire_inner_lock();
-        parent_inner.children.push(task_control_block.clone());
-        task_control_block.acquire_inner_lock().parent = Some(Arc::downgrade(self));
-        Some(task_control_block)
-    }
-
-
-    pub fn getpid(&self) -> usize {
-        self.pid.0
+    pub fn gettid(&self) -> usize {
+        self.acquire_inner_lock().res.as_ref().unwrap().tid
     }
 }
 
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TaskStatus {
     Ready,
     Running,
+    /// Waiting for a wakeup, but not switched out by its hart yet.
+    Blocking,
+    Blocked,
     Zombie,
 }
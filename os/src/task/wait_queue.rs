@@ -0,0 +1,53 @@
+use super::{TaskControlBlock, current_task, prepare_block_current, wakeup_task};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Threads blocked until something they wait for happens. A queue lives
+/// under the same lock as the state it waits on, so that a thread checks
+/// that state and enqueues itself without a wakeup slipping in between.
+pub struct WaitQueue {
+    queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Enqueue the current thread, which is then to release the lock
+    /// guarding the queue and call `block_current_and_run_next`.
+    pub fn add_current(&mut self) {
+        self.queue.push_back(prepare_block_current());
+    }
+
+    /// Take the current thread out of the queue, as it stops waiting
+    /// before being woken up. Returns false if it has been woken up from the
+    /// queue meanwhile, and so got what it waited for.
+    pub fn remove_current(&mut self) -> bool {
+        let task = current_task().unwrap();
+        let len = self.queue.len();
+        self.queue.retain(|waiting| !Arc::ptr_eq(waiting, &task));
+        self.queue.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Wake up the thread that has waited longest, skipping those that
+    /// have exited meanwhile. Returns whether one was woken up.
+    pub fn wake_one(&mut self) -> bool {
+        while let Some(task) = self.queue.pop_front() {
+            if wakeup_task(task) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn wake_all(&mut self) {
+        while self.wake_one() {}
+    }
+}
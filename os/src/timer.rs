@@ -1,10 +1,16 @@
 use riscv::register::time;
 use crate::sbi::set_timer;
 use crate::config::CLOCK_FREQ;
+use crate::task::{TaskControlBlock, wakeup_task};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+use lazy_static::*;
 
 const TICKS_PER_SEC: usize = 100;
 //const MSEC_PER_SEC: usize = 1000;
 const USEC_PER_SEC: usize = 1000000;
+const NSEC_PER_SEC: usize = 1000000000;
 
 pub fn get_time() -> usize {
     time::read()
@@ -21,14 +27,94 @@ pub fn set_next_trigger() {
 }
 
 pub fn get_time_val() -> TimeVal {
-    let mut clock = time::read();
-    let sec = clock / CLOCK_FREQ;
-    clock %= CLOCK_FREQ;
-    let usec = clock / (CLOCK_FREQ / USEC_PER_SEC);
-    TimeVal(sec, usec)
+    TimeVal::from_ticks(time::read())
+}
+
+/// `ticks` of `get_time` in the clock ticks of `times`, of which there
+/// are `TICKS_PER_SEC` a second.
+pub fn to_clock_ticks(ticks: usize) -> usize {
+    ticks / (CLOCK_FREQ / TICKS_PER_SEC)
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct TimeVal(usize, usize);
 
+impl TimeVal {
+    pub fn from_ticks(mut ticks: usize) -> Self {
+        let sec = ticks / CLOCK_FREQ;
+        ticks %= CLOCK_FREQ;
+        let usec = ticks / (CLOCK_FREQ / USEC_PER_SEC);
+        TimeVal(sec, usec)
+    }
+}
+
+/// The CPU time of a process and of its children it has waited for, in
+/// clock ticks.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+/// Only the CPU times are kept track of.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn is_valid(&self) -> bool {
+        self.nsec < NSEC_PER_SEC
+    }
+
+    pub fn to_ticks(&self) -> usize {
+        self.sec * CLOCK_FREQ + self.nsec * CLOCK_FREQ / NSEC_PER_SEC
+    }
+}
+
+
+lazy_static! {
+    /// Sleeping threads sorted by the time they wake up at, with their
+    /// kernel stack id to tell apart those waking up at the same time.
+    static ref TIMERS: Mutex<BTreeMap<(usize, usize), Arc<TaskControlBlock>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Have `task`, which is about to block, woken up once `get_time()`
+/// reaches `expire`.
+pub fn add_timer(expire: usize, task: Arc<TaskControlBlock>) {
+    let id = task.kernel_stack.0;
+    TIMERS.lock().insert((expire, id), task);
+}
+
+/// Cancel the timer `add_timer` set up for `task`. Returns false if it has
+/// gone off already.
+pub fn remove_timer(expire: usize, task: &Arc<TaskControlBlock>) -> bool {
+    TIMERS.lock().remove(&(expire, task.kernel_stack.0)).is_some()
+}
+
+/// Wake up the threads whose time has come.
+pub fn check_timer() {
+    let current = get_time();
+    let mut timers = TIMERS.lock();
+    while let Some(&(expire, id)) = timers.keys().next() {
+        if expire > current {
+            break;
+        }
+        let task = timers.remove(&(expire, id)).unwrap();
+        wakeup_task(task);
+    }
+}
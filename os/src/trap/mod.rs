@@ -11,16 +11,26 @@ use riscv::register::{
     },
     stval,
     sie,
+    sip,
 };
 use crate::syscall::syscall;
 use crate::task::{
-    exit_current_and_run_next,
     suspend_current_and_run_next,
     current_user_token,
     current_trap_cx,
+    current_trap_cx_user_va,
+    handle_page_fault_current,
+    force_signal_current,
+    handle_signals,
+    charge_utime_current,
+    charge_stime_current,
+    unpin_pages_current,
+    SignalFlags,
 };
-use crate::timer::set_next_trigger;
-use crate::config::{TRAP_CONTEXT, TRAMPOLINE};
+use crate::timer::{set_next_trigger, check_timer};
+use crate::fs::poll_stdin;
+use crate::config::TRAMPOLINE;
+use crate::smp::{enter_user, leave_user};
 
 global_asm!(include_str!("trap.S"));
 
@@ -42,23 +52,34 @@ fn set_user_trap_entry() {
 }
 
 pub fn enable_timer_interrupt() {
-    unsafe { sie::set_stimer(); }
+    unsafe {
+        sie::set_stimer();
+        // the TLB shootdowns of the other harts
+        sie::set_ssoft();
+    }
 }
 
 
 #[no_mangle]
 pub fn trap_handler() -> ! {
+    // before anything that could wait for a TLB shootdown of another hart
+    leave_user();
     set_kernel_trap_entry();
+    charge_utime_current();
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14]]) as usize;
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]) as usize;
+            unpin_pages_current();
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        Trap::Exception(Exception::StorePageFault) if handle_page_fault_current(stval, true) => {}
+        Trap::Exception(Exception::InstructionPageFault) |
+        Trap::Exception(Exception::LoadPageFault) if handle_page_fault_current(stval, false) => {}
         Trap::Exception(Exception::StoreFault) |
         Trap::Exception(Exception::StorePageFault) |
         Trap::Exception(Exception::InstructionFault) |
@@ -71,20 +92,28 @@ pub fn trap_handler() -> ! {
             //    stval,
             //    current_trap_cx().sepc,
             //);
-            exit_current_and_run_next(-2);
+            force_signal_current(SignalFlags::SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             //println!("[kernel] IllegalInstruction in application, core dumped.");
-            exit_current_and_run_next(-3);
+            force_signal_current(SignalFlags::SIGILL);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
+            check_timer();
+            poll_stdin();
             suspend_current_and_run_next();
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // a TLB shootdown, the TLB was flushed on the way in and
+            // leave_user has told the sender
+            unsafe { sip::clear_ssoft(); }
+        }
         _ => {
             panic!("Unsupported trap {:?}, stval = {:#x}!", scause.cause(), stval);
         }
     }
+    handle_signals();
     trap_return();
 }
 
@@ -92,13 +121,15 @@ pub fn trap_handler() -> ! {
 #[no_mangle]
 pub fn trap_return() -> ! {
     set_user_trap_entry();
-    let trap_cx_ptr = TRAP_CONTEXT;
+    charge_stime_current();
+    let trap_cx_ptr = current_trap_cx_user_va();
     let user_satp = current_user_token();
     extern "C" {
         fn __alltraps();
         fn __restore();
     }
     let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
+    enter_user();
     unsafe {
         llvm_asm!("fence.i" :::: "volatile");
         llvm_asm!("jr $0" :: "r"(restore_va), "{a0}"(trap_cx_ptr), "{a1}"(user_satp) :: "volatile");
@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, waitpid, exit, pipe, read, write, close};
+
+const LEN: usize = 4096 * 4;
+static mut DATA: [u8; LEN] = [0u8; LEN];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let data = unsafe { &mut DATA };
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[1]);
+        // a store from user mode and a write by the kernel on our behalf
+        // must both land in private copies of the shared pages
+        data[0] = 0xff;
+        assert_eq!(read(pipe_fd[0], &mut data[4096..4096 + 5]), 5);
+        assert_eq!(&data[4096..4096 + 5], b"hello");
+        assert_eq!(data[8192], 0);
+        close(pipe_fd[0]);
+        exit(0);
+    }
+    close(pipe_fd[0]);
+    assert_eq!(write(pipe_fd[1], b"hello"), 5);
+    close(pipe_fd[1]);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for (i, byte) in data.iter().enumerate() {
+        assert_eq!(*byte, i as u8);
+    }
+    // the parent is the only owner again and can write in place
+    data[0] = 0xff;
+    assert_eq!(data[0], 0xff);
+    println!("cowtest passed!");
+    0
+}
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    openat,
+    close,
+    read,
+    write,
+    mkdir,
+    rmdir,
+    unlink,
+    chdir,
+    getcwd,
+    fork,
+    waitpid,
+    exit,
+    OpenFlags,
+};
+
+fn cwd() -> &'static str {
+    static mut BUF: [u8; 64] = [0u8; 64];
+    let len = getcwd(unsafe { &mut BUF });
+    assert!(len > 0);
+    core::str::from_utf8(unsafe { &BUF[..len as usize - 1] }).unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(cwd(), "/");
+    assert_eq!(mkdir("cwd_a\0"), 0);
+    assert_eq!(mkdir("cwd_a/cwd_b\0"), 0);
+    assert_eq!(chdir("cwd_a/./cwd_b/\0"), 0);
+    assert_eq!(cwd(), "/cwd_a/cwd_b");
+    assert_eq!(chdir("no_such_dir\0"), -1);
+    assert_eq!(cwd(), "/cwd_a/cwd_b");
+
+    let test_str = "Hello, cwd!";
+    let fd = open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    assert_eq!(chdir("..\0"), 0);
+    assert_eq!(cwd(), "/cwd_a");
+    let pid = fork();
+    if pid == 0 {
+        // the working directory is inherited by the child
+        assert_eq!(cwd(), "/cwd_a");
+        assert_eq!(chdir("/\0"), 0);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(cwd(), "/cwd_a");
+
+    let dirfd = open("cwd_b\0", OpenFlags::RDONLY);
+    assert!(dirfd > 0);
+    let dirfd = dirfd as usize;
+    assert_eq!(write(dirfd, test_str.as_bytes()), -1);
+    let fd = openat(dirfd, "file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 32];
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    close(dirfd);
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+
+    assert_eq!(unlink("cwd_b/file\0"), 0);
+    assert_eq!(rmdir("cwd_b\0"), 0);
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(rmdir("cwd_a\0"), 0);
+    println!("cwd_test passed!");
+    0
+}
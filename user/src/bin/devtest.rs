@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    write,
+    fstat,
+    lseek,
+    unlink,
+    OpenFlags,
+    Stat,
+    StatMode,
+    SEEK_SET,
+    SEEK_END,
+};
+
+const EFS_MAGIC: u32 = 0x3b800001;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buffer = [0xffu8; 64];
+
+    // what shell redirection does
+    let fd = open("/dev/null\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &buffer), buffer.len() as isize);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    assert!(stat.mode.contains(StatMode::CHR));
+    close(fd);
+    let fd = open("/dev/null\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buffer), 0);
+    close(fd);
+
+    let fd = open("/dev/zero\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buffer), buffer.len() as isize);
+    assert!(buffer.iter().all(|byte| *byte == 0));
+    close(fd);
+
+    let fd = open("/dev/random\0", OpenFlags::RDWR) as usize;
+    let mut other = [0u8; 64];
+    assert_eq!(read(fd, &mut buffer), buffer.len() as isize);
+    assert_eq!(write(fd, b"some entropy"), 12);
+    assert_eq!(read(fd, &mut other), other.len() as isize);
+    assert_ne!(buffer, other);
+    close(fd);
+
+    // block 0 of the disk is the superblock of easy-fs
+    let fd = open("/dev/vda\0", OpenFlags::RDONLY) as usize;
+    let mut block = [0u8; 512];
+    assert_eq!(read(fd, &mut block), 512);
+    assert_eq!(u32::from_ne_bytes([block[0], block[1], block[2], block[3]]), EFS_MAGIC);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert!(lseek(fd, 0, SEEK_END) > 512);
+    assert_eq!(read(fd, &mut block), 0);
+    close(fd);
+
+    assert_eq!(open("/dev/missing\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    assert_eq!(unlink("/dev/null\0"), -1);
+
+    let fd = open("/dev/console\0", OpenFlags::WRONLY) as usize;
+    let message = "devtest passed!\n";
+    assert_eq!(write(fd, message.as_bytes()), message.len() as isize);
+    close(fd);
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    write,
+    mkdir,
+    rmdir,
+    unlink,
+    link,
+    OpenFlags,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("dir_a\0"), 0);
+    assert_eq!(mkdir("dir_a\0"), -1);
+    assert_eq!(mkdir("dir_a/dir_b\0"), 0);
+
+    let test_str = "Hello, directories!";
+    let fd = open("dir_a/dir_b/file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, test_str.as_bytes());
+    close(fd);
+
+    assert_eq!(link("dir_a/dir_b/file\0", "/dir_a/file_link\0"), 0);
+    let fd = open("/dir_a/./dir_b/../file_link\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 100];
+    let read_len = read(fd, &mut buffer) as usize;
+    close(fd);
+    assert_eq!(
+        test_str,
+        core::str::from_utf8(&buffer[..read_len]).unwrap(),
+    );
+
+    assert_eq!(open("dir_a\0", OpenFlags::RDONLY), -1);
+    assert_eq!(rmdir("dir_a/dir_b\0"), -1);
+    assert_eq!(unlink("dir_a/dir_b\0"), -1);
+    assert_eq!(unlink("dir_a/dir_b/file\0"), 0);
+    assert_eq!(unlink("dir_a/file_link\0"), 0);
+    assert_eq!(rmdir("dir_a/dir_b/file\0"), -1);
+    assert_eq!(rmdir("dir_a/dir_b\0"), 0);
+    assert_eq!(rmdir("dir_a\0"), 0);
+    assert_eq!(open("dir_a/dir_b/file\0", OpenFlags::RDONLY), -1);
+    println!("dir_test passed!");
+    0
+}
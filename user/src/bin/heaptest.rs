@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use user_lib::{brk, sbrk};
+use alloc::vec::Vec;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // much more than the initial heap could ever hold
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..128 * 1024 {
+        v.push(i);
+    }
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(*x, i);
+    }
+    drop(v);
+
+    let old_brk = sbrk(0);
+    assert!(old_brk > 0);
+    assert_eq!(sbrk(4096), old_brk);
+    let p = old_brk as usize as *mut u8;
+    unsafe {
+        *p = 42;
+        assert_eq!(*p.add(4095), 0);
+    }
+    assert_eq!(sbrk(0), old_brk + 4096);
+    assert_eq!(sbrk(-4096), old_brk + 4096);
+    assert_eq!(brk(0), old_brk);
+    // the break cannot be moved below the start of the heap
+    assert_eq!(brk(1), old_brk);
+    println!("heaptest passed!");
+    0
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, getcwd, fork, waitpid, exit, SIGSEGV};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // far more than the physical memory available, only backed when touched
+    let start: usize = 0x10000000;
+    let len: usize = 256 * 1024 * 1024;
+    assert_eq!(mmap(start, len, 3), len as isize);
+    assert_eq!(mmap(start + len / 2, PAGE_SIZE, 3), -1);
+
+    let step = 16 * 1024 * 1024;
+    for addr in (start..start + len).step_by(step) {
+        unsafe { *(addr as *mut usize) = addr; }
+    }
+    for addr in (start..start + len).step_by(step) {
+        assert_eq!(unsafe { *(addr as *const usize) }, addr);
+    }
+    // untouched pages read as zero
+    assert_eq!(unsafe { *((start + PAGE_SIZE) as *const usize) }, 0);
+
+    // the kernel has to fault in pages the task has not touched either
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut((start + 3 * PAGE_SIZE) as *mut u8, 8)
+    };
+    assert_eq!(getcwd(buf), 2);
+    assert_eq!(buf[0], b'/');
+
+    let pid = fork();
+    if pid == 0 {
+        for addr in (start..start + len).step_by(step) {
+            assert_eq!(unsafe { *(addr as *const usize) }, addr);
+        }
+        assert_eq!(buf[0], b'/');
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(munmap(start, len), len as isize);
+    assert_eq!(munmap(start, PAGE_SIZE), -1);
+    let pid = fork();
+    if pid == 0 {
+        unsafe { *(start as *mut usize) = 0; }
+        panic!("should have been killed");
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -(SIGSEGV as i32));
+    println!("lazymmap passed!");
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    OpenFlags,
+    close,
+    getdents64,
+    DT_DIR,
+};
+
+fn list(path: &str) -> i32 {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd == -1 {
+        println!("ls: cannot open {}", path.trim_end_matches('\0'));
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 512];
+    loop {
+        let size = getdents64(fd, &mut buf);
+        if size <= 0 {
+            close(fd);
+            if size < 0 {
+                println!("ls: {} is not a directory", path.trim_end_matches('\0'));
+                return -1;
+            }
+            return 0;
+        }
+        let mut pos = 0usize;
+        while pos < size as usize {
+            // linux_dirent64: d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8, d_name
+            let reclen = u16::from_ne_bytes([buf[pos + 16], buf[pos + 17]]) as usize;
+            let d_type = buf[pos + 18];
+            let name = &buf[pos + 19..pos + reclen];
+            let len = name.iter().position(|c| *c == 0).unwrap();
+            let name = core::str::from_utf8(&name[..len]).unwrap();
+            if d_type == DT_DIR {
+                println!("{}/", name);
+            } else {
+                println!("{}", name);
+            }
+            pos += reclen;
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        return list(".\0");
+    }
+    let mut ret = 0;
+    for path in &argv[1..] {
+        if argc > 2 {
+            println!("{}:", path);
+        }
+        if list(path) != 0 {
+            ret = -1;
+        }
+    }
+    ret
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    write,
+    lseek,
+    mmap_file,
+    munmap,
+    msync,
+    unlink,
+    fork,
+    waitpid,
+    exit,
+    OpenFlags,
+    MAP_SHARED,
+    MAP_PRIVATE,
+    SEEK_SET,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+fn content(fd: usize, buf: &mut [u8]) -> usize {
+    lseek(fd, 0, SEEK_SET);
+    read(fd, buf) as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("mmapfile_data\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut data = [b'a'; PAGE_SIZE + 100];
+    data[PAGE_SIZE] = b'b';
+    assert_eq!(write(fd, &data), data.len() as isize);
+
+    // private mappings read the file but never write to it
+    let start: usize = 0x10000000;
+    assert_eq!(mmap_file(start, 2 * PAGE_SIZE, 3, MAP_PRIVATE, fd, 0), 2 * PAGE_SIZE as isize);
+    let mapped = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, 2 * PAGE_SIZE) };
+    assert_eq!(mapped[0], b'a');
+    assert_eq!(mapped[PAGE_SIZE], b'b');
+    assert_eq!(mapped[PAGE_SIZE + 100], 0);
+    mapped[0] = b'x';
+    assert_eq!(munmap(start, 2 * PAGE_SIZE), 2 * PAGE_SIZE as isize);
+    let mut buf = [0u8; 2 * PAGE_SIZE];
+    assert_eq!(content(fd, &mut buf), data.len());
+    assert_eq!(buf[0], b'a');
+
+    // shared mappings are written back by msync and munmap
+    assert_eq!(mmap_file(start, PAGE_SIZE, 3, MAP_SHARED, fd, PAGE_SIZE), PAGE_SIZE as isize);
+    let mapped = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, PAGE_SIZE) };
+    assert_eq!(mapped[0], b'b');
+    mapped[1] = b'y';
+    assert_eq!(msync(start, PAGE_SIZE), 0);
+    assert_eq!(content(fd, &mut buf), data.len());
+    assert_eq!(buf[PAGE_SIZE + 1], b'y');
+
+    // a forked child writes through the same frames
+    let pid = fork();
+    if pid == 0 {
+        mapped[2] = b'z';
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(mapped[2], b'z');
+    // writes past the end of the file are not written back
+    mapped[200] = b'w';
+    assert_eq!(munmap(start, PAGE_SIZE), PAGE_SIZE as isize);
+    assert_eq!(content(fd, &mut buf), data.len());
+    assert_eq!(buf[PAGE_SIZE + 2], b'z');
+    close(fd);
+
+    // shared writable mappings need a writable file
+    let fd = open("mmapfile_data\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(mmap_file(start, PAGE_SIZE, 3, MAP_SHARED, fd, 0), -1);
+    assert_eq!(mmap_file(start, PAGE_SIZE, 1, MAP_SHARED, fd, 1), -1);
+    close(fd);
+    assert_eq!(unlink("mmapfile_data\0"), 0);
+    println!("mmapfile passed!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    mkdir,
+    rmdir,
+    mount,
+    umount,
+    OpenFlags,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("mnt\0"), 0);
+    assert_eq!(mount("mnt\0", "nofs\0"), -1);
+    assert_eq!(mount("mnt/missing\0", "proc\0"), -1);
+    assert_eq!(mount("mnt\0", "proc\0"), 0);
+    assert_eq!(mount("mnt\0", "proc\0"), -1);
+
+    let fd = open("/mnt/meminfo\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 256];
+    assert!(read(fd, &mut buffer) > 0);
+    close(fd);
+
+    assert_eq!(open("/mnt/meminfo\0", OpenFlags::WRONLY), -1);
+    assert_eq!(open("/mnt/new_file\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    assert_eq!(mkdir("/mnt/dir\0"), -1);
+    assert_eq!(rmdir("mnt\0"), -1);
+    assert_eq!(umount("/\0"), -1);
+
+    assert_eq!(umount("mnt\0"), 0);
+    assert_eq!(umount("mnt\0"), -1);
+    assert_eq!(open("/mnt/meminfo\0", OpenFlags::RDONLY), -1);
+    assert_eq!(rmdir("mnt\0"), 0);
+    println!("mounttest passed!");
+    0
+}
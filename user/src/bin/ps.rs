@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use user_lib::{
+    open,
+    OpenFlags,
+    close,
+    read,
+    getdents64,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd == -1 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 256];
+    let mut bytes = Vec::new();
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..size as usize]);
+    }
+    close(fd);
+    String::from_utf8(bytes).ok()
+}
+
+fn list_pids() -> Vec<usize> {
+    let mut pids = Vec::new();
+    let fd = open("/proc\0", OpenFlags::RDONLY);
+    if fd == -1 {
+        return pids;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 512];
+    loop {
+        let size = getdents64(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        let mut pos = 0usize;
+        while pos < size as usize {
+            let reclen = u16::from_ne_bytes([buf[pos + 16], buf[pos + 17]]) as usize;
+            let name = &buf[pos + 19..pos + reclen];
+            let len = name.iter().position(|c| *c == 0).unwrap();
+            if let Some(pid) = core::str::from_utf8(&name[..len]).ok()
+                .and_then(|name| name.parse::<usize>().ok()) {
+                pids.push(pid);
+            }
+            pos += reclen;
+        }
+    }
+    close(fd);
+    pids
+}
+
+fn field<'a>(status: &'a str, key: &str) -> &'a str {
+    status.lines()
+        .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix(": ")))
+        .unwrap_or("?")
+}
+
+/// List the processes with what /proc tells about them.
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("{:>5} {:>5} {:>8} {:>7} {:>6} {:>6}", "PID", "PPID", "STATE", "THREADS", "UTIME", "STIME");
+    for pid in list_pids() {
+        let mut path = String::from("/proc/");
+        path.push_str(pid.to_string().as_str());
+        path.push_str("/status\0");
+        // it may have been reaped meanwhile
+        let status = match read_file(path.as_str()) {
+            Some(status) => status,
+            None => continue,
+        };
+        println!(
+            "{:>5} {:>5} {:>8} {:>7} {:>6} {:>6}",
+            pid,
+            field(&status, "ppid"),
+            field(&status, "state"),
+            field(&status, "threads"),
+            field(&status, "utime"),
+            field(&status, "stime"),
+        );
+    }
+    if let Some(schedstat) = read_file("/proc/schedstat\0") {
+        print!("{}", schedstat);
+    }
+    if let Some(meminfo) = read_file("/proc/meminfo\0") {
+        print!("{}", meminfo);
+    }
+    0
+}
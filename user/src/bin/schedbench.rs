@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    fork, waitpid, exit, set_priority, times, getrusage,
+    Tms, RUsage, RUSAGE_SELF, RUSAGE_CHILDREN, CLOCKS_PER_SEC,
+};
+
+const CHILDREN: usize = 4;
+const ROUNDS: usize = 20000000;
+
+fn spin() -> usize {
+    let mut x: usize = 1;
+    for i in 0..ROUNDS {
+        x = x.wrapping_mul(31).wrapping_add(i);
+    }
+    x
+}
+
+/// Run the same CPU-bound work in children of different priorities and
+/// report when each finishes and how much CPU time it got, to compare the
+/// scheduling policies the kernel can be built with.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut tms = Tms::new();
+    let start = times(&mut tms);
+    let mut pids = [0usize; CHILDREN];
+    for i in 0..CHILDREN {
+        let pid = fork();
+        if pid == 0 {
+            set_priority(((i + 1) * 4) as isize);
+            let x = spin();
+            let mut tms = Tms::new();
+            times(&mut tms);
+            assert!(tms.utime > 0);
+            exit((x & 1) as i32);
+        }
+        pids[i] = pid as usize;
+    }
+
+    let mut exit_code: i32 = 0;
+    let mut usage = RUsage::new();
+    for i in 0..CHILDREN {
+        assert_eq!(waitpid(pids[i], &mut exit_code), pids[i] as isize);
+        let now = times(&mut tms);
+        println!(
+            "child {} done after {} ticks, children cpu time {} ticks",
+            i, now - start, tms.cutime + tms.cstime,
+        );
+    }
+    assert!(tms.cutime > 0);
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut usage), 0);
+    assert!(usage.utime.sec * CLOCKS_PER_SEC + usage.utime.usec / (1000000 / CLOCKS_PER_SEC) >= tms.cutime);
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    println!("schedbench passed!");
+    0
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    write,
+    lseek,
+    pread,
+    pwrite,
+    pipe,
+    unlink,
+    OpenFlags,
+    SEEK_SET,
+    SEEK_CUR,
+    SEEK_END,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("seektest_file\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"hello, world"), 12);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+    assert_eq!(lseek(fd, -5, SEEK_END), 7);
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(lseek(fd, -1, SEEK_SET), -1);
+    assert_eq!(lseek(fd, -100, SEEK_CUR), -1);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+
+    // pread/pwrite leave the file offset untouched
+    assert_eq!(pwrite(fd, b"WORLD", 7), 5);
+    assert_eq!(pread(fd, &mut buf, 0), 12);
+    assert_eq!(&buf[..12], b"hello, WORLD");
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+
+    // seeking past the end and writing extends the file
+    assert_eq!(lseek(fd, 4, SEEK_END), 16);
+    assert_eq!(write(fd, b"!"), 1);
+    assert_eq!(pread(fd, &mut buf, 12), 5);
+    assert_eq!(&buf[..5], b"\0\0\0\0!");
+    close(fd);
+
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+    assert_eq!(pwrite(pipe_fd[1], b"x", 0), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    assert_eq!(unlink("seektest_file\0"), 0);
+    println!("seektest passed!");
+    0
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    kill, getpid, fork, waitpid, exit, yield_,
+    sigaction, sigprocmask, sigreturn, SignalAction,
+    SIG_IGN, SIG_BLOCK, SIG_UNBLOCK, SIGUSR1, SIGSEGV, SIGKILL, SIGSTOP,
+};
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+fn on_usr1(signum: usize) {
+    assert_eq!(signum, SIGUSR1);
+    RECEIVED.fetch_add(1, Ordering::SeqCst);
+    sigreturn();
+}
+
+fn on_segv(signum: usize) {
+    assert_eq!(signum, SIGSEGV);
+    exit(42);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    let action = SignalAction::new(on_usr1 as usize, 0);
+    let mut old_action = SignalAction::new(SIG_IGN, 0);
+    assert_eq!(sigaction(SIGUSR1, Some(&action), Some(&mut old_action)), 0);
+    assert_eq!(old_action.handler, 0);
+    assert_eq!(sigaction(SIGKILL, Some(&action), None), -1);
+
+    // delivered on the way back from kill itself
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 1);
+
+    // held back while blocked
+    let mask = 1u32 << SIGUSR1;
+    assert_eq!(sigprocmask(SIG_BLOCK, Some(mask), None), 0);
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 1);
+    let mut old_mask = 0u32;
+    assert_eq!(sigprocmask(SIG_UNBLOCK, Some(mask), Some(&mut old_mask)), 0);
+    assert_eq!(old_mask, mask);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 2);
+
+    // faults are signals too
+    let child = fork();
+    if child == 0 {
+        let action = SignalAction::new(on_segv as usize, 0);
+        sigaction(SIGSEGV, Some(&action), None);
+        unsafe { (0 as *mut usize).write_volatile(0); }
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 42);
+
+    // the default action of SIGKILL, even on a stopped task
+    let child = fork();
+    if child == 0 {
+        loop { yield_(); }
+    }
+    assert_eq!(kill(child as usize, SIGSTOP), 0);
+    yield_();
+    assert_eq!(kill(child as usize, SIGKILL), 0);
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, -(SIGKILL as i32));
+    println!("sigtest passed!");
+    0
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, pipe, read, write, close, fork, waitpid, exit};
+
+const PAGE_SIZE: usize = 4096;
+
+fn check(start: usize, len: usize) {
+    for addr in (start..start + len).step_by(PAGE_SIZE) {
+        assert_eq!(unsafe { *(addr as *const usize) }, addr);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // more than the physical memory, so that most pages end up swapped out
+    let start: usize = 0x10000000;
+    let len: usize = 12 * 1024 * 1024;
+    assert_eq!(mmap(start, len, 3), len as isize);
+    for addr in (start..start + len).step_by(PAGE_SIZE) {
+        unsafe { *(addr as *mut usize) = addr; }
+    }
+    check(start, len);
+
+    // the kernel has to bring swapped out pages back before using them
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let first = unsafe {
+        core::slice::from_raw_parts_mut(start as *mut u8, 8)
+    };
+    assert_eq!(write(pipe_fd[1], first), 8);
+    check(start + PAGE_SIZE, len - PAGE_SIZE);
+    let last = unsafe {
+        core::slice::from_raw_parts_mut((start + len - PAGE_SIZE + 8) as *mut u8, 8)
+    };
+    assert_eq!(read(pipe_fd[0], last), 8);
+    check(start, len - PAGE_SIZE);
+    assert_eq!(unsafe { *((start + len - PAGE_SIZE + 8) as *const usize) }, start);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    // pages shared copy-on-write can be swapped out as well
+    let pid = fork();
+    if pid == 0 {
+        check(start, len);
+        for addr in (start..start + len).step_by(2 * PAGE_SIZE) {
+            unsafe { *(addr as *mut usize) = 0; }
+        }
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    check(start, len);
+
+    assert_eq!(munmap(start, len), len as isize);
+    println!("swaptest passed!");
+    0
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    thread_create, waittid, exit, yield_,
+    mutex_create, mutex_blocking_create, mutex_lock, mutex_unlock,
+    semaphore_create, semaphore_up, semaphore_down,
+    condvar_create, condvar_signal, condvar_wait,
+};
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 50;
+
+static mut COUNTER: usize = 0;
+static mut READY: bool = false;
+
+fn add_under_lock(mutex_id: usize) -> ! {
+    for _ in 0..ROUNDS {
+        assert_eq!(mutex_lock(mutex_id), 0);
+        // give the others a chance to run inside the critical section
+        let old = unsafe { COUNTER };
+        yield_();
+        unsafe { COUNTER = old + 1; }
+        assert_eq!(mutex_unlock(mutex_id), 0);
+    }
+    exit(0)
+}
+
+fn produce(sem_id: usize) -> ! {
+    for _ in 0..ROUNDS {
+        unsafe { COUNTER += 1; }
+        assert_eq!(semaphore_up(sem_id), 0);
+    }
+    exit(0)
+}
+
+fn notify(ids: usize) -> ! {
+    let (condvar_id, mutex_id) = (ids >> 16, ids & 0xffff);
+    yield_();
+    mutex_lock(mutex_id);
+    unsafe { READY = true; }
+    condvar_signal(condvar_id);
+    mutex_unlock(mutex_id);
+    exit(0)
+}
+
+fn run_threads(entry: usize, arg: usize, count: usize) {
+    let tids: Vec<usize> = (0..count)
+        .map(|_| thread_create(entry, arg) as usize)
+        .collect();
+    let mut exit_code: i32 = 0;
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid, &mut exit_code), *tid as isize);
+        assert_eq!(exit_code, 0);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for mutex_id in [mutex_create(), mutex_blocking_create()].iter() {
+        let mutex_id = *mutex_id as usize;
+        assert_eq!(mutex_unlock(mutex_id), -1);
+        unsafe { COUNTER = 0; }
+        run_threads(add_under_lock as usize, mutex_id, THREADS);
+        assert_eq!(unsafe { COUNTER }, THREADS * ROUNDS);
+    }
+    assert_eq!(mutex_lock(100), -1);
+
+    let sem_id = semaphore_create(0) as usize;
+    unsafe { COUNTER = 0; }
+    let tid = thread_create(produce as usize, sem_id) as usize;
+    for i in 0..ROUNDS {
+        assert_eq!(semaphore_down(sem_id), 0);
+        assert!(unsafe { COUNTER } > i);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+
+    let condvar_id = condvar_create() as usize;
+    let mutex_id = mutex_blocking_create() as usize;
+    assert_eq!(condvar_wait(condvar_id, mutex_id), -1);
+    let tid = thread_create(notify as usize, condvar_id << 16 | mutex_id) as usize;
+    mutex_lock(mutex_id);
+    while !unsafe { READY } {
+        assert_eq!(condvar_wait(condvar_id, mutex_id), 0);
+    }
+    mutex_unlock(mutex_id);
+    assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+    println!("synctest passed!");
+    0
+}
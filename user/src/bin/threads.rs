@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{thread_create, gettid, waittid, exit, yield_, fork};
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 100;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn worker(arg: usize) -> ! {
+    // each thread has a stack of its own
+    let mut local = [arg; 64];
+    for _ in 0..ROUNDS {
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+        yield_();
+    }
+    for x in local.iter_mut() {
+        *x += 1;
+    }
+    assert!(local.iter().all(|x| *x == arg + 1));
+    exit(gettid() as i32 + 100)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(gettid(), 0);
+    let mut tids = Vec::new();
+    for i in 0..THREADS {
+        let tid = thread_create(worker as usize, i);
+        assert!(tid > 0);
+        tids.push(tid as usize);
+    }
+    // fork is only allowed with a single thread
+    assert_eq!(fork(), -1);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waittid(0, &mut exit_code), -1);
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid, &mut exit_code), *tid as isize);
+        assert_eq!(exit_code, *tid as i32 + 100);
+    }
+    assert_eq!(waittid(tids[0], &mut exit_code), -1);
+    assert_eq!(COUNTER.load(Ordering::SeqCst), THREADS * ROUNDS);
+    println!("threads passed!");
+    0
+}
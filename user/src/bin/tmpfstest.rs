@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    open,
+    close,
+    read,
+    write,
+    fstat,
+    link,
+    unlink,
+    mkdir,
+    rmdir,
+    OpenFlags,
+    Stat,
+};
+
+const PAGES: usize = 16;
+
+/// MemFree of /proc/meminfo, in kB.
+fn mem_free() -> usize {
+    let fd = open("/proc/meminfo\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 256];
+    let len = read(fd, &mut buffer) as usize;
+    close(fd);
+    core::str::from_utf8(&buffer[..len]).unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("MemFree: "))
+        .and_then(|rest| rest.trim_end_matches(" kB").parse().ok())
+        .unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let free_before = mem_free();
+
+    let fd = open("/tmp/scratch\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut page = [0u8; 4096];
+    for i in 0..PAGES {
+        for (j, byte) in page.iter_mut().enumerate() {
+            *byte = (i + j) as u8;
+        }
+        assert_eq!(write(fd, &page), page.len() as isize);
+    }
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    close(fd);
+    let free_written = mem_free();
+    assert!(free_written + PAGES * 4 <= free_before);
+
+    assert_eq!(link("/tmp/scratch\0", "/tmp/scratch_link\0"), 0);
+    // hard links stay within one filesystem
+    assert_eq!(link("/tmp/scratch\0", "/scratch_link\0"), -1);
+
+    let fd = open("/tmp/scratch_link\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    for i in 0..PAGES {
+        assert_eq!(read(fd, &mut page), page.len() as isize);
+        assert!(page.iter().enumerate().all(|(j, byte)| *byte == (i + j) as u8));
+    }
+    assert_eq!(read(fd, &mut page), 0);
+    let mut link_stat = Stat::new();
+    assert_eq!(fstat(fd, &mut link_stat), 0);
+    assert_eq!(link_stat.ino, stat.ino);
+    assert_eq!(link_stat.nlink, 2);
+    close(fd);
+
+    assert_eq!(mkdir("/tmp/dir\0"), 0);
+    assert_eq!(mkdir("/tmp/dir/sub\0"), 0);
+    assert_eq!(rmdir("/tmp/dir\0"), -1);
+    assert_eq!(rmdir("/tmp/dir/sub\0"), 0);
+    assert_eq!(rmdir("/tmp/dir\0"), 0);
+
+    assert_eq!(unlink("/tmp/scratch\0"), 0);
+    assert_eq!(unlink("/tmp/scratch_link\0"), 0);
+    assert_eq!(open("/tmp/scratch\0", OpenFlags::RDONLY), -1);
+    // the last link is gone, and the frames with it
+    assert!(mem_free() >= free_written + PAGES * 4);
+    println!("tmpfstest passed!");
+    0
+}
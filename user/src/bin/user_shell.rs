@@ -21,6 +21,7 @@ use user_lib::{
     OpenFlags,
     close,
     dup,
+    chdir,
 };
 use user_lib::console::getchar;
 
@@ -69,6 +70,16 @@ pub fn main() -> i32 {
                         args_copy.drain(idx..=idx + 1);
                     }
                     
+                    if args_copy[0].as_str() == "cd\0" {
+                        let path = if args_copy.len() > 1 { args_copy[1].as_str() } else { "/\0" };
+                        if chdir(path) == -1 {
+                            println!("cd: no such directory {}", path);
+                        }
+                        line.clear();
+                        print!(">> ");
+                        continue;
+                    }
+
                     let mut args_addr: Vec<*const u8> = args_copy
                         .iter()
                         .map(|arg| arg.as_ptr())
@@ -104,8 +115,14 @@ pub fn main() -> i32 {
                         }
 
                         if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
-                            println!("Error when executing!");
-                            return -4;
+                            // Applications live in the root directory, so
+                            // fall back to it when they are not found in cwd
+                            let mut app = String::from("/");
+                            app.push_str(args_copy[0].as_str());
+                            if args_copy[0].contains('/') || exec(app.as_str(), args_addr.as_slice()) == -1 {
+                                println!("Error when executing!");
+                                return -4;
+                            }
                         }
                         unreachable!();
                     } else {
@@ -16,13 +16,42 @@ extern crate bitflags;
 use buddy_system_allocator::LockedHeap;
 use syscall::*;
 use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
 
-const USER_HEAP_SIZE: usize = 32768;
+const HEAP_GROW_SIZE: usize = 32768;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// A buddy allocator that asks the kernel for more memory with `sbrk`
+/// whenever it runs out.
+struct GrowableHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        loop {
+            if let Ok(ptr) = heap.alloc(layout) {
+                return ptr.as_ptr();
+            }
+            // a free block of the rounded-up size is only guaranteed
+            // to fit in a fresh range twice as large
+            let size = HEAP_GROW_SIZE.max(
+                layout.size().max(layout.align()).next_power_of_two() * 2
+            );
+            let start = sbrk(size as isize);
+            if start == -1 {
+                return core::ptr::null_mut();
+            }
+            heap.add_to_heap(start as usize, start as usize + size);
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -41,6 +70,46 @@ impl TimeVal {
     }
 }
 
+/// CPU times in clock ticks, of which there are `CLOCKS_PER_SEC` a
+/// second.
+#[repr(C)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+impl Tms {
+    pub fn new() -> Self {
+        Tms { utime: 0, stime: 0, cutime: 0, cstime: 0 }
+    }
+}
+
+pub const CLOCKS_PER_SEC: usize = 100;
+
+#[repr(C)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+}
+
+impl RUsage {
+    pub fn new() -> Self {
+        RUsage { utime: TimeVal::new(), stime: TimeVal::new() }
+    }
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+#[repr(C)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
 
 #[repr(C)]
 pub struct Stat {
@@ -66,21 +135,73 @@ impl Stat {
 bitflags! {
     pub struct StatMode: u32 {
         const NULL = 0;
+        const CHR = 0o020000;
         const DIR = 0o040000;
         const FILE = 0o100000;
     }
 }
 
 const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+
+/// `handler` is SIG_DFL, SIG_IGN or the address of a `fn(signum: usize)`
+/// that has to end by calling `sigreturn`. The signals in `mask`, bit `n`
+/// for signal `n`, are blocked while it runs.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: u32,
+}
+
+impl SignalAction {
+    pub fn new(handler: usize, mask: u32) -> Self {
+        SignalAction { handler, mask }
+    }
+}
 
 
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start = unsafe {
@@ -118,20 +239,57 @@ bitflags! {
 
 pub fn dup(fd: usize) -> isize { sys_dup(fd) }
 pub fn unlink(path: &str) -> isize { sys_unlinkat(AT_FDCWD as usize, path, 0) }
+pub fn mkdir(path: &str) -> isize { sys_mkdirat(AT_FDCWD as usize, path, 0) }
+pub fn rmdir(path: &str) -> isize { sys_unlinkat(AT_FDCWD as usize, path, AT_REMOVEDIR) }
 
 pub fn link(old_path: &str, new_path: &str) -> isize {
     sys_linkat(AT_FDCWD as usize, old_path, AT_FDCWD as usize, new_path, 0)
 }
 
-pub fn open(path: &str, flags: OpenFlags) -> isize { sys_open(path, flags.bits) }
+/// Mount a new filesystem of type `fs_type`, such as "proc", on the
+/// directory `target`.
+pub fn mount(target: &str, fs_type: &str) -> isize { sys_mount("none\0", target, fs_type, 0, 0) }
+pub fn umount(target: &str) -> isize { sys_umount2(target, 0) }
+
+pub fn open(path: &str, flags: OpenFlags) -> isize { sys_openat(AT_FDCWD as usize, path, flags.bits, 0) }
+
+pub fn openat(dirfd: usize, path: &str, flags: OpenFlags) -> isize {
+    sys_openat(dirfd, path, flags.bits, 0)
+}
+
+pub fn chdir(path: &str) -> isize { sys_chdir(path) }
+pub fn getcwd(buf: &mut [u8]) -> isize { sys_getcwd(buf) }
 pub fn close(fd: usize) -> isize { sys_close(fd) }
 pub fn pipe(pipe_fd: &mut [usize]) -> isize { sys_pipe(pipe_fd) }
 pub fn read(fd: usize, buf: &mut [u8]) -> isize { sys_read(fd, buf) }
 pub fn write(fd: usize, buf: &[u8]) -> isize { sys_write(fd, buf) }
+pub fn getdents64(fd: usize, buf: &mut [u8]) -> isize { sys_getdents64(fd, buf) }
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize { sys_lseek(fd, offset, whence) }
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize { sys_pread(fd, buf, offset) }
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize { sys_pwrite(fd, buf, offset) }
 pub fn fstat(fd: usize, st: &mut Stat) -> isize { sys_fstat(fd, st) }
 pub fn exit(exit_code: i32) -> ! { sys_exit(exit_code) }
 pub fn yield_() -> isize { sys_yield() }
 pub fn set_priority(priority: isize) -> isize { sys_set_priority(priority) }
+pub fn kill(pid: usize, signum: usize) -> isize { sys_kill(pid, signum) }
+
+pub fn sigaction(signum: usize, action: Option<&SignalAction>, old_action: Option<&mut SignalAction>) -> isize {
+    sys_sigaction(
+        signum,
+        action.map_or(core::ptr::null(), |action| action as *const _),
+        old_action.map_or(core::ptr::null_mut(), |action| action as *mut _),
+    )
+}
+
+pub fn sigprocmask(how: usize, set: Option<u32>, old_set: Option<&mut u32>) -> isize {
+    sys_sigprocmask(
+        how,
+        set.as_ref().map_or(core::ptr::null(), |set| set as *const _),
+        old_set.map_or(core::ptr::null_mut(), |set| set as *mut _),
+    )
+}
+
+pub fn sigreturn() -> isize { sys_sigreturn() }
 
 pub fn get_time() -> isize {
     let mut time = TimeVal::new();
@@ -141,37 +299,67 @@ pub fn get_time() -> isize {
     }
 }
 
+/// Returns the clock ticks since boot.
+pub fn times(tms: &mut Tms) -> isize { sys_times(tms) }
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize { sys_getrusage(who, usage) }
+
 pub fn getpid() -> isize { sys_getpid() }
 pub fn munmap(start: usize, len: usize) -> isize { sys_munmap(start, len) }
-pub fn fork() -> isize { sys_fork() }
-pub fn exec(path: &str, args: &[*const u8]) -> isize { sys_exec(path, args) }
-pub fn mmap(start: usize, len: usize, prot: usize) -> isize { sys_mmap(start, len, prot) }
+pub fn brk(addr: usize) -> isize { sys_brk(addr) }
 
-pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => { yield_(); }
-            exit_pid => return exit_pid,
-        }
+/// Move the program break by `increment` bytes and return the old break,
+/// or -1 if the kernel refused to move it.
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = sys_brk(0);
+    if increment == 0 {
+        return old_brk;
     }
-}
-
-pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => { yield_(); }
-            exit_pid => return exit_pid,
-        }
+    let new_brk = (old_brk + increment) as usize;
+    if sys_brk(new_brk) != new_brk as isize {
+        return -1;
     }
+    old_brk
+}
+pub fn fork() -> isize { sys_fork() }
+pub fn exec(path: &str, args: &[*const u8]) -> isize { sys_exec(path, args) }
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap(start, len, prot, MAP_PRIVATE | MAP_ANONYMOUS, 0, 0)
 }
+pub fn mmap_file(start: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot, flags, fd, offset)
+}
+pub fn msync(start: usize, len: usize) -> isize { sys_msync(start, len, 0) }
+
+pub fn wait(exit_code: &mut i32) -> isize { sys_waitpid(-1, exit_code as *mut _) }
+pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize { sys_waitpid(pid as isize, exit_code as *mut _) }
+
+pub fn nanosleep(req: &TimeSpec) -> isize { sys_nanosleep(req, core::ptr::null_mut()) }
 
 pub fn sleep(period_ms: usize) {
-    let start = get_time();
-    while get_time() < start + period_ms as isize {
-        yield_();
-    }
+    let req = TimeSpec {
+        sec: period_ms / 1000,
+        nsec: period_ms % 1000 * 1000000,
+    };
+    nanosleep(&req);
 }
 
 pub fn spawn(path: &str) -> isize { sys_spawn(path) }
 pub fn mailread(buf: &[u8]) -> isize { sys_mailread(buf.as_ptr(), buf.len()) }
 pub fn mailwrite(pid: usize, buf: &[u8]) -> isize { sys_mailwrite(pid, buf.as_ptr(), buf.len()) }
+
+/// Start a thread running `entry(arg)`, which has to end by calling
+/// `exit`. Returns its tid.
+pub fn thread_create(entry: usize, arg: usize) -> isize { sys_thread_create(entry, arg) }
+pub fn gettid() -> isize { sys_gettid() }
+pub fn waittid(tid: usize, exit_code: &mut i32) -> isize { sys_waittid(tid, exit_code as *mut _) }
+
+pub fn mutex_create() -> isize { sys_mutex_create(false) }
+pub fn mutex_blocking_create() -> isize { sys_mutex_create(true) }
+pub fn mutex_lock(mutex_id: usize) -> isize { sys_mutex_lock(mutex_id) }
+pub fn mutex_unlock(mutex_id: usize) -> isize { sys_mutex_unlock(mutex_id) }
+pub fn semaphore_create(res_count: usize) -> isize { sys_semaphore_create(res_count) }
+pub fn semaphore_up(sem_id: usize) -> isize { sys_semaphore_up(sem_id) }
+pub fn semaphore_down(sem_id: usize) -> isize { sys_semaphore_down(sem_id) }
+pub fn condvar_create() -> isize { sys_condvar_create() }
+pub fn condvar_signal(condvar_id: usize) -> isize { sys_condvar_signal(condvar_id) }
+pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize { sys_condvar_wait(condvar_id, mutex_id) }